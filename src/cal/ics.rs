@@ -0,0 +1,1399 @@
+//! Conversion between calendars and iCalendar (RFC 5545) text.
+
+use crate::cal::interval::Interval;
+use crate::cal::recurrence::End;
+use crate::cal::recurrence::Frequency;
+use crate::cal::recurrence::MonthDay;
+use crate::cal::recurrence::Recurrence;
+use crate::cal::AllDay;
+use crate::cal::Cal;
+use crate::cal::Event;
+use crate::cal::EventId;
+use crate::cal::PersistentCal;
+
+use std::io;
+
+use chrono::DateTime;
+use chrono::Datelike;
+use chrono::Duration;
+use chrono::LocalResult;
+use chrono::NaiveDate;
+use chrono::NaiveDateTime;
+use chrono::TimeZone;
+use chrono::Utc;
+use chrono::Weekday;
+use chrono_tz::Tz;
+
+/// Content lines longer than this many octets must be folded.
+const MAX_LINE_LEN: usize = 75;
+
+/// Serializes a whole calendar as a VCALENDAR. Each event's UID is
+/// made from its ID and `uid_domain`, which should be unique to the
+/// calendar. `stamp` is used as every event's DTSTAMP, and should
+/// normally be the current time.
+pub fn write_calendar(cal: &Cal, uid_domain: &str, stamp: DateTime<Utc>) -> String {
+    let mut out = String::new();
+    write_line(&mut out, "BEGIN:VCALENDAR");
+    write_line(&mut out, "VERSION:2.0");
+    write_line(&mut out, "PRODID:-//calendar_bot//EN");
+    for (id, event) in cal.iter() {
+        write_event(&mut out, id, event, uid_domain, stamp);
+    }
+    write_line(&mut out, "END:VCALENDAR");
+    out
+}
+
+/// Serializes a single event as a VEVENT, appending it to `out`.
+pub fn write_event(
+    out: &mut String,
+    id: EventId,
+    event: &Event,
+    uid_domain: &str,
+    stamp: DateTime<Utc>,
+) {
+    write_line(out, "BEGIN:VEVENT");
+    write_line(out, &format!("UID:{}@{}", id.0, uid_domain));
+    write_line(out, &format!("DTSTAMP:{}", format_datetime(stamp)));
+    if let Some(all_day) = event.all_day {
+        write_line(
+            out,
+            &format!("DTSTART;VALUE=DATE:{}", format_date(all_day.start)),
+        );
+        write_line(
+            out,
+            &format!("DTEND;VALUE=DATE:{}", format_date(all_day.end)),
+        );
+    } else {
+        // Rules followed in another zone are followed from the start's
+        // local time there.
+        let zone = event.recurrence.as_ref().map_or(Tz::UTC, |x| x.zone);
+        write_line(
+            out,
+            &format!("DTSTART{}", format_zoned(event.interval.start, &zone)),
+        );
+        write_line(
+            out,
+            &format!("DTEND{}", format_zoned(event.interval.end, &zone)),
+        );
+    }
+    if !event.description.is_empty() {
+        write_line(out, &format!("SUMMARY:{}", escape_text(&event.description)));
+    }
+    if !event.organizer.is_empty() {
+        write_line(
+            out,
+            &format!("ORGANIZER{}", organizer_value(&event.organizer)),
+        );
+    }
+    if let Some(ref recurrence) = event.recurrence {
+        let all_day = event.all_day.is_some();
+        write_line(out, &format!("RRULE:{}", format_rrule(recurrence, all_day)));
+        for exception in &recurrence.exceptions {
+            if all_day {
+                let date = format_date(exception.naive_utc().date());
+                write_line(out, &format!("EXDATE;VALUE=DATE:{}", date));
+            } else {
+                write_line(out, &format!("EXDATE:{}", format_datetime(*exception)));
+            }
+        }
+    }
+    write_line(out, "END:VEVENT");
+}
+
+/// Formats a UTC DATE-TIME value.
+fn format_datetime(datetime: DateTime<Utc>) -> String {
+    datetime.format("%Y%m%dT%H%M%SZ").to_string()
+}
+
+/// Formats the parameters and value of a DATE-TIME property, in UTC
+/// or as a local time in `zone`.
+fn format_zoned(datetime: DateTime<Utc>, zone: &Tz) -> String {
+    if *zone == Tz::UTC {
+        return format!(":{}", format_datetime(datetime));
+    }
+    let local = datetime.with_timezone(zone).naive_local();
+    format!(";TZID={}:{}", zone.name(), local.format("%Y%m%dT%H%M%S"))
+}
+
+/// Formats a DATE value.
+fn format_date(date: NaiveDate) -> String {
+    date.format("%Y%m%d").to_string()
+}
+
+/// Formats an RRULE value. The rules of all-day events work in days
+/// (see `Event::recurrence`), so their UNTIL is a DATE.
+fn format_rrule(recurrence: &Recurrence, all_day: bool) -> String {
+    let mut parts = Vec::new();
+    match recurrence.frequency {
+        Frequency::Daily => parts.push("FREQ=DAILY".to_string()),
+        Frequency::Weekly(ref days) => {
+            parts.push("FREQ=WEEKLY".to_string());
+            if !days.is_empty() {
+                let days: Vec<_> = days.iter().map(|x| format_weekday(*x)).collect();
+                parts.push(format!("BYDAY={}", days.join(",")));
+            }
+        }
+        Frequency::Monthly(MonthDay::Day(day)) => {
+            parts.push("FREQ=MONTHLY".to_string());
+            parts.push(format!("BYMONTHDAY={}", day));
+        }
+        Frequency::Monthly(MonthDay::NthWeekday(n, weekday)) => {
+            parts.push("FREQ=MONTHLY".to_string());
+            parts.push(format!("BYDAY={}{}", n, format_weekday(weekday)));
+        }
+        Frequency::Yearly => parts.push("FREQ=YEARLY".to_string()),
+    }
+    if recurrence.interval > 1 {
+        parts.push(format!("INTERVAL={}", recurrence.interval));
+    }
+    match recurrence.end {
+        End::Never => (),
+        End::Count(n) => parts.push(format!("COUNT={}", n)),
+        End::Until(until) if all_day => {
+            parts.push(format!("UNTIL={}", format_date(until.naive_utc().date())))
+        }
+        End::Until(until) => parts.push(format!("UNTIL={}", format_datetime(until))),
+    }
+    parts.join(";")
+}
+
+fn format_weekday(weekday: Weekday) -> &'static str {
+    match weekday {
+        Weekday::Mon => "MO",
+        Weekday::Tue => "TU",
+        Weekday::Wed => "WE",
+        Weekday::Thu => "TH",
+        Weekday::Fri => "FR",
+        Weekday::Sat => "SA",
+        Weekday::Sun => "SU",
+    }
+}
+
+/// Formats the parameters and value of an ORGANIZER property. Its
+/// value must be a URI, so organizers that aren't one are given as a
+/// common name with a placeholder address.
+fn organizer_value(organizer: &str) -> String {
+    if organizer.contains(':') {
+        format!(":{}", organizer)
+    } else if organizer.contains('@') {
+        format!(":mailto:{}", organizer)
+    } else {
+        format!(";CN=\"{}\":invalid:nomail", organizer.replace('"', "'"))
+    }
+}
+
+/// Escapes a TEXT value.
+fn escape_text(text: &str) -> String {
+    let mut escaped = String::with_capacity(text.len());
+    for c in text.chars() {
+        match c {
+            '\\' => escaped.push_str("\\\\"),
+            ';' => escaped.push_str("\\;"),
+            ',' => escaped.push_str("\\,"),
+            '\n' => escaped.push_str("\\n"),
+            '\r' => (),
+            c => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+/// Appends a content line to `out`, folding it so that no line is
+/// longer than `MAX_LINE_LEN` octets.
+fn write_line(out: &mut String, line: &str) {
+    let mut line_len = 0;
+    for c in line.chars() {
+        if line_len + c.len_utf8() > MAX_LINE_LEN {
+            out.push_str("\r\n ");
+            // The leading space counts towards the folded line.
+            line_len = 1;
+        }
+        out.push(c);
+        line_len += c.len_utf8();
+    }
+    out.push_str("\r\n");
+}
+
+/// The events read from an iCalendar file, and the components that
+/// couldn't be turned into events.
+#[derive(Debug, Default)]
+pub struct Import {
+    pub events: Vec<Event>,
+    pub skipped: Vec<Skipped>,
+}
+
+/// A component left out of an `Import`.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct Skipped {
+    /// The line the component begins on, counting from 1.
+    pub line: usize,
+    /// The component's name, e.g. "VEVENT".
+    pub component: String,
+    pub summary: Option<String>,
+    pub reason: String,
+}
+
+impl std::fmt::Display for Skipped {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(f, "line {}: {}", self.line, self.component)?;
+        if let Some(ref summary) = self.summary {
+            write!(f, " \"{}\"", summary)?;
+        }
+        write!(f, ": {}", self.reason)
+    }
+}
+
+/// Reads the VEVENTs in an iCalendar file. Times without a zone, and
+/// all-day dates, are taken to be in `local`. Anything that can't be
+/// represented by an `Event` is skipped rather than approximated.
+pub fn read_calendar(text: &str, local: &Tz) -> Import {
+    /// A VEVENT whose properties are still being read.
+    struct Pending {
+        line: usize,
+        /// How many components it's nested in.
+        depth: usize,
+        properties: Vec<ContentLine>,
+        /// The first line in it that couldn't be parsed.
+        malformed: Option<usize>,
+    }
+
+    let mut import = Import::default();
+    // The names of the components we're in, innermost last.
+    let mut components: Vec<String> = Vec::new();
+    let mut pending: Option<Pending> = None;
+
+    for (number, line) in unfold(text) {
+        let line = match parse_content_line(&line) {
+            Some(line) => line,
+            None => {
+                if let Some(ref mut event) = pending {
+                    event.malformed = event.malformed.or(Some(number));
+                }
+                continue;
+            }
+        };
+
+        match line.name.as_str() {
+            "BEGIN" => {
+                let name = line.value.to_ascii_uppercase();
+                if pending.is_none() && name == "VEVENT" {
+                    pending = Some(Pending {
+                        line: number,
+                        depth: components.len(),
+                        properties: Vec::new(),
+                        malformed: None,
+                    });
+                } else if pending.is_none()
+                    && components.last().map(String::as_str) == Some("VCALENDAR")
+                    && name != "VTIMEZONE"
+                {
+                    import.skipped.push(Skipped {
+                        line: number,
+                        component: name.clone(),
+                        summary: None,
+                        reason: String::from("only VEVENTs can be imported"),
+                    });
+                }
+                components.push(name);
+            }
+            "END" => {
+                let name = line.value.to_ascii_uppercase();
+                // Be forgiving of components that are never ended.
+                if let Some(ndx) = components.iter().rposition(|x| *x == name) {
+                    components.truncate(ndx);
+                }
+                if pending
+                    .as_ref()
+                    .is_some_and(|x| components.len() <= x.depth)
+                {
+                    let event = pending.take().unwrap();
+                    let result = if name != "VEVENT" {
+                        Err(String::from("it is missing END:VEVENT"))
+                    } else if let Some(malformed) = event.malformed {
+                        Err(format!("line {} is malformed", malformed))
+                    } else {
+                        to_event(&event.properties, local)
+                    };
+                    match result {
+                        Ok(x) => import.events.push(x),
+                        Err(reason) => import.skipped.push(Skipped {
+                            line: event.line,
+                            component: String::from("VEVENT"),
+                            summary: summary(&event.properties),
+                            reason: reason,
+                        }),
+                    }
+                }
+            }
+            _ => {
+                if let Some(ref mut event) = pending {
+                    // Skip the properties of nested components like
+                    // VALARM.
+                    if components.len() == event.depth + 1 {
+                        event.properties.push(line);
+                    }
+                }
+            }
+        }
+    }
+
+    if let Some(event) = pending {
+        import.skipped.push(Skipped {
+            line: event.line,
+            component: String::from("VEVENT"),
+            summary: summary(&event.properties),
+            reason: String::from("it is missing END:VEVENT"),
+        });
+    }
+
+    import
+}
+
+/// Reads an iCalendar file like `read_calendar` and adds its events
+/// to `cal`, returning their IDs along with the skipped components.
+pub fn import(
+    cal: &mut PersistentCal,
+    text: &str,
+    local: &Tz,
+) -> io::Result<(Vec<EventId>, Vec<Skipped>)> {
+    let import = read_calendar(text, local);
+    let mut ids = Vec::with_capacity(import.events.len());
+    for event in import.events {
+        ids.push(cal.add_event(event)?);
+    }
+    Ok((ids, import.skipped))
+}
+
+/// A property or component delimiter, e.g.
+/// `DTSTART;TZID=Europe/Paris:20190304T093000`.
+#[derive(Debug)]
+struct ContentLine {
+    /// The name, in upper case.
+    name: String,
+    /// Parameter names, in upper case, and values without quotes.
+    params: Vec<(String, String)>,
+    value: String,
+}
+
+impl ContentLine {
+    fn param(&self, name: &str) -> Option<&str> {
+        self.params
+            .iter()
+            .find(|x| x.0 == name)
+            .map(|x| x.1.as_str())
+    }
+}
+
+/// Splits `text` into content lines, joining folded lines back
+/// together. Each is paired with the line number it starts on.
+fn unfold(text: &str) -> Vec<(usize, String)> {
+    let mut lines: Vec<(usize, String)> = Vec::new();
+    for (ndx, line) in text.split('\n').enumerate() {
+        let line = line.strip_suffix('\r').unwrap_or(line);
+        if line.starts_with(' ') || line.starts_with('\t') {
+            if let Some(last) = lines.last_mut() {
+                last.1.push_str(&line[1..]);
+                continue;
+            }
+        }
+        if !line.is_empty() {
+            lines.push((ndx + 1, line.to_string()));
+        }
+    }
+    lines
+}
+
+fn parse_content_line(text: &str) -> Option<ContentLine> {
+    let name_end = text.find([';', ':'])?;
+    let mut line = ContentLine {
+        name: text[..name_end].to_ascii_uppercase(),
+        params: Vec::new(),
+        value: String::new(),
+    };
+
+    let mut rest = &text[name_end..];
+    while rest.starts_with(';') {
+        rest = &rest[1..];
+        let equals = rest.find('=')?;
+        let name = rest[..equals].to_ascii_uppercase();
+        rest = &rest[equals + 1..];
+
+        // Quoted values may contain ';' and ':'.
+        let mut in_quotes = false;
+        let mut value_end = None;
+        for (ndx, c) in rest.char_indices() {
+            match c {
+                '"' => in_quotes = !in_quotes,
+                ';' | ':' if !in_quotes => {
+                    value_end = Some(ndx);
+                    break;
+                }
+                _ => (),
+            }
+        }
+        let value_end = value_end?;
+        line.params.push((name, rest[..value_end].replace('"', "")));
+        rest = &rest[value_end..];
+    }
+
+    if !rest.starts_with(':') || line.name.is_empty() {
+        return None;
+    }
+    line.value = rest[1..].to_string();
+    Some(line)
+}
+
+fn summary(properties: &[ContentLine]) -> Option<String> {
+    properties
+        .iter()
+        .find(|x| x.name == "SUMMARY")
+        .map(|x| unescape_text(&x.value))
+}
+
+fn to_event(properties: &[ContentLine], local: &Tz) -> Result<Event, String> {
+    let mut start = None;
+    let mut end = None;
+    let mut duration = None;
+    let mut rrule = None;
+    let mut exdates = Vec::new();
+    let mut event = Event {
+        organizer: String::new(),
+        description: String::new(),
+        interval: Interval {
+            start: Utc.timestamp(0, 0),
+            end: Utc.timestamp(0, 0),
+        },
+        recurrence: None,
+        all_day: None,
+        remind_minutes_before: None,
+    };
+
+    for property in properties {
+        match property.name.as_str() {
+            "DTSTART" => start = Some(parse_time(property, &property.value, local)?),
+            "DTEND" => end = Some(parse_time(property, &property.value, local)?),
+            "DURATION" => {
+                duration = Some(
+                    parse_duration(&property.value)
+                        .ok_or_else(|| format!("DURATION {} is malformed", property.value))?,
+                )
+            }
+            "SUMMARY" => event.description = unescape_text(&property.value),
+            "ORGANIZER" => event.organizer = organizer_name(property),
+            "RRULE" if rrule.is_some() => {
+                return Err(String::from("more than one RRULE isn't supported"))
+            }
+            "RRULE" => rrule = Some(property),
+            "EXDATE" => {
+                for value in property.value.split(',') {
+                    exdates.push(parse_time(property, value, local)?);
+                }
+            }
+            "RDATE" => return Err(String::from("RDATE isn't supported")),
+            "RECURRENCE-ID" => {
+                return Err(String::from(
+                    "changes to single occurrences of recurring events aren't supported",
+                ))
+            }
+            _ => (),
+        }
+    }
+
+    let start = start.ok_or_else(|| String::from("it has no DTSTART"))?;
+    if start.all_day {
+        let last = match (end, duration) {
+            (Some(ref end), _) if end.all_day => end.local_date.pred(),
+            (Some(_), _) => return Err(String::from("DTEND has to be a date like DTSTART")),
+            (None, Some(duration)) if duration.num_seconds() % 86400 == 0 => duration
+                .checked_sub(&Duration::days(1))
+                .and_then(|x| start.local_date.checked_add_signed(x))
+                .ok_or_else(|| String::from("DURATION is too long"))?,
+            (None, Some(_)) => return Err(String::from("DURATION has to be whole days")),
+            (None, None) => start.local_date,
+        };
+        if last < start.local_date {
+            return Err(String::from("it ends before it starts"));
+        }
+        let all_day = AllDay {
+            start: start.local_date,
+            end: last.succ(),
+            zone: *local,
+        };
+        event.interval = all_day.interval();
+        event.all_day = Some(all_day);
+    } else {
+        event.interval.start = start.utc;
+        event.interval.end = match (end, duration) {
+            (Some(end), _) => end.utc,
+            (None, Some(duration)) => start
+                .utc
+                .checked_add_signed(duration)
+                .ok_or_else(|| String::from("DURATION is too long"))?,
+            (None, None) => start.utc,
+        };
+        if event.interval.end < event.interval.start {
+            return Err(String::from("it ends before it starts"));
+        }
+    }
+
+    if let Some(rrule) = rrule {
+        let mut recurrence = parse_rrule(&rrule.value, &start, local)?;
+        recurrence.exceptions.extend(exdates.into_iter().map(|x| {
+            if start.all_day {
+                AllDay::rule_time(x.local_date)
+            } else {
+                x.utc
+            }
+        }));
+        event.recurrence = Some(recurrence);
+    }
+
+    Ok(event)
+}
+
+/// A DATE or DATE-TIME value.
+struct Time {
+    utc: DateTime<Utc>,
+    /// The value's own time zone: UTC, its TZID, or the local zone for
+    /// floating times and DATEs.
+    zone: Tz,
+    /// The date in the value's own time zone.
+    local_date: NaiveDate,
+    /// Whether it's a DATE, which is taken to start at midnight.
+    all_day: bool,
+}
+
+/// Parses `value`, one of the values of `property`, honoring its
+/// VALUE and TZID parameters.
+fn parse_time(property: &ContentLine, value: &str, local: &Tz) -> Result<Time, String> {
+    parse_time_with(
+        value,
+        property.param("VALUE"),
+        property.param("TZID"),
+        local,
+    )
+    .map_err(|x| format!("{}: {}", property.name, x))
+}
+
+fn parse_time_with(
+    value: &str,
+    value_type: Option<&str>,
+    tzid: Option<&str>,
+    local: &Tz,
+) -> Result<Time, String> {
+    let malformed = || format!("{} is not a valid date or time", value);
+
+    if value_type == Some("DATE") || value.len() == 8 {
+        let date = NaiveDate::parse_from_str(value, "%Y%m%d").map_err(|_| malformed())?;
+        return Ok(Time {
+            utc: resolve_local(local, date.and_hms(0, 0, 0)).ok_or_else(malformed)?,
+            zone: *local,
+            local_date: date,
+            all_day: true,
+        });
+    }
+
+    let (naive, utc) = match value.strip_suffix('Z') {
+        Some(naive) => (naive, true),
+        None => (value, false),
+    };
+    let naive = NaiveDateTime::parse_from_str(naive, "%Y%m%dT%H%M%S").map_err(|_| malformed())?;
+
+    let zone = if utc {
+        Tz::UTC
+    } else if let Some(tzid) = tzid {
+        find_zone(tzid).ok_or_else(|| format!("time zone {} is unknown", tzid))?
+    } else {
+        *local
+    };
+
+    Ok(Time {
+        utc: resolve_local(&zone, naive).ok_or_else(malformed)?,
+        zone: zone,
+        local_date: naive.date(),
+        all_day: false,
+    })
+}
+
+/// Finds the zone with the given TZID. Some calendars prefix IANA
+/// names, as in "/mozilla.org/20050126_1/America/New_York", so the
+/// prefix is stripped a component at a time until a name is found.
+fn find_zone(tzid: &str) -> Option<Tz> {
+    let mut name = tzid;
+    loop {
+        if let Ok(zone) = name.parse() {
+            return Some(zone);
+        }
+        name = &name[name.find('/')? + 1..];
+    }
+}
+
+/// Converts a local time to UTC the way RFC 5545 says to: a time that
+/// happens twice is the first one, and a time skipped by a transition
+/// uses the offset from before the transition.
+fn resolve_local<Z: TimeZone>(zone: &Z, naive: NaiveDateTime) -> Option<DateTime<Utc>> {
+    match zone.from_local_datetime(&naive) {
+        LocalResult::Single(x) => Some(x.with_timezone(&Utc)),
+        LocalResult::Ambiguous(earliest, _) => Some(earliest.with_timezone(&Utc)),
+        LocalResult::None => zone
+            .from_local_datetime(&(naive - Duration::hours(1)))
+            .earliest()
+            .map(|x| x.with_timezone(&Utc) + Duration::hours(1)),
+    }
+}
+
+/// Parses a DURATION value, e.g. "PT1H30M" or "P2D".
+fn parse_duration(value: &str) -> Option<Duration> {
+    let (negative, value) = match value.strip_prefix('-') {
+        Some(value) => (true, value),
+        None => (false, value.strip_prefix('+').unwrap_or(value)),
+    };
+    let value = value.strip_prefix('P')?;
+
+    // Counted in milliseconds, which chrono can hold any `i64` of, so
+    // that nothing overflows without being caught.
+    let mut milliseconds: i64 = 0;
+    let mut number = String::new();
+    let mut in_time = false;
+    let mut any = false;
+    for c in value.chars() {
+        let unit = match c {
+            '0'..='9' => {
+                number.push(c);
+                continue;
+            }
+            'T' if !in_time && number.is_empty() => {
+                in_time = true;
+                continue;
+            }
+            'W' if !in_time => 7 * 86_400_000,
+            'D' if !in_time => 86_400_000,
+            'H' if in_time => 3_600_000,
+            'M' if in_time => 60_000,
+            'S' if in_time => 1000,
+            _ => return None,
+        };
+        let n: i64 = number.parse().ok()?;
+        milliseconds = n
+            .checked_mul(unit)
+            .and_then(|x| milliseconds.checked_add(x))?;
+        number.clear();
+        any = true;
+    }
+
+    if !any || !number.is_empty() {
+        return None;
+    }
+    let duration = Duration::milliseconds(milliseconds);
+    Some(if negative { -duration } else { duration })
+}
+
+fn parse_rrule(rrule: &str, start: &Time, local: &Tz) -> Result<Recurrence, String> {
+    let mut frequency = None;
+    let mut recurrence = Recurrence::new(Frequency::Daily);
+    // The rules of all-day events work in days, so they're followed
+    // in UTC. Others are followed in their start's zone.
+    if !start.all_day {
+        recurrence.zone = start.zone;
+    }
+    let mut by_day = None;
+    let mut by_month_day = None;
+
+    for part in rrule.split(';').filter(|x| !x.is_empty()) {
+        let malformed = || format!("RRULE part {} is malformed", part);
+        let equals = part.find('=').ok_or_else(malformed)?;
+        let (name, value) = (part[..equals].to_ascii_uppercase(), &part[equals + 1..]);
+        match name.as_str() {
+            "FREQ" => frequency = Some(value.to_ascii_uppercase()),
+            "INTERVAL" => recurrence.interval = value.parse().map_err(|_| malformed())?,
+            "COUNT" => recurrence.end = End::Count(value.parse().map_err(|_| malformed())?),
+            "UNTIL" => {
+                let until = parse_time_with(value, None, None, local)
+                    .map_err(|x| format!("RRULE UNTIL: {}", x))?;
+                recurrence.end = End::Until(if start.all_day {
+                    AllDay::rule_time(until.local_date)
+                } else {
+                    until.utc
+                });
+            }
+            "BYDAY" => by_day = Some(value.to_ascii_uppercase()),
+            "BYMONTHDAY" => by_month_day = Some(value),
+            // Only matters for weekly rules with several days and an
+            // interval, and Monday is both the default and our week
+            // start.
+            "WKST" if value.eq_ignore_ascii_case("MO") => (),
+            _ => return Err(format!("RRULE part {} isn't supported", part)),
+        }
+    }
+
+    let unsupported = || format!("RRULE {} isn't supported", rrule);
+
+    let frequency = frequency.ok_or_else(|| String::from("RRULE has no FREQ"))?;
+    recurrence.frequency = match (frequency.as_str(), by_day, by_month_day) {
+        ("DAILY", None, None) => Frequency::Daily,
+        ("WEEKLY", None, None) => Frequency::Weekly(Vec::new()),
+        ("WEEKLY", Some(days), None) => Frequency::Weekly(
+            days.split(',')
+                .map(parse_weekday)
+                .collect::<Option<_>>()
+                .ok_or_else(unsupported)?,
+        ),
+        ("MONTHLY", None, None) => Frequency::Monthly(MonthDay::Day(start.local_date.day())),
+        ("MONTHLY", None, Some(day)) => match day.parse() {
+            Ok(day) if (1..=31).contains(&day) => Frequency::Monthly(MonthDay::Day(day)),
+            _ => return Err(unsupported()),
+        },
+        ("MONTHLY", Some(day), None) => {
+            let weekday_ndx = day.len().saturating_sub(2);
+            let (n, weekday) = match (day.get(..weekday_ndx), day.get(weekday_ndx..)) {
+                (Some(n), Some(weekday)) => (n, parse_weekday(weekday).ok_or_else(unsupported)?),
+                _ => return Err(unsupported()),
+            };
+            match n.trim_start_matches('+').parse() {
+                Ok(n) if n != 0 && (-5..=5).contains(&n) => {
+                    Frequency::Monthly(MonthDay::NthWeekday(n, weekday))
+                }
+                _ => return Err(unsupported()),
+            }
+        }
+        ("YEARLY", None, None) => Frequency::Yearly,
+        _ => return Err(unsupported()),
+    };
+
+    Ok(recurrence)
+}
+
+fn parse_weekday(weekday: &str) -> Option<Weekday> {
+    match weekday {
+        "MO" => Some(Weekday::Mon),
+        "TU" => Some(Weekday::Tue),
+        "WE" => Some(Weekday::Wed),
+        "TH" => Some(Weekday::Thu),
+        "FR" => Some(Weekday::Fri),
+        "SA" => Some(Weekday::Sat),
+        "SU" => Some(Weekday::Sun),
+        _ => None,
+    }
+}
+
+/// Gets the organizer's name from an ORGANIZER property, undoing
+/// `organizer_value`.
+fn organizer_name(property: &ContentLine) -> String {
+    if let Some(name) = property.param("CN") {
+        return name.to_string();
+    }
+    let value = &property.value;
+    match value.get(..7) {
+        Some(scheme) if scheme.eq_ignore_ascii_case("mailto:") => value[7..].to_string(),
+        _ => value.clone(),
+    }
+}
+
+/// Undoes `escape_text`.
+fn unescape_text(text: &str) -> String {
+    let mut unescaped = String::with_capacity(text.len());
+    let mut chars = text.chars();
+    while let Some(c) = chars.next() {
+        if c != '\\' {
+            unescaped.push(c);
+            continue;
+        }
+        match chars.next() {
+            Some('n') | Some('N') => unescaped.push('\n'),
+            Some(c) => unescaped.push(c),
+            None => unescaped.push('\\'),
+        }
+    }
+    unescaped
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::cal::interval::Interval;
+
+    use chrono::TimeZone;
+
+    fn event(description: &str) -> Event {
+        Event {
+            organizer: "".to_string(),
+            description: description.to_string(),
+            interval: Interval {
+                start: Utc.ymd(2019, 3, 4).and_hms(9, 30, 0),
+                end: Utc.ymd(2019, 3, 4).and_hms(10, 0, 0),
+            },
+            recurrence: None,
+            all_day: None,
+            remind_minutes_before: None,
+        }
+    }
+
+    #[test]
+    fn write_calendar_test() {
+        let mut cal = Cal::new();
+        cal.add_event(Event {
+            organizer: "ann@example.com".to_string(),
+            ..event("standup")
+        });
+
+        let expected = "BEGIN:VCALENDAR\r\n\
+                        VERSION:2.0\r\n\
+                        PRODID:-//calendar_bot//EN\r\n\
+                        BEGIN:VEVENT\r\n\
+                        UID:0@example.org\r\n\
+                        DTSTAMP:20190101T000000Z\r\n\
+                        DTSTART:20190304T093000Z\r\n\
+                        DTEND:20190304T100000Z\r\n\
+                        SUMMARY:standup\r\n\
+                        ORGANIZER:mailto:ann@example.com\r\n\
+                        END:VEVENT\r\n\
+                        END:VCALENDAR\r\n";
+        assert_eq!(
+            write_calendar(&cal, "example.org", Utc.ymd(2019, 1, 1).and_hms(0, 0, 0)),
+            expected
+        );
+    }
+
+    #[test]
+    fn organizer_without_address() {
+        assert_eq!(organizer_value("ann"), ";CN=\"ann\":invalid:nomail");
+        assert_eq!(organizer_value("https://t.me/ann"), ":https://t.me/ann");
+    }
+
+    #[test]
+    fn text_is_escaped() {
+        assert_eq!(
+            escape_text("lunch; bring a fork, knife\\spoon\nor not"),
+            "lunch\\; bring a fork\\, knife\\\\spoon\\nor not"
+        );
+    }
+
+    #[test]
+    fn long_lines_are_folded() {
+        let mut out = String::new();
+        let line = format!("SUMMARY:{}", "é".repeat(40));
+        write_line(&mut out, &line);
+
+        let lines: Vec<_> = out.split("\r\n").collect();
+        assert_eq!(lines.len(), 3);
+        assert_eq!(lines[2], "");
+        assert!(lines.iter().all(|x| x.len() <= MAX_LINE_LEN));
+        assert_eq!(out.replace("\r\n ", ""), format!("{}\r\n", line));
+    }
+
+    #[test]
+    fn rrule_is_written() {
+        let mut recurrence = Recurrence {
+            interval: 2,
+            end: End::Count(5),
+            ..Recurrence::new(Frequency::Weekly(vec![Weekday::Mon, Weekday::Thu]))
+        };
+        recurrence
+            .exceptions
+            .insert(Utc.ymd(2019, 3, 7).and_hms(9, 30, 0));
+        let mut out = String::new();
+        write_event(
+            &mut out,
+            EventId(3),
+            &Event {
+                recurrence: Some(recurrence),
+                ..event("")
+            },
+            "example.org",
+            Utc.ymd(2019, 1, 1).and_hms(0, 0, 0),
+        );
+
+        assert!(out.contains("\r\nRRULE:FREQ=WEEKLY;BYDAY=MO,TH;INTERVAL=2;COUNT=5\r\n"));
+        assert!(out.contains("\r\nEXDATE:20190307T093000Z\r\n"));
+        assert!(!out.contains("SUMMARY"));
+    }
+
+    #[test]
+    fn monthly_rrules() {
+        let last_friday = Recurrence {
+            end: End::Until(Utc.ymd(2020, 1, 1).and_hms(0, 0, 0)),
+            ..Recurrence::new(Frequency::Monthly(MonthDay::NthWeekday(-1, Weekday::Fri)))
+        };
+        assert_eq!(
+            format_rrule(&last_friday, false),
+            "FREQ=MONTHLY;BYDAY=-1FR;UNTIL=20200101T000000Z"
+        );
+        assert_eq!(
+            format_rrule(
+                &Recurrence::new(Frequency::Monthly(MonthDay::Day(15))),
+                false
+            ),
+            "FREQ=MONTHLY;BYMONTHDAY=15"
+        );
+    }
+    /// Reads `lines` as a file with CRLF line endings.
+    fn read(lines: &[&str]) -> Import {
+        let mut text = lines.join("\r\n");
+        text.push_str("\r\n");
+        read_calendar(&text, &Tz::UTC)
+    }
+
+    #[test]
+    fn read_event() {
+        let import = read(&[
+            "BEGIN:VCALENDAR",
+            "VERSION:2.0",
+            "BEGIN:VEVENT",
+            "UID:1@example.com",
+            "DTSTART;TZID=America/New_York:20190304T093000",
+            "DTEND;TZID=\"/example.com/1/America/New_York\":20190304T100000",
+            "SUMMARY:stand",
+            " up\\, briefly",
+            "ORGANIZER;CN=\"Ann; Bo\":mailto:ann@example.com",
+            "BEGIN:VALARM",
+            "ACTION:DISPLAY",
+            "SUMMARY:not this",
+            "END:VALARM",
+            "END:VEVENT",
+            "END:VCALENDAR",
+        ]);
+
+        assert_eq!(import.skipped, vec![]);
+        assert_eq!(
+            import.events,
+            vec![Event {
+                organizer: "Ann; Bo".to_string(),
+                description: "standup, briefly".to_string(),
+                interval: Interval {
+                    start: Utc.ymd(2019, 3, 4).and_hms(14, 30, 0),
+                    end: Utc.ymd(2019, 3, 4).and_hms(15, 0, 0),
+                },
+                recurrence: None,
+                all_day: None,
+                remind_minutes_before: None,
+            }]
+        );
+    }
+
+    #[test]
+    fn read_what_was_written() {
+        let mut recurrence = Recurrence {
+            end: End::Until(Utc.ymd(2019, 6, 1).and_hms(0, 0, 0)),
+            ..Recurrence::new(Frequency::Monthly(MonthDay::NthWeekday(2, Weekday::Tue)))
+        };
+        recurrence
+            .exceptions
+            .insert(Utc.ymd(2019, 4, 9).and_hms(9, 30, 0));
+        let events = vec![
+            Event {
+                organizer: "ann".to_string(),
+                description: "a long description; with, \\ special\ncharacters ".repeat(3),
+                ..event("")
+            },
+            Event {
+                organizer: "bo@example.com".to_string(),
+                recurrence: Some(recurrence),
+                ..event("planning")
+            },
+        ];
+        let mut cal = Cal::new();
+        for event in &events {
+            cal.add_event(event.clone());
+        }
+
+        let text = write_calendar(&cal, "example.org", Utc::now());
+        let import = read_calendar(&text, &Tz::UTC);
+        assert_eq!(import.skipped, vec![]);
+        assert_eq!(import.events, events);
+    }
+
+    #[test]
+    fn zoned_rules_round_trip() {
+        // 9:00 on Mondays in Los Angeles, across the end of daylight
+        // time on 2019-11-03.
+        let import = read(&[
+            "BEGIN:VCALENDAR",
+            "BEGIN:VEVENT",
+            "DTSTART;TZID=America/Los_Angeles:20191028T090000",
+            "DTEND;TZID=America/Los_Angeles:20191028T093000",
+            "RRULE:FREQ=WEEKLY;BYDAY=MO;COUNT=2",
+            "SUMMARY:standup",
+            "END:VEVENT",
+            "END:VCALENDAR",
+        ]);
+        assert_eq!(import.skipped, vec![]);
+        let event = &import.events[0];
+        let recurrence = event.recurrence.as_ref().unwrap();
+        assert_eq!(
+            recurrence.starts(event.interval.start).collect::<Vec<_>>(),
+            vec![
+                Utc.ymd(2019, 10, 28).and_hms(16, 0, 0),
+                Utc.ymd(2019, 11, 4).and_hms(17, 0, 0),
+            ]
+        );
+
+        let mut cal = Cal::new();
+        cal.add_event(event.clone());
+        let text = write_calendar(&cal, "example.org", Utc::now());
+        assert!(text.contains("\r\nDTSTART;TZID=America/Los_Angeles:20191028T090000\r\n"));
+        assert_eq!(read_calendar(&text, &Tz::UTC).events, import.events);
+    }
+
+    #[test]
+    fn read_all_day_events() {
+        let local: Tz = "America/Los_Angeles".parse().unwrap();
+        let text = "BEGIN:VCALENDAR\n\
+                    BEGIN:VEVENT\n\
+                    DTSTART;VALUE=DATE:20190310\n\
+                    SUMMARY:one day\n\
+                    END:VEVENT\n\
+                    BEGIN:VEVENT\n\
+                    DTSTART;VALUE=DATE:20190309\n\
+                    DURATION:P2D\n\
+                    SUMMARY:two days\n\
+                    END:VEVENT\n\
+                    END:VCALENDAR\n";
+        let import = read_calendar(text, &local);
+
+        assert_eq!(import.skipped, vec![]);
+        assert_eq!(
+            import.events[0].all_day,
+            Some(AllDay {
+                start: NaiveDate::from_ymd(2019, 3, 10),
+                end: NaiveDate::from_ymd(2019, 3, 11),
+                zone: local,
+            })
+        );
+        // Daylight saving time starts on the 10th, so it's 23 hours
+        // long.
+        assert_eq!(
+            import.events[0].interval,
+            Interval {
+                start: Utc.ymd(2019, 3, 10).and_hms(8, 0, 0),
+                end: Utc.ymd(2019, 3, 11).and_hms(7, 0, 0),
+            }
+        );
+        assert_eq!(
+            import.events[1].interval,
+            Interval {
+                start: Utc.ymd(2019, 3, 9).and_hms(8, 0, 0),
+                end: Utc.ymd(2019, 3, 11).and_hms(7, 0, 0),
+            }
+        );
+    }
+
+    #[test]
+    fn all_day_events_round_trip() {
+        // Midnight in Berlin is the day before in UTC, which mustn't
+        // move the days the rule picks.
+        let zone = chrono_tz::Europe::Berlin;
+        let all_day = AllDay {
+            start: NaiveDate::from_ymd(2019, 3, 4),
+            end: NaiveDate::from_ymd(2019, 3, 6),
+            zone: zone,
+        };
+        let mut recurrence = Recurrence {
+            end: End::Until(AllDay::rule_time(NaiveDate::from_ymd(2019, 6, 1))),
+            ..Recurrence::new(Frequency::Weekly(vec![Weekday::Mon]))
+        };
+        recurrence
+            .exceptions
+            .insert(AllDay::rule_time(NaiveDate::from_ymd(2019, 3, 11)));
+        let event = Event {
+            interval: all_day.interval(),
+            recurrence: Some(recurrence),
+            all_day: Some(all_day),
+            ..event("conference")
+        };
+
+        let mut out = String::new();
+        write_event(
+            &mut out,
+            EventId(0),
+            &event,
+            "example.org",
+            Utc.ymd(2019, 1, 1).and_hms(0, 0, 0),
+        );
+        assert!(out.contains("\r\nDTSTART;VALUE=DATE:20190304\r\n"));
+        assert!(out.contains("\r\nDTEND;VALUE=DATE:20190306\r\n"));
+        assert!(out.contains("\r\nRRULE:FREQ=WEEKLY;BYDAY=MO;UNTIL=20190601\r\n"));
+        assert!(out.contains("\r\nEXDATE;VALUE=DATE:20190311\r\n"));
+
+        let text = format!("BEGIN:VCALENDAR\r\n{}END:VCALENDAR\r\n", out);
+        let import = read_calendar(&text, &zone);
+        assert_eq!(import.skipped, vec![]);
+        assert_eq!(import.events, vec![event]);
+    }
+
+    #[test]
+    fn all_day_events_must_end_on_a_day() {
+        let import = read(&[
+            "BEGIN:VCALENDAR",
+            "BEGIN:VEVENT",
+            "DTSTART;VALUE=DATE:20190304",
+            "DTEND:20190304T120000Z",
+            "END:VEVENT",
+            "BEGIN:VEVENT",
+            "DTSTART;VALUE=DATE:20190304",
+            "DURATION:PT12H",
+            "END:VEVENT",
+            "END:VCALENDAR",
+        ]);
+
+        assert_eq!(import.events, vec![]);
+        let skipped: Vec<_> = import.skipped.iter().map(|x| x.reason.as_str()).collect();
+        assert_eq!(
+            skipped,
+            vec![
+                "DTEND has to be a date like DTSTART",
+                "DURATION has to be whole days"
+            ]
+        );
+    }
+
+    #[test]
+    fn read_ambiguous_and_skipped_times() {
+        let import = read(&[
+            "BEGIN:VCALENDAR",
+            "BEGIN:VEVENT",
+            "DTSTART;TZID=America/New_York:20191103T013000",
+            "DURATION:PT30M",
+            "END:VEVENT",
+            "BEGIN:VEVENT",
+            "DTSTART;TZID=America/New_York:20190310T023000",
+            "DURATION:PT30M",
+            "END:VEVENT",
+            "END:VCALENDAR",
+        ]);
+
+        assert_eq!(import.skipped, vec![]);
+        // 1:30 happens twice; the first is in EDT.
+        assert_eq!(
+            import.events[0].interval.start,
+            Utc.ymd(2019, 11, 3).and_hms(5, 30, 0)
+        );
+        // 2:30 never happens, so EST is used.
+        assert_eq!(
+            import.events[1].interval.start,
+            Utc.ymd(2019, 3, 10).and_hms(7, 30, 0)
+        );
+    }
+
+    #[test]
+    fn read_rrules() {
+        let start =
+            |value: &str, tzid: Option<&str>| parse_time_with(value, None, tzid, &Tz::UTC).unwrap();
+        let monday = start("20190304T093000Z", None);
+        let cases = vec![
+            (
+                "FREQ=DAILY;COUNT=3",
+                Recurrence {
+                    end: End::Count(3),
+                    ..Recurrence::new(Frequency::Daily)
+                },
+            ),
+            (
+                "FREQ=WEEKLY;BYDAY=MO,WE;INTERVAL=2;WKST=MO",
+                Recurrence {
+                    interval: 2,
+                    ..Recurrence::new(Frequency::Weekly(vec![Weekday::Mon, Weekday::Wed]))
+                },
+            ),
+            (
+                "FREQ=MONTHLY;BYDAY=-1FR;UNTIL=20191231",
+                Recurrence {
+                    end: End::Until(Utc.ymd(2019, 12, 31).and_hms(0, 0, 0)),
+                    ..Recurrence::new(Frequency::Monthly(MonthDay::NthWeekday(-1, Weekday::Fri)))
+                },
+            ),
+            (
+                "FREQ=MONTHLY;BYMONTHDAY=15",
+                Recurrence::new(Frequency::Monthly(MonthDay::Day(15))),
+            ),
+            (
+                "FREQ=MONTHLY",
+                Recurrence::new(Frequency::Monthly(MonthDay::Day(4))),
+            ),
+            ("FREQ=YEARLY", Recurrence::new(Frequency::Yearly)),
+        ];
+        for (rrule, expected) in cases {
+            assert_eq!(
+                parse_rrule(rrule, &monday, &Tz::UTC),
+                Ok(expected),
+                "{}",
+                rrule
+            );
+        }
+
+        // Rules are followed in their start's zone, even where it's a
+        // different day in UTC.
+        let evening = start("20190304T180000", Some("America/Los_Angeles"));
+        assert_eq!(
+            parse_rrule("FREQ=WEEKLY;BYDAY=MO,SU", &evening, &Tz::UTC),
+            Ok(Recurrence {
+                zone: Tz::America__Los_Angeles,
+                ..Recurrence::new(Frequency::Weekly(vec![Weekday::Mon, Weekday::Sun]))
+            })
+        );
+        assert_eq!(
+            parse_rrule("FREQ=MONTHLY;BYMONTHDAY=4", &evening, &Tz::UTC),
+            Ok(Recurrence {
+                zone: Tz::America__Los_Angeles,
+                ..Recurrence::new(Frequency::Monthly(MonthDay::Day(4)))
+            })
+        );
+
+        for rrule in &[
+            "FREQ=HOURLY",
+            "FREQ=MONTHLY;BYDAY=TU",
+            "FREQ=MONTHLY;BYMONTHDAY=1,15",
+            "FREQ=MONTHLY;BYDAY=MO;BYSETPOS=1",
+            "FREQ=YEARLY;BYMONTH=3",
+            "FREQ=WEEKLY;WKST=SU",
+            "COUNT=3",
+            "FREQ=DAILY;COUNT=many",
+        ] {
+            assert!(parse_rrule(rrule, &monday, &Tz::UTC).is_err(), "{}", rrule);
+        }
+    }
+
+    #[test]
+    fn read_durations() {
+        assert_eq!(parse_duration("PT15M"), Some(Duration::minutes(15)));
+        assert_eq!(
+            parse_duration("P1DT2H30M5S"),
+            Some(Duration::days(1) + Duration::minutes(150) + Duration::seconds(5))
+        );
+        assert_eq!(parse_duration("+P2W"), Some(Duration::weeks(2)));
+        assert_eq!(parse_duration("-PT1H"), Some(Duration::hours(-1)));
+        for value in &["", "P", "PT", "P1H", "PT1D", "P1", "1D", "PT1H2"] {
+            assert_eq!(parse_duration(value), None, "{}", value);
+        }
+        assert_eq!(parse_duration("P99999999999999W"), None);
+        assert_eq!(parse_duration("P1000000000000000DT1000000000000000H"), None);
+    }
+
+    #[test]
+    fn hostile_values_are_skipped() {
+        let import = read(&[
+            "BEGIN:VCALENDAR",
+            "BEGIN:VEVENT",
+            "DTSTART:20190304T120000Z",
+            "DURATION:P2147483647W",
+            "END:VEVENT",
+            "BEGIN:VEVENT",
+            "DTSTART;VALUE=DATE:20190304",
+            "DURATION:P2147483647W",
+            "END:VEVENT",
+            "BEGIN:VEVENT",
+            "DTSTART;VALUE=DATE:20190304",
+            "DURATION:-P15250284452W",
+            "END:VEVENT",
+            "BEGIN:VEVENT",
+            "DTSTART:20190304T120000Z",
+            "RRULE:FREQ=MONTHLY;BYDAY=€",
+            "END:VEVENT",
+            "BEGIN:VEVENT",
+            "DTSTART:20190304T120000Z",
+            "ORGANIZER:aaaaa€x",
+            "END:VEVENT",
+            "END:VCALENDAR",
+        ]);
+
+        let skipped: Vec<_> = import.skipped.iter().map(|x| x.reason.as_str()).collect();
+        assert_eq!(
+            skipped,
+            vec![
+                "DURATION is too long",
+                "DURATION is too long",
+                "DURATION is too long",
+                "RRULE FREQ=MONTHLY;BYDAY=€ isn't supported",
+            ]
+        );
+        let organizers: Vec<_> = import.events.iter().map(|x| x.organizer.as_str()).collect();
+        assert_eq!(organizers, vec!["aaaaa€x"]);
+    }
+
+    #[test]
+    fn skipped_components_are_reported() {
+        let import = read(&[
+            "BEGIN:VCALENDAR",
+            "BEGIN:VTIMEZONE",
+            "TZID:Custom",
+            "END:VTIMEZONE",
+            "BEGIN:VTODO",
+            "SUMMARY:chores",
+            "END:VTODO",
+            "BEGIN:VEVENT",
+            "SUMMARY:no start",
+            "END:VEVENT",
+            "BEGIN:VEVENT",
+            "SUMMARY:hourly",
+            "DTSTART:20190304T093000Z",
+            "RRULE:FREQ=HOURLY",
+            "END:VEVENT",
+            "BEGIN:VEVENT",
+            "DTSTART;TZID=Custom:20190304T093000",
+            "END:VEVENT",
+            "BEGIN:VEVENT",
+            "DTSTART:20190304T093000Z",
+            "this line has no colon",
+            "END:VEVENT",
+            "BEGIN:VEVENT",
+            "SUMMARY:backwards",
+            "DTSTART:20190304T093000Z",
+            "DTEND:20190304T090000Z",
+            "END:VEVENT",
+            "BEGIN:VEVENT",
+            "SUMMARY:fine",
+            "DTSTART:20190304T093000Z",
+            "END:VEVENT",
+            "BEGIN:VEVENT",
+            "SUMMARY:unfinished",
+            "DTSTART:20190304T093000Z",
+        ]);
+
+        assert_eq!(import.events.len(), 1);
+        assert_eq!(import.events[0].description, "fine");
+        assert_eq!(
+            import.events[0].interval.start,
+            import.events[0].interval.end
+        );
+
+        let skipped: Vec<_> = import.skipped.iter().map(|x| x.to_string()).collect();
+        assert_eq!(
+            skipped,
+            vec![
+                "line 5: VTODO: only VEVENTs can be imported",
+                "line 8: VEVENT \"no start\": it has no DTSTART",
+                "line 11: VEVENT \"hourly\": RRULE FREQ=HOURLY isn't supported",
+                "line 16: VEVENT: DTSTART: time zone Custom is unknown",
+                "line 19: VEVENT: line 21 is malformed",
+                "line 23: VEVENT \"backwards\": it ends before it starts",
+                "line 32: VEVENT \"unfinished\": it is missing END:VEVENT",
+            ]
+        );
+    }
+
+    #[test]
+    fn import_into_persistent_cal() {
+        extern crate tempfile;
+
+        let dir = tempfile::tempdir().unwrap();
+        let store_path = dir.path().join("cal");
+        let mut cal = PersistentCal::create(&store_path).unwrap();
+
+        let text = "BEGIN:VCALENDAR\n\
+                    BEGIN:VEVENT\n\
+                    DTSTART:20190304T093000Z\n\
+                    DTEND:20190304T100000Z\n\
+                    SUMMARY:standup\n\
+                    END:VEVENT\n\
+                    BEGIN:VJOURNAL\n\
+                    END:VJOURNAL\n\
+                    END:VCALENDAR\n";
+        let (ids, skipped) = import(&mut cal, text, &Tz::UTC).unwrap();
+        assert_eq!(ids.len(), 1);
+        assert_eq!(skipped.len(), 1);
+        drop(cal);
+
+        let cal = PersistentCal::open_strict(&store_path).unwrap();
+        assert_eq!(cal.get_cal().get(ids[0]), Some(&event("standup")));
+    }
+}
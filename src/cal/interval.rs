@@ -128,11 +128,301 @@ where
     }
 }
 
+/// A set of points kept as disjoint intervals in order. Intervals
+/// that overlap or touch are merged, and empty ones are left out, so
+/// every set has one representation.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct IntervalSet<T> {
+    intervals: Vec<Interval<T>>,
+}
+
+#[allow(dead_code)]
+impl<T> IntervalSet<T>
+where
+    T: Eq,
+    T: Ord,
+    T: Copy,
+    T: std::fmt::Debug,
+{
+    pub fn new() -> IntervalSet<T> {
+        IntervalSet {
+            intervals: Vec::new(),
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.intervals.is_empty()
+    }
+
+    /// Gets the intervals in order.
+    pub fn iter(&self) -> std::slice::Iter<'_, Interval<T>> {
+        self.intervals.iter()
+    }
+
+    /// Adds an interval, merging it with any it overlaps or touches.
+    pub fn insert(&mut self, interval: Interval<T>) {
+        if interval.start >= interval.end {
+            return;
+        }
+        // The intervals from `first` up to `last` overlap or touch the
+        // new one.
+        let first = self.intervals.partition_point(|x| x.end < interval.start);
+        let last = self.intervals.partition_point(|x| x.start <= interval.end);
+        let merged = Interval {
+            start: self
+                .intervals
+                .get(first)
+                .filter(|_| first < last)
+                .map_or(interval.start, |x| min(x.start, interval.start)),
+            end: match last.checked_sub(1) {
+                Some(ndx) if first < last => max(self.intervals[ndx].end, interval.end),
+                _ => interval.end,
+            },
+        };
+        self.intervals.splice(first..last, Some(merged));
+    }
+
+    /// Takes an interval out, splitting any interval it's inside of.
+    pub fn remove(&mut self, interval: Interval<T>) {
+        if interval.start >= interval.end {
+            return;
+        }
+        let first = self.intervals.partition_point(|x| x.end <= interval.start);
+        let last = self.intervals.partition_point(|x| x.start < interval.end);
+        let mut pieces = Vec::with_capacity(2);
+        if first < last {
+            let before = self.intervals[first];
+            let after = self.intervals[last - 1];
+            if before.start < interval.start {
+                pieces.push(Interval {
+                    start: before.start,
+                    end: interval.start,
+                });
+            }
+            if interval.end < after.end {
+                pieces.push(Interval {
+                    start: interval.end,
+                    end: after.end,
+                });
+            }
+        }
+        self.intervals.splice(first..last, pieces);
+    }
+
+    /// Gets the points in either set.
+    pub fn union(&self, other: &IntervalSet<T>) -> IntervalSet<T> {
+        self.iter().chain(other.iter()).cloned().collect()
+    }
+
+    /// Gets the points in both sets.
+    pub fn intersection(&self, other: &IntervalSet<T>) -> IntervalSet<T> {
+        let mut result = Vec::new();
+        let mut a = self.iter().peekable();
+        let mut b = other.iter().peekable();
+        while let (Some(x), Some(y)) = (a.peek(), b.peek()) {
+            if let Some(both) = x.intersection(y) {
+                result.push(both);
+            }
+            // Whichever ends first can't overlap anything after the
+            // other.
+            if x.end < y.end {
+                a.next();
+            } else {
+                b.next();
+            }
+        }
+        IntervalSet { intervals: result }
+    }
+
+    /// Gets the points in this set but not `other`.
+    pub fn difference(&self, other: &IntervalSet<T>) -> IntervalSet<T> {
+        let mut result = self.clone();
+        for interval in other.iter() {
+            result.remove(*interval);
+        }
+        result
+    }
+
+    /// Gets the points within `bound` that aren't in the set.
+    pub fn complement(&self, bound: Interval<T>) -> IntervalSet<T> {
+        let mut result = IntervalSet::new();
+        result.insert(bound);
+        result.difference(self)
+    }
+
+    /// Checks whether a point is in the set.
+    pub fn contains(&self, point: T) -> bool {
+        let ndx = self.intervals.partition_point(|x| x.end <= point);
+        self.intervals.get(ndx).is_some_and(|x| x.start <= point)
+    }
+
+    /// Checks whether all of an interval is in the set.
+    pub fn covers(&self, interval: &Interval<T>) -> bool {
+        if interval.start >= interval.end {
+            return true;
+        }
+        let ndx = self.intervals.partition_point(|x| x.end < interval.end);
+        self.intervals
+            .get(ndx)
+            .is_some_and(|x| x.start <= interval.start)
+    }
+
+    /// Checks whether any of an interval is in the set.
+    pub fn overlaps(&self, interval: &Interval<T>) -> bool {
+        let ndx = self.intervals.partition_point(|x| x.end <= interval.start);
+        interval.start < interval.end
+            && self
+                .intervals
+                .get(ndx)
+                .is_some_and(|x| x.start < interval.end)
+    }
+}
+
+impl<T> Default for IntervalSet<T> {
+    fn default() -> IntervalSet<T> {
+        IntervalSet {
+            intervals: Vec::new(),
+        }
+    }
+}
+
+impl<T> std::iter::FromIterator<Interval<T>> for IntervalSet<T>
+where
+    T: Ord,
+    T: Copy,
+{
+    fn from_iter<I: IntoIterator<Item = Interval<T>>>(iter: I) -> IntervalSet<T> {
+        let mut intervals: Vec<_> = iter.into_iter().filter(|x| x.start < x.end).collect();
+        intervals.sort();
+        let mut merged: Vec<Interval<T>> = Vec::with_capacity(intervals.len());
+        for interval in intervals {
+            match merged.last_mut() {
+                Some(last) if interval.start <= last.end => last.end = max(last.end, interval.end),
+                _ => merged.push(interval),
+            }
+        }
+        IntervalSet { intervals: merged }
+    }
+}
+
+impl<T> IntoIterator for IntervalSet<T> {
+    type Item = Interval<T>;
+    type IntoIter = std::vec::IntoIter<Interval<T>>;
+
+    fn into_iter(self) -> std::vec::IntoIter<Interval<T>> {
+        self.intervals.into_iter()
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
     use chrono::TimeZone;
 
+    fn interval(start: i32, end: i32) -> Interval<i32> {
+        Interval {
+            start: start,
+            end: end,
+        }
+    }
+
+    fn set(intervals: &[(i32, i32)]) -> IntervalSet<i32> {
+        intervals.iter().map(|x| interval(x.0, x.1)).collect()
+    }
+
+    #[test]
+    fn interval_sets_merge() {
+        assert_eq!(
+            set(&[(5, 7), (0, 2), (1, 3), (3, 4), (6, 6), (8, 8), (5, 6)]),
+            IntervalSet {
+                intervals: vec![interval(0, 4), interval(5, 7)],
+            }
+        );
+
+        let mut intervals = set(&[(0, 2), (4, 6), (8, 10)]);
+        intervals.insert(interval(2, 4));
+        intervals.insert(interval(12, 14));
+        intervals.insert(interval(11, 11));
+        assert_eq!(intervals, set(&[(0, 6), (8, 10), (12, 14)]));
+        intervals.insert(interval(-1, 13));
+        assert_eq!(intervals, set(&[(-1, 14)]));
+    }
+
+    #[test]
+    fn interval_sets_remove() {
+        let mut intervals = set(&[(0, 4), (6, 10), (12, 14)]);
+        intervals.remove(interval(2, 7));
+        assert_eq!(intervals, set(&[(0, 2), (7, 10), (12, 14)]));
+        intervals.remove(interval(8, 9));
+        assert_eq!(intervals, set(&[(0, 2), (7, 8), (9, 10), (12, 14)]));
+        intervals.remove(interval(-5, 12));
+        assert_eq!(intervals, set(&[(12, 14)]));
+    }
+
+    #[test]
+    fn interval_set_operations() {
+        let a = set(&[(0, 4), (6, 10)]);
+        let b = set(&[(2, 7), (9, 12)]);
+        assert_eq!(a.union(&b), set(&[(0, 12)]));
+        assert_eq!(a.intersection(&b), set(&[(2, 4), (6, 7), (9, 10)]));
+        assert_eq!(a.difference(&b), set(&[(0, 2), (7, 9)]));
+        assert_eq!(a.complement(interval(-2, 8)), set(&[(-2, 0), (4, 6)]));
+
+        assert!(a.contains(0));
+        assert!(!a.contains(4));
+        assert!(a.covers(&interval(6, 10)));
+        assert!(!a.covers(&interval(3, 7)));
+        assert!(a.overlaps(&interval(3, 7)));
+        assert!(!a.overlaps(&interval(4, 6)));
+    }
+
+    /// Checks the set operations against sets of points in 0..64 kept
+    /// as bits.
+    #[test]
+    fn interval_sets_match_bits() {
+        extern crate quickcheck;
+
+        fn to_set(bits: u64) -> IntervalSet<i32> {
+            (0..64)
+                .filter(|x| bits & (1 << x) != 0)
+                .map(|x| interval(x, x + 1))
+                .collect()
+        }
+
+        fn to_bits(points: &[u8]) -> u64 {
+            points.iter().fold(0, |bits, x| bits | 1 << (x % 64))
+        }
+
+        fn prop(a: Vec<u8>, b: Vec<u8>, x: u8, y: u8) -> bool {
+            let (a, b) = (to_bits(&a), to_bits(&b));
+            let (x, y) = (i32::from(x % 65), i32::from(y % 65));
+            let bound = interval(x.min(y), x.max(y));
+            let bound_bits = (bound.start..bound.end).fold(0, |bits, x| bits | 1 << x);
+            let (sa, sb) = (to_set(a), to_set(b));
+
+            let mut inserted = sa.clone();
+            let mut removed = sa.clone();
+            for x in sb.iter() {
+                inserted.insert(*x);
+                removed.remove(*x);
+            }
+            let points_match = (0..64).all(|x| sa.contains(x) == (a & 1 << x != 0));
+            let covers_match = sa.covers(&bound) == (a & bound_bits == bound_bits);
+            let overlaps_match = sa.overlaps(&bound) == (a & bound_bits != 0);
+
+            sa.union(&sb) == to_set(a | b)
+                && inserted == to_set(a | b)
+                && sa.intersection(&sb) == to_set(a & b)
+                && sa.difference(&sb) == to_set(a & !b)
+                && removed == to_set(a & !b)
+                && sa.complement(bound) == to_set(!a & bound_bits)
+                && points_match
+                && covers_match
+                && overlaps_match
+        }
+        quickcheck::quickcheck(prop as fn(Vec<u8>, Vec<u8>, u8, u8) -> bool);
+    }
+
     #[test]
     fn difference_no_overlap() {
         let interval_a = Interval {
@@ -0,0 +1,501 @@
+//! An interval tree: a balanced search tree of intervals ordered by
+//! start, where each node also knows the latest end in its subtree.
+//! That lets it find every interval overlapping a range without
+//! looking at the ones that can't.
+
+use crate::cal::interval::Interval;
+
+use std::cmp::max;
+use std::cmp::Ordering;
+
+use serde::Deserialize;
+use serde::Serialize;
+
+/// Intervals, each with a value, ordered by interval and then value.
+/// The same interval may be in the tree any number of times with
+/// different values.
+#[derive(Clone, Debug, Deserialize, Serialize)]
+#[serde(from = "Vec<(Interval<T>, V)>", into = "Vec<(Interval<T>, V)>")]
+#[serde(bound(serialize = "T: Ord + Copy + Serialize, V: Ord + Copy + Serialize"))]
+#[serde(bound(deserialize = "T: Ord + Copy + Deserialize<'de>, V: Ord + Copy + Deserialize<'de>"))]
+pub struct IntervalTree<T, V> {
+    root: Link<T, V>,
+    len: usize,
+}
+
+/// A subtree, which may be empty.
+type Link<T, V> = Option<Box<Node<T, V>>>;
+
+#[derive(Clone, Debug)]
+struct Node<T, V> {
+    interval: Interval<T>,
+    value: V,
+    /// The latest end of any interval in this node's subtree.
+    max_end: T,
+    height: u8,
+    left: Link<T, V>,
+    right: Link<T, V>,
+}
+
+#[allow(dead_code)]
+impl<T, V> IntervalTree<T, V>
+where
+    T: Ord,
+    T: Copy,
+    V: Ord,
+    V: Copy,
+{
+    pub fn new() -> IntervalTree<T, V> {
+        IntervalTree { root: None, len: 0 }
+    }
+
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.root.is_none()
+    }
+
+    /// Adds `interval` with `value`. Returns false, leaving the tree
+    /// as it was, if they're already in it.
+    pub fn insert(&mut self, interval: Interval<T>, value: V) -> bool {
+        let mut inserted = false;
+        self.root = Some(Node::insert(
+            self.root.take(),
+            interval,
+            value,
+            &mut inserted,
+        ));
+        if inserted {
+            self.len += 1;
+        }
+        inserted
+    }
+
+    /// Takes `interval` with `value` out of the tree. Returns false if
+    /// they weren't in it.
+    pub fn remove(&mut self, interval: Interval<T>, value: V) -> bool {
+        let mut removed = false;
+        self.root = Node::remove(self.root.take(), interval, value, &mut removed);
+        if removed {
+            self.len -= 1;
+        }
+        removed
+    }
+
+    /// Iterates over all intervals and their values, in order.
+    pub fn iter(&self) -> Iter<'_, T, V> {
+        let mut iter = Iter { stack: Vec::new() };
+        iter.push_left(self.root.as_deref());
+        iter
+    }
+
+    /// Iterates over the intervals that overlap `range` or start within
+    /// it, with their values, in order. The second part only matters
+    /// for empty intervals. Takes `O(log n)` time to find each one.
+    pub fn overlapping(&self, range: Interval<T>) -> Overlapping<'_, T, V> {
+        let mut overlapping = Overlapping {
+            stack: Vec::new(),
+            range: range,
+        };
+        overlapping.push_left(self.root.as_deref());
+        overlapping
+    }
+}
+
+/// Whether `interval` overlaps `range` or starts within it.
+pub fn overlaps<T: Ord>(interval: &Interval<T>, range: &Interval<T>) -> bool {
+    interval.start < range.end && (range.start < interval.end || range.start <= interval.start)
+}
+
+impl<T, V> Node<T, V>
+where
+    T: Ord,
+    T: Copy,
+    V: Ord,
+    V: Copy,
+{
+    fn new(interval: Interval<T>, value: V) -> Box<Node<T, V>> {
+        Box::new(Node {
+            interval: interval,
+            value: value,
+            max_end: interval.end,
+            height: 1,
+            left: None,
+            right: None,
+        })
+    }
+
+    fn cmp_key(&self, interval: &Interval<T>, value: &V) -> Ordering {
+        (&self.interval, &self.value).cmp(&(interval, value))
+    }
+
+    fn insert(
+        node: Link<T, V>,
+        interval: Interval<T>,
+        value: V,
+        inserted: &mut bool,
+    ) -> Box<Node<T, V>> {
+        let mut node = match node {
+            Some(node) => node,
+            None => {
+                *inserted = true;
+                return Node::new(interval, value);
+            }
+        };
+        match node.cmp_key(&interval, &value) {
+            Ordering::Greater => {
+                node.left = Some(Node::insert(node.left.take(), interval, value, inserted))
+            }
+            Ordering::Less => {
+                node.right = Some(Node::insert(node.right.take(), interval, value, inserted))
+            }
+            Ordering::Equal => return node,
+        }
+        Node::rebalance(node)
+    }
+
+    fn remove(node: Link<T, V>, interval: Interval<T>, value: V, removed: &mut bool) -> Link<T, V> {
+        let mut node = node?;
+        match node.cmp_key(&interval, &value) {
+            Ordering::Greater => {
+                node.left = Node::remove(node.left.take(), interval, value, removed)
+            }
+            Ordering::Less => {
+                node.right = Node::remove(node.right.take(), interval, value, removed)
+            }
+            Ordering::Equal => {
+                *removed = true;
+                // Replace the node with the first one after it, if it
+                // has a right subtree to take it from.
+                let left = node.left.take();
+                let right = match node.right.take() {
+                    Some(right) => right,
+                    None => return left,
+                };
+                let (rest, mut first) = Node::remove_first(right);
+                first.left = left;
+                first.right = rest;
+                return Some(Node::rebalance(first));
+            }
+        }
+        Some(Node::rebalance(node))
+    }
+
+    /// Takes the first node out of a subtree, returning what's left of
+    /// the subtree and the node.
+    fn remove_first(mut node: Box<Node<T, V>>) -> (Link<T, V>, Box<Node<T, V>>) {
+        match node.left.take() {
+            Some(left) => {
+                let (rest, first) = Node::remove_first(left);
+                node.left = rest;
+                (Some(Node::rebalance(node)), first)
+            }
+            None => (node.right.take(), node),
+        }
+    }
+
+    fn height(node: &Link<T, V>) -> u8 {
+        node.as_ref().map_or(0, |x| x.height)
+    }
+
+    /// Recomputes `height` and `max_end` from the node's children.
+    fn update(&mut self) {
+        self.height = 1 + max(Node::height(&self.left), Node::height(&self.right));
+        self.max_end = self.interval.end;
+        for child in self.left.iter().chain(self.right.iter()) {
+            self.max_end = max(self.max_end, child.max_end);
+        }
+    }
+
+    fn rotate_left(mut node: Box<Node<T, V>>) -> Box<Node<T, V>> {
+        let mut right = node.right.take().unwrap();
+        node.right = right.left.take();
+        node.update();
+        right.left = Some(node);
+        right.update();
+        right
+    }
+
+    fn rotate_right(mut node: Box<Node<T, V>>) -> Box<Node<T, V>> {
+        let mut left = node.left.take().unwrap();
+        node.left = left.right.take();
+        node.update();
+        left.right = Some(node);
+        left.update();
+        left
+    }
+
+    /// Restores the AVL property at a node whose subtrees are balanced
+    /// and differ in height by at most two.
+    fn rebalance(mut node: Box<Node<T, V>>) -> Box<Node<T, V>> {
+        node.update();
+        let left = i32::from(Node::height(&node.left));
+        let right = i32::from(Node::height(&node.right));
+        if left > right + 1 {
+            let inner = node.left.as_ref().unwrap();
+            if Node::height(&inner.left) < Node::height(&inner.right) {
+                node.left = Some(Node::rotate_left(node.left.take().unwrap()));
+            }
+            Node::rotate_right(node)
+        } else if right > left + 1 {
+            let inner = node.right.as_ref().unwrap();
+            if Node::height(&inner.right) < Node::height(&inner.left) {
+                node.right = Some(Node::rotate_right(node.right.take().unwrap()));
+            }
+            Node::rotate_left(node)
+        } else {
+            node
+        }
+    }
+}
+
+pub struct Iter<'a, T, V> {
+    stack: Vec<&'a Node<T, V>>,
+}
+
+impl<'a, T, V> Iter<'a, T, V> {
+    fn push_left(&mut self, mut node: Option<&'a Node<T, V>>) {
+        while let Some(x) = node {
+            self.stack.push(x);
+            node = x.left.as_deref();
+        }
+    }
+}
+
+impl<'a, T, V> Iterator for Iter<'a, T, V>
+where
+    T: Copy,
+    V: Copy,
+{
+    type Item = (Interval<T>, V);
+
+    fn next(&mut self) -> Option<(Interval<T>, V)> {
+        let node = self.stack.pop()?;
+        self.push_left(node.right.as_deref());
+        Some((node.interval, node.value))
+    }
+}
+
+pub struct Overlapping<'a, T, V> {
+    /// Nodes still to visit, each with its right subtree, in reverse
+    /// order. Subtrees ending before the range are never pushed.
+    stack: Vec<&'a Node<T, V>>,
+    range: Interval<T>,
+}
+
+impl<'a, T, V> Overlapping<'a, T, V>
+where
+    T: Ord,
+{
+    fn push_left(&mut self, mut node: Option<&'a Node<T, V>>) {
+        while let Some(x) = node {
+            if x.max_end < self.range.start {
+                return;
+            }
+            self.stack.push(x);
+            node = x.left.as_deref();
+        }
+    }
+}
+
+impl<'a, T, V> Iterator for Overlapping<'a, T, V>
+where
+    T: Ord,
+    T: Copy,
+    V: Copy,
+{
+    type Item = (Interval<T>, V);
+
+    fn next(&mut self) -> Option<(Interval<T>, V)> {
+        while let Some(node) = self.stack.pop() {
+            if node.interval.start >= self.range.end {
+                // Everything after this starts too late as well.
+                self.stack.clear();
+                return None;
+            }
+            self.push_left(node.right.as_deref());
+            if overlaps(&node.interval, &self.range) {
+                return Some((node.interval, node.value));
+            }
+        }
+        None
+    }
+}
+
+impl<T, V> Default for IntervalTree<T, V> {
+    fn default() -> IntervalTree<T, V> {
+        IntervalTree { root: None, len: 0 }
+    }
+}
+
+/// Trees are equal if they hold the same intervals and values, however
+/// they happen to be balanced.
+impl<T, V> PartialEq for IntervalTree<T, V>
+where
+    T: Ord,
+    T: Copy,
+    V: Ord,
+    V: Copy,
+{
+    fn eq(&self, other: &IntervalTree<T, V>) -> bool {
+        self.len == other.len && self.iter().eq(other.iter())
+    }
+}
+
+impl<T, V> Eq for IntervalTree<T, V>
+where
+    T: Ord,
+    T: Copy,
+    V: Ord,
+    V: Copy,
+{
+}
+
+impl<T, V> std::iter::FromIterator<(Interval<T>, V)> for IntervalTree<T, V>
+where
+    T: Ord,
+    T: Copy,
+    V: Ord,
+    V: Copy,
+{
+    fn from_iter<I: IntoIterator<Item = (Interval<T>, V)>>(iter: I) -> IntervalTree<T, V> {
+        let mut tree = IntervalTree::new();
+        for (interval, value) in iter {
+            tree.insert(interval, value);
+        }
+        tree
+    }
+}
+
+impl<T, V> From<Vec<(Interval<T>, V)>> for IntervalTree<T, V>
+where
+    T: Ord,
+    T: Copy,
+    V: Ord,
+    V: Copy,
+{
+    fn from(entries: Vec<(Interval<T>, V)>) -> IntervalTree<T, V> {
+        entries.into_iter().collect()
+    }
+}
+
+impl<T, V> From<IntervalTree<T, V>> for Vec<(Interval<T>, V)>
+where
+    T: Ord,
+    T: Copy,
+    V: Ord,
+    V: Copy,
+{
+    fn from(tree: IntervalTree<T, V>) -> Vec<(Interval<T>, V)> {
+        tree.iter().collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn interval(start: i32, end: i32) -> Interval<i32> {
+        Interval {
+            start: start,
+            end: end,
+        }
+    }
+
+    /// Checks that every node is balanced and knows its subtree's
+    /// height and latest end, returning the subtree's height.
+    fn check_node(node: &Option<Box<Node<i32, u32>>>) -> u8 {
+        let node = match node {
+            Some(node) => node,
+            None => return 0,
+        };
+        let left = check_node(&node.left);
+        let right = check_node(&node.right);
+        assert!(left <= right + 1 && right <= left + 1);
+        assert_eq!(node.height, 1 + max(left, right));
+        let ends = node.left.iter().chain(node.right.iter()).map(|x| x.max_end);
+        assert_eq!(node.max_end, ends.fold(node.interval.end, max));
+        node.height
+    }
+
+    #[test]
+    fn interval_trees_find_overlaps() {
+        let mut tree: IntervalTree<i32, u32> = IntervalTree::new();
+        assert!(tree.insert(interval(0, 10), 0));
+        assert!(tree.insert(interval(2, 3), 1));
+        assert!(tree.insert(interval(4, 6), 2));
+        assert!(tree.insert(interval(5, 5), 3));
+        assert!(tree.insert(interval(8, 12), 4));
+        assert!(!tree.insert(interval(2, 3), 1));
+        assert_eq!(tree.len(), 5);
+
+        let found: Vec<_> = tree.overlapping(interval(5, 8)).map(|x| x.1).collect();
+        assert_eq!(found, vec![0, 2, 3]);
+        let found: Vec<_> = tree.overlapping(interval(10, 20)).map(|x| x.1).collect();
+        assert_eq!(found, vec![4]);
+        assert_eq!(tree.overlapping(interval(12, 20)).count(), 0);
+
+        assert!(tree.remove(interval(0, 10), 0));
+        assert!(!tree.remove(interval(0, 10), 0));
+        let found: Vec<_> = tree.overlapping(interval(5, 8)).map(|x| x.1).collect();
+        assert_eq!(found, vec![2, 3]);
+        assert_eq!(tree.len(), 4);
+    }
+
+    /// Checks the tree against a sorted list, after inserting and
+    /// removing intervals in any order.
+    #[test]
+    fn interval_trees_match_list() {
+        extern crate quickcheck;
+
+        fn prop(ops: Vec<(bool, u8, u8, u8)>, queries: Vec<(u8, u8)>) -> bool {
+            let mut tree = IntervalTree::new();
+            let mut list = Vec::new();
+            for (insert, start, length, value) in ops {
+                // Few distinct keys, so that removals often hit.
+                let start = i32::from(start % 32);
+                let key = (
+                    interval(start, start + i32::from(length % 8)),
+                    u32::from(value % 4),
+                );
+                let present = list.contains(&key);
+                if insert {
+                    if tree.insert(key.0, key.1) == present {
+                        return false;
+                    }
+                    if !present {
+                        list.push(key);
+                    }
+                } else {
+                    if tree.remove(key.0, key.1) != present {
+                        return false;
+                    }
+                    list.retain(|x| *x != key);
+                }
+                check_node(&tree.root);
+            }
+            list.sort();
+
+            tree.len() == list.len()
+                && tree.iter().eq(list.iter().cloned())
+                && queries.into_iter().all(|(start, length)| {
+                    let range = interval(i32::from(start % 48), i32::from(start % 48 + length % 8));
+                    tree.overlapping(range)
+                        .eq(list.iter().cloned().filter(|x| overlaps(&x.0, &range)))
+                })
+        }
+        quickcheck::quickcheck(prop as fn(Vec<(bool, u8, u8, u8)>, Vec<(u8, u8)>) -> bool);
+    }
+
+    #[test]
+    fn interval_trees_round_trip() {
+        let tree: IntervalTree<i32, u32> =
+            (0..20).map(|x| (interval(x % 7, 10), x as u32)).collect();
+        let json = serde_json::to_string(&tree).unwrap();
+        let read: IntervalTree<i32, u32> = serde_json::from_str(&json).unwrap();
+        assert_eq!(read, tree);
+        check_node(&read.root);
+    }
+}
@@ -0,0 +1,538 @@
+//! Upgrades `PersistentCal` stores written in older layouts. Each
+//! older layout's types are kept here, converted into the current
+//! types, and written back out as a current store.
+
+use super::damaged_store;
+use super::interval::Interval;
+use super::read_records;
+use super::recurrence;
+use super::recurrence::Frequency;
+use super::recurrence::Recurrence;
+use super::write_store;
+use super::AllDay;
+use super::Cal;
+use super::Event;
+use super::Record;
+use super::Tail;
+use super::STORE_HEADER_SIZE;
+
+use std::collections::BTreeSet;
+use std::fs::File;
+use std::io;
+use std::io::BufReader;
+use std::io::Seek;
+use std::io::SeekFrom;
+use std::path::Path;
+
+use chrono::DateTime;
+use chrono::Utc;
+use serde::de::DeserializeOwned;
+use serde::Deserialize;
+
+/// `Event` as stored in version 0 stores. These had no header or
+/// framing: just a sequence of bincode events, each added in turn.
+#[derive(Deserialize)]
+struct EventV0 {
+    organizer: String,
+    description: String,
+    interval: Interval<DateTime<Utc>>,
+}
+
+impl From<EventV0> for Event {
+    fn from(event: EventV0) -> Event {
+        Event {
+            organizer: event.organizer,
+            description: event.description,
+            interval: event.interval,
+            recurrence: None,
+            all_day: None,
+            remind_minutes_before: None,
+        }
+    }
+}
+
+/// `Event` as stored in version 1 stores, from before recurrence rules
+/// had a zone.
+#[derive(Deserialize)]
+struct EventV1 {
+    organizer: String,
+    description: String,
+    interval: Interval<DateTime<Utc>>,
+    recurrence: Option<RecurrenceV1>,
+}
+
+impl From<EventV1> for Event {
+    fn from(event: EventV1) -> Event {
+        Event {
+            organizer: event.organizer,
+            description: event.description,
+            interval: event.interval,
+            recurrence: event.recurrence.map(Recurrence::from),
+            all_day: None,
+            remind_minutes_before: None,
+        }
+    }
+}
+
+/// `Event` as stored in version 2 stores, from before all-day events.
+#[derive(Deserialize)]
+struct EventV2 {
+    organizer: String,
+    description: String,
+    interval: Interval<DateTime<Utc>>,
+    recurrence: Option<Recurrence>,
+}
+
+impl From<EventV2> for Event {
+    fn from(event: EventV2) -> Event {
+        Event {
+            organizer: event.organizer,
+            description: event.description,
+            interval: event.interval,
+            recurrence: event.recurrence,
+            all_day: None,
+            remind_minutes_before: None,
+        }
+    }
+}
+
+/// `Event` as stored in version 3 stores, from before reminders.
+#[derive(Deserialize)]
+struct EventV3 {
+    organizer: String,
+    description: String,
+    interval: Interval<DateTime<Utc>>,
+    recurrence: Option<Recurrence>,
+    all_day: Option<AllDay>,
+}
+
+impl From<EventV3> for Event {
+    fn from(event: EventV3) -> Event {
+        Event {
+            organizer: event.organizer,
+            description: event.description,
+            interval: event.interval,
+            recurrence: event.recurrence,
+            all_day: event.all_day,
+            remind_minutes_before: None,
+        }
+    }
+}
+
+/// `Recurrence` as stored in version 1 stores. These rules were
+/// followed in UTC, so they still are, to keep their occurrences where
+/// they were.
+#[derive(Deserialize)]
+struct RecurrenceV1 {
+    frequency: Frequency,
+    interval: u32,
+    end: recurrence::End,
+    exceptions: BTreeSet<DateTime<Utc>>,
+}
+
+impl From<RecurrenceV1> for Recurrence {
+    fn from(recurrence: RecurrenceV1) -> Recurrence {
+        Recurrence {
+            frequency: recurrence.frequency,
+            zone: chrono_tz::UTC,
+            interval: recurrence.interval,
+            end: recurrence.end,
+            exceptions: recurrence.exceptions,
+        }
+    }
+}
+
+/// Rewrites the store at `store_path`, currently in layout `version`,
+/// in the current layout. Damage is handled as `PersistentCal::open`
+/// handles it, or as `PersistentCal::open_strict` does if `strict`.
+pub(super) fn upgrade(store_path: &Path, version: u32, strict: bool) -> io::Result<()> {
+    let cal = match version {
+        0 => read_v0(store_path)?,
+        1 => read_framed::<EventV1>(store_path, strict)?,
+        2 => read_framed::<EventV2>(store_path, strict)?,
+        3 => read_framed::<EventV3>(store_path, strict)?,
+        _ => {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!(
+                    "don't know how to upgrade calendar store {} from version {}",
+                    store_path.display(),
+                    version
+                ),
+            ))
+        }
+    };
+
+    write_store(store_path, &cal)?;
+    Ok(())
+}
+
+fn read_v0(store_path: &Path) -> io::Result<Cal> {
+    let mut cal = Cal::new();
+    let mut file = BufReader::new(File::open(store_path)?);
+    // Version 0 stores can't tell a torn record from the end of the
+    // store, so read as much as possible like version 0 code did.
+    while let Ok(event) = bincode::deserialize_from::<_, EventV0>(&mut file) {
+        cal.add_event(event.into());
+    }
+    Ok(cal)
+}
+
+/// Reads a store from version 1 on, where only the layout of `Event`
+/// differs from the current one.
+fn read_framed<E>(store_path: &Path, strict: bool) -> io::Result<Cal>
+where
+    E: DeserializeOwned,
+    Event: From<E>,
+{
+    let mut cal = Cal::new();
+    let mut file = BufReader::new(File::open(store_path)?);
+    file.seek(SeekFrom::Start(STORE_HEADER_SIZE))?;
+    // These records are framed like current ones, so damage is judged
+    // just as it is for current stores.
+    let mut records = 0;
+    let (len, tail) = read_records(file, |record: Record<E>| {
+        cal.apply(record.map(Event::from));
+        records += 1;
+    })?;
+    if tail == Tail::Corrupt || (strict && tail == Tail::Torn) {
+        return Err(damaged_store(
+            store_path,
+            tail,
+            STORE_HEADER_SIZE + len,
+            records,
+        ));
+    }
+    Ok(cal)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::cal::PersistentCal;
+
+    use chrono::TimeZone;
+
+    extern crate tempfile;
+
+    /// Gets the bytes of a version 0 store event.
+    fn v0_event(organizer: &str, description: &str, start: &str, end: &str) -> Vec<u8> {
+        let mut bytes = Vec::new();
+        for s in &[organizer, description, start, end] {
+            bytes.extend_from_slice(&(s.len() as u64).to_le_bytes());
+            bytes.extend_from_slice(s.as_bytes());
+        }
+        bytes
+    }
+
+    #[test]
+    fn upgrade_from_v0() {
+        let mut bytes = v0_event(
+            "ann",
+            "lunch",
+            "2019-01-01T12:00:00Z",
+            "2019-01-01T13:00:00Z",
+        );
+        bytes.extend(v0_event(
+            "",
+            "dinner",
+            "2019-01-01T18:00:00Z",
+            "2019-01-01T19:00:00Z",
+        ));
+        let store_path = tempfile::NamedTempFile::new().unwrap().into_temp_path();
+        std::fs::write(&store_path, &bytes).unwrap();
+
+        let expected = [
+            Event {
+                organizer: "ann".to_string(),
+                description: "lunch".to_string(),
+                interval: Interval {
+                    start: Utc.ymd(2019, 1, 1).and_hms(12, 0, 0),
+                    end: Utc.ymd(2019, 1, 1).and_hms(13, 0, 0),
+                },
+                recurrence: None,
+                all_day: None,
+                remind_minutes_before: None,
+            },
+            Event {
+                organizer: "".to_string(),
+                description: "dinner".to_string(),
+                interval: Interval {
+                    start: Utc.ymd(2019, 1, 1).and_hms(18, 0, 0),
+                    end: Utc.ymd(2019, 1, 1).and_hms(19, 0, 0),
+                },
+                recurrence: None,
+                all_day: None,
+                remind_minutes_before: None,
+            },
+        ];
+
+        let cal = PersistentCal::open(&store_path).unwrap();
+        assert_eq!(cal.recovery(), None);
+        assert_eq!(
+            cal.get_cal().iter().map(|x| x.1).collect::<Vec<_>>(),
+            expected.iter().collect::<Vec<_>>()
+        );
+        drop(cal);
+
+        // The store was rewritten in the current layout.
+        let cal = PersistentCal::open_strict(&store_path).unwrap();
+        assert_eq!(
+            cal.get_cal().iter().map(|x| x.1).collect::<Vec<_>>(),
+            expected.iter().collect::<Vec<_>>()
+        );
+        assert_ne!(std::fs::read(&store_path).unwrap(), bytes);
+    }
+
+    /// Gets a version 1 store holding a single event with ID 0.
+    fn v1_store() -> Vec<u8> {
+        let mut bytes = Vec::new();
+        bytes.extend_from_slice(b"calbot\0\0");
+        bytes.extend_from_slice(&[1, 0, 0, 0]); // Version
+        bytes.extend_from_slice(&[93, 0, 0, 0]); // Record length
+        bytes.extend_from_slice(&[156, 229, 144, 152]); // Record checksum
+        bytes.extend_from_slice(&[0, 0, 0, 0]); // Record::Add
+        bytes.extend_from_slice(&[0; 8]); // EventId
+        bytes.extend_from_slice(&[3, 0, 0, 0, 0, 0, 0, 0]);
+        bytes.extend_from_slice(b"ann");
+        bytes.extend_from_slice(&[5, 0, 0, 0, 0, 0, 0, 0]);
+        bytes.extend_from_slice(b"lunch");
+        bytes.extend_from_slice(&[20, 0, 0, 0, 0, 0, 0, 0]);
+        bytes.extend_from_slice(b"2019-01-01T00:00:00Z");
+        bytes.extend_from_slice(&[20, 0, 0, 0, 0, 0, 0, 0]);
+        bytes.extend_from_slice(b"2019-01-01T01:00:00Z");
+        bytes.push(0); // No recurrence
+        bytes
+    }
+
+    #[test]
+    fn upgrade_from_v1() {
+        let store_path = tempfile::NamedTempFile::new().unwrap().into_temp_path();
+        std::fs::write(&store_path, v1_store()).unwrap();
+
+        let expected = Event {
+            organizer: "ann".to_string(),
+            description: "lunch".to_string(),
+            interval: Interval {
+                start: Utc.ymd(2019, 1, 1).and_hms(0, 0, 0),
+                end: Utc.ymd(2019, 1, 1).and_hms(1, 0, 0),
+            },
+            recurrence: None,
+            all_day: None,
+            remind_minutes_before: None,
+        };
+
+        let cal = PersistentCal::open_strict(&store_path).unwrap();
+        assert_eq!(
+            cal.get_cal().iter().map(|x| x.1).collect::<Vec<_>>(),
+            vec![&expected]
+        );
+        drop(cal);
+
+        // The store was rewritten in the current layout.
+        let bytes = std::fs::read(&store_path).unwrap();
+        assert_eq!(bytes[8..12], crate::cal::STORE_VERSION.to_le_bytes());
+    }
+
+    #[test]
+    fn strict_upgrade_rejects_damage() {
+        // The single record is followed by the start of a torn one.
+        let mut bytes = v1_store();
+        bytes.extend_from_slice(&[93, 0, 0]);
+        let store_path = tempfile::NamedTempFile::new().unwrap().into_temp_path();
+        std::fs::write(&store_path, &bytes).unwrap();
+
+        let err = PersistentCal::open_strict(&store_path).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+        assert_eq!(std::fs::read(&store_path).unwrap(), bytes);
+
+        let cal = PersistentCal::open(&store_path).unwrap();
+        assert_eq!(cal.get_cal().len(), 1);
+    }
+
+    #[test]
+    fn upgrade_rejects_corruption() {
+        // The single record is corrupt and followed by a copy of itself.
+        let mut bytes = v1_store();
+        let record = bytes[STORE_HEADER_SIZE as usize..].to_vec();
+        bytes[STORE_HEADER_SIZE as usize + 10] ^= 0xff;
+        bytes.extend_from_slice(&record);
+        let store_path = tempfile::NamedTempFile::new().unwrap().into_temp_path();
+        std::fs::write(&store_path, &bytes).unwrap();
+
+        let err = PersistentCal::open(&store_path).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+        assert_eq!(std::fs::read(&store_path).unwrap(), bytes);
+    }
+
+    #[test]
+    fn upgrade_rules_from_v1() {
+        // A version 1 store holding a single daily event with ID 0.
+        let mut bytes = Vec::new();
+        bytes.extend_from_slice(b"calbot\0\0");
+        bytes.extend_from_slice(&[1, 0, 0, 0]); // Version
+        bytes.extend_from_slice(&[0; 8]); // Record length and checksum
+        bytes.extend_from_slice(&[0, 0, 0, 0]); // Record::Add
+        bytes.extend_from_slice(&[0; 8]); // EventId
+        bytes.extend_from_slice(&[3, 0, 0, 0, 0, 0, 0, 0]);
+        bytes.extend_from_slice(b"ann");
+        bytes.extend_from_slice(&[5, 0, 0, 0, 0, 0, 0, 0]);
+        bytes.extend_from_slice(b"lunch");
+        bytes.extend_from_slice(&[20, 0, 0, 0, 0, 0, 0, 0]);
+        bytes.extend_from_slice(b"2019-01-01T00:00:00Z");
+        bytes.extend_from_slice(&[20, 0, 0, 0, 0, 0, 0, 0]);
+        bytes.extend_from_slice(b"2019-01-01T01:00:00Z");
+        bytes.push(1); // Recurrence
+        bytes.extend_from_slice(&[0, 0, 0, 0]); // Frequency::Daily
+        bytes.extend_from_slice(&[2, 0, 0, 0]); // Interval
+        bytes.extend_from_slice(&[1, 0, 0, 0, 5, 0, 0, 0]); // End::Count(5)
+        bytes.extend_from_slice(&[0; 8]); // No exceptions
+        let payload_len = bytes.len() - 20;
+        let checksum = crc32fast::hash(&bytes[20..]);
+        bytes[12..16].copy_from_slice(&(payload_len as u32).to_le_bytes());
+        bytes[16..20].copy_from_slice(&checksum.to_le_bytes());
+        let store_path = tempfile::NamedTempFile::new().unwrap().into_temp_path();
+        std::fs::write(&store_path, &bytes).unwrap();
+
+        // The rule is followed in UTC, as it was.
+        let expected = Event {
+            organizer: "ann".to_string(),
+            description: "lunch".to_string(),
+            interval: Interval {
+                start: Utc.ymd(2019, 1, 1).and_hms(0, 0, 0),
+                end: Utc.ymd(2019, 1, 1).and_hms(1, 0, 0),
+            },
+            recurrence: Some(Recurrence {
+                interval: 2,
+                end: recurrence::End::Count(5),
+                ..Recurrence::new(Frequency::Daily)
+            }),
+            all_day: None,
+            remind_minutes_before: None,
+        };
+
+        let cal = PersistentCal::open_strict(&store_path).unwrap();
+        assert_eq!(
+            cal.get_cal().iter().map(|x| x.1).collect::<Vec<_>>(),
+            vec![&expected]
+        );
+    }
+
+    #[test]
+    fn upgrade_from_v2() {
+        // A version 2 store holding a single weekly event with ID 0.
+        let mut bytes = Vec::new();
+        bytes.extend_from_slice(b"calbot\0\0");
+        bytes.extend_from_slice(&[2, 0, 0, 0]); // Version
+        bytes.extend_from_slice(&[0; 8]); // Record length and checksum
+        bytes.extend_from_slice(&[0, 0, 0, 0]); // Record::Add
+        bytes.extend_from_slice(&[0; 8]); // EventId
+        bytes.extend_from_slice(&[3, 0, 0, 0, 0, 0, 0, 0]);
+        bytes.extend_from_slice(b"ann");
+        bytes.extend_from_slice(&[5, 0, 0, 0, 0, 0, 0, 0]);
+        bytes.extend_from_slice(b"lunch");
+        bytes.extend_from_slice(&[20, 0, 0, 0, 0, 0, 0, 0]);
+        bytes.extend_from_slice(b"2019-01-01T20:00:00Z");
+        bytes.extend_from_slice(&[20, 0, 0, 0, 0, 0, 0, 0]);
+        bytes.extend_from_slice(b"2019-01-01T21:00:00Z");
+        bytes.push(1); // Recurrence
+        bytes.extend_from_slice(&[1, 0, 0, 0]); // Frequency::Weekly
+        bytes.extend_from_slice(&[0; 8]); // No days
+        bytes.extend_from_slice(&[19, 0, 0, 0, 0, 0, 0, 0]);
+        bytes.extend_from_slice(b"America/Los_Angeles");
+        bytes.extend_from_slice(&[1, 0, 0, 0]); // Interval
+        bytes.extend_from_slice(&[0, 0, 0, 0]); // End::Never
+        bytes.extend_from_slice(&[0; 8]); // No exceptions
+        let payload_len = bytes.len() - 20;
+        let checksum = crc32fast::hash(&bytes[20..]);
+        bytes[12..16].copy_from_slice(&(payload_len as u32).to_le_bytes());
+        bytes[16..20].copy_from_slice(&checksum.to_le_bytes());
+        let store_path = tempfile::NamedTempFile::new().unwrap().into_temp_path();
+        std::fs::write(&store_path, &bytes).unwrap();
+
+        let expected = Event {
+            organizer: "ann".to_string(),
+            description: "lunch".to_string(),
+            interval: Interval {
+                start: Utc.ymd(2019, 1, 1).and_hms(20, 0, 0),
+                end: Utc.ymd(2019, 1, 1).and_hms(21, 0, 0),
+            },
+            recurrence: Some(Recurrence {
+                zone: chrono_tz::America::Los_Angeles,
+                ..Recurrence::new(Frequency::Weekly(vec![]))
+            }),
+            all_day: None,
+            remind_minutes_before: None,
+        };
+
+        let cal = PersistentCal::open_strict(&store_path).unwrap();
+        assert_eq!(
+            cal.get_cal().iter().map(|x| x.1).collect::<Vec<_>>(),
+            vec![&expected]
+        );
+        drop(cal);
+
+        // The store was rewritten in the current layout.
+        let bytes = std::fs::read(&store_path).unwrap();
+        assert_eq!(bytes[8..12], crate::cal::STORE_VERSION.to_le_bytes());
+    }
+
+    #[test]
+    fn upgrade_from_v3() {
+        // A version 3 store holding a single all-day event with ID 0.
+        let mut bytes = Vec::new();
+        bytes.extend_from_slice(b"calbot\0\0");
+        bytes.extend_from_slice(&[3, 0, 0, 0]); // Version
+        bytes.extend_from_slice(&[0; 8]); // Record length and checksum
+        bytes.extend_from_slice(&[0, 0, 0, 0]); // Record::Add
+        bytes.extend_from_slice(&[0; 8]); // EventId
+        bytes.extend_from_slice(&[3, 0, 0, 0, 0, 0, 0, 0]);
+        bytes.extend_from_slice(b"ann");
+        bytes.extend_from_slice(&[5, 0, 0, 0, 0, 0, 0, 0]);
+        bytes.extend_from_slice(b"lunch");
+        bytes.extend_from_slice(&[20, 0, 0, 0, 0, 0, 0, 0]);
+        bytes.extend_from_slice(b"2019-01-01T00:00:00Z");
+        bytes.extend_from_slice(&[20, 0, 0, 0, 0, 0, 0, 0]);
+        bytes.extend_from_slice(b"2019-01-02T00:00:00Z");
+        bytes.push(0); // No recurrence
+        bytes.push(1); // All day
+        bytes.extend_from_slice(&[10, 0, 0, 0, 0, 0, 0, 0]);
+        bytes.extend_from_slice(b"2019-01-01");
+        bytes.extend_from_slice(&[10, 0, 0, 0, 0, 0, 0, 0]);
+        bytes.extend_from_slice(b"2019-01-02");
+        bytes.extend_from_slice(&[3, 0, 0, 0, 0, 0, 0, 0]);
+        bytes.extend_from_slice(b"UTC");
+        let payload_len = bytes.len() - 20;
+        let checksum = crc32fast::hash(&bytes[20..]);
+        bytes[12..16].copy_from_slice(&(payload_len as u32).to_le_bytes());
+        bytes[16..20].copy_from_slice(&checksum.to_le_bytes());
+        let store_path = tempfile::NamedTempFile::new().unwrap().into_temp_path();
+        std::fs::write(&store_path, &bytes).unwrap();
+
+        let all_day = AllDay {
+            start: chrono::NaiveDate::from_ymd(2019, 1, 1),
+            end: chrono::NaiveDate::from_ymd(2019, 1, 2),
+            zone: chrono_tz::UTC,
+        };
+        let expected = Event {
+            organizer: "ann".to_string(),
+            description: "lunch".to_string(),
+            interval: all_day.interval(),
+            recurrence: None,
+            all_day: Some(all_day),
+            remind_minutes_before: None,
+        };
+
+        let cal = PersistentCal::open_strict(&store_path).unwrap();
+        assert_eq!(
+            cal.get_cal().iter().map(|x| x.1).collect::<Vec<_>>(),
+            vec![&expected]
+        );
+        drop(cal);
+
+        // The store was rewritten in the current layout.
+        let bytes = std::fs::read(&store_path).unwrap();
+        assert_eq!(bytes[8..12], crate::cal::STORE_VERSION.to_le_bytes());
+    }
+}
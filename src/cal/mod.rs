@@ -1,25 +1,107 @@
+pub mod ics;
 pub mod interval;
+pub mod interval_tree;
+mod migrate;
+pub mod recurrence;
 
 use crate::cal::interval::Interval;
+use crate::cal::interval::IntervalSet;
+use crate::cal::interval_tree::IntervalTree;
+use crate::cal::recurrence::Recurrence;
+use crate::local_time;
 
+use std::cmp::max;
 use std::cmp::Ordering;
+use std::collections::BTreeMap;
 use std::collections::BTreeSet;
+use std::ffi::OsString;
+use std::fmt;
+use std::fs;
 use std::fs::File;
 use std::fs::OpenOptions;
 use std::io;
+use std::io::BufReader;
+use std::io::BufWriter;
+use std::io::Read;
+use std::io::Write;
+use std::iter;
 use std::iter::Iterator;
+use std::num::ParseIntError;
 use std::ops::Range;
 use std::path::Path;
+use std::path::PathBuf;
+use std::str::FromStr;
 
 use chrono::DateTime;
 use chrono::Duration;
+use chrono::NaiveDate;
+use chrono::TimeZone;
 use chrono::Utc;
+use chrono_tz::Tz;
+use serde::de::DeserializeOwned;
 use serde::Deserialize;
 use serde::Serialize;
 
+/// Identifies an event within a `Cal`. IDs are handed out by
+/// `Cal::add_event` and are never reused, even after the event is
+/// removed.
+#[derive(Clone, Copy, Debug, Deserialize, Eq, Hash, Ord, PartialEq, PartialOrd, Serialize)]
+pub struct EventId(u64);
+
+impl EventId {
+    fn next(self) -> EventId {
+        EventId(self.0 + 1)
+    }
+}
+
+/// Writes the ID as a number, which `from_str` reads back.
+impl fmt::Display for EventId {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.0)
+    }
+}
+
+impl FromStr for EventId {
+    type Err = ParseIntError;
+
+    fn from_str(s: &str) -> Result<EventId, ParseIntError> {
+        s.parse().map(EventId)
+    }
+}
+
 #[derive(Clone, Debug, Deserialize, Eq, PartialEq, Serialize)]
 pub struct Cal {
     events: BTreeSet<CmpEvent>,
+    /// Maps each event ID to the interval it is keyed by in
+    /// `events`. Together they form the event's `CmpEvent` key.
+    intervals: BTreeMap<EventId, Interval<DateTime<Utc>>>,
+    /// IDs of events with a recurrence rule. Their occurrences are
+    /// expanded at query time, so they can't be found by interval in
+    /// `events`.
+    recurring: BTreeSet<EventId>,
+    /// IDs of events without a recurrence rule, keyed by interval, so
+    /// that ones overlapping a range can be found without a scan.
+    single: IntervalTree<DateTime<Utc>, EventId>,
+    next_id: EventId,
+}
+
+/// A single occurrence of an event. Events without a recurrence rule
+/// have exactly one, spanning the event's own interval.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub struct Occurrence<'a> {
+    pub id: EventId,
+    pub event: &'a Event,
+    pub interval: Interval<DateTime<Utc>>,
+}
+
+impl<'a> Occurrence<'a> {
+    /// Gets the days this occurrence covers, if it's an occurrence of
+    /// an all-day event.
+    pub fn days(&self) -> Option<AllDay> {
+        let all_day = self.event.all_day?;
+        let start = self.interval.start.with_timezone(&all_day.zone);
+        Some(all_day.starting_on(start.naive_local().date()))
+    }
 }
 
 #[allow(dead_code)]
@@ -27,15 +109,113 @@ impl Cal {
     pub fn new() -> Cal {
         Cal {
             events: BTreeSet::new(),
+            intervals: BTreeMap::new(),
+            recurring: BTreeSet::new(),
+            single: IntervalTree::new(),
+            next_id: EventId(0),
         }
     }
 
-    /// Gets all events within a range
+    /// Gets the number of events, counting each recurring event once.
+    pub fn len(&self) -> usize {
+        self.intervals.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.intervals.is_empty()
+    }
+
+    /// Iterates over all events and their IDs, ordered by interval.
+    pub fn iter(&self) -> impl Iterator<Item = (EventId, &Event)> {
+        self.events.iter().map(|x| (x.id, &x.event))
+    }
+
+    /// Gets the event with the given ID, if it exists.
+    pub fn get(&self, id: EventId) -> Option<&Event> {
+        let interval = self.intervals.get(&id)?;
+        self.events
+            .get(&CmpEvent::key(id, *interval))
+            .map(|x| &x.event)
+    }
+
+    /// Gets all event occurrences starting within a range, ordered by
+    /// interval. Recurring events are expanded lazily. All-day events
+    /// are included for every day they cover, so ones that started
+    /// before the range are included too if they're still going.
     pub fn events_in<'a>(
         &'a self,
         range: Range<DateTime<Utc>>,
-    ) -> impl Iterator<Item = &Event> + 'a {
-        self.events_in_cmp(range).map(|x| &x.event)
+    ) -> impl Iterator<Item = Occurrence<'a>> + 'a {
+        let single = self
+            .events_in_cmp(range.clone())
+            .filter(|x| x.event.recurrence.is_none() && x.event.all_day.is_none())
+            .map(|x| Occurrence {
+                id: x.id,
+                event: &x.event,
+                interval: x.event.interval,
+            });
+        let all_day = self
+            .single_overlapping(&range)
+            .filter(|x| x.event.all_day.is_some());
+        let recurring = self
+            .recurring
+            .iter()
+            .map(move |id| self.occurrences_in(*id, range.clone()));
+
+        itertools::kmerge_by(
+            iter::once(Box::new(single) as Box<dyn Iterator<Item = Occurrence<'a>> + 'a>)
+                .chain(iter::once(Box::new(all_day) as Box<_>))
+                .chain(recurring),
+            |a: &Occurrence, b: &Occurrence| (a.interval, a.id) < (b.interval, b.id),
+        )
+    }
+
+    /// Gets the occurrences of a recurring event starting within a
+    /// range.
+    fn occurrences_in<'a>(
+        &'a self,
+        id: EventId,
+        range: Range<DateTime<Utc>>,
+    ) -> Box<dyn Iterator<Item = Occurrence<'a>> + 'a> {
+        let event = self.get(id).unwrap();
+        let recurrence = event.recurrence.as_ref().unwrap();
+
+        if let Some(all_day) = event.all_day {
+            // The rule works in days, so expand it over every day that
+            // could start within the range in any zone, and then find
+            // when each of those days starts in the event's zone.
+            let first = AllDay::rule_time(all_day.start);
+            let end = range.end + Duration::days(1);
+            return Box::new(
+                recurrence
+                    .starts_from(first, range.start - Duration::days(1))
+                    .take_while(move |x| *x < end)
+                    .map(move |x| Occurrence {
+                        id: id,
+                        event: event,
+                        interval: all_day.starting_on(x.naive_utc().date()).interval(),
+                    })
+                    .filter(move |x| range.contains(&x.interval.start)),
+            );
+        }
+
+        let duration = event
+            .interval
+            .end
+            .signed_duration_since(event.interval.start);
+        Box::new(
+            recurrence
+                .starts_from(event.interval.start, range.start)
+                .take_while(move |x| *x < range.end)
+                .map(move |x| Occurrence {
+                    id: id,
+                    event: event,
+                    interval: Interval {
+                        start: x,
+                        end: x + duration,
+                    },
+                }),
+        )
     }
 
     fn events_in_cmp(&self, range: Range<DateTime<Utc>>) -> impl Iterator<Item = &CmpEvent> {
@@ -46,115 +226,396 @@ impl Cal {
         self.events.range(event_range)
     }
 
-    fn free_time_in<'a>(
+    /// Gets all event occurrences overlapping a range, ordered by
+    /// interval, including ones that started before it. Occurrences
+    /// that take no time are included if they're at a time within the
+    /// range. Events without a recurrence rule are found in `O(log n)`
+    /// time each; recurring events are expanded lazily.
+    pub fn events_overlapping<'a>(
         &'a self,
         range: Range<DateTime<Utc>>,
-    ) -> impl Iterator<Item = Interval<DateTime<Utc>>> + 'a {
-        struct FreeTime<T> {
-            busy_times: T,
-            interval: Interval<DateTime<Utc>>,
-        }
+    ) -> impl Iterator<Item = Occurrence<'a>> + 'a {
+        let range_interval = Interval {
+            start: range.start,
+            end: range.end,
+        };
+        let single = self.single_overlapping(&range);
+        let recurring = self.recurring.iter().map(move |id| {
+            // Occurrences that start up to one event length before the
+            // range still overlap it. All-day occurrences can be an hour
+            // longer than the first, across a daylight saving change.
+            let event = self.get(*id).unwrap();
+            let mut length = event
+                .interval
+                .end
+                .signed_duration_since(event.interval.start);
+            if event.all_day.is_some() {
+                length = length + Duration::hours(1);
+            }
+            Box::new(
+                self.occurrences_in(*id, range.start - length..range.end)
+                    .filter(move |x| interval_tree::overlaps(&x.interval, &range_interval)),
+            ) as Box<dyn Iterator<Item = Occurrence<'a>> + 'a>
+        });
 
-        impl<T> Iterator for FreeTime<T>
-        where
-            T: Iterator<Item = Interval<DateTime<Utc>>>,
-        {
-            type Item = Interval<DateTime<Utc>>;
+        itertools::kmerge_by(
+            iter::once(Box::new(single) as Box<dyn Iterator<Item = Occurrence<'a>> + 'a>)
+                .chain(recurring),
+            |a: &Occurrence, b: &Occurrence| (a.interval, a.id) < (b.interval, b.id),
+        )
+    }
 
-            fn next(&mut self) -> Option<Interval<DateTime<Utc>>> {
-                if self.interval.start >= self.interval.end {
-                    return None;
-                }
+    /// Gets the occurrences of events without a recurrence rule that
+    /// overlap a range, as `events_overlapping` does.
+    fn single_overlapping<'a>(
+        &'a self,
+        range: &Range<DateTime<Utc>>,
+    ) -> impl Iterator<Item = Occurrence<'a>> + 'a {
+        let range = Interval {
+            start: range.start,
+            end: range.end,
+        };
+        self.single
+            .overlapping(range)
+            .map(move |(interval, id)| Occurrence {
+                id: id,
+                event: self.get(id).unwrap(),
+                interval: interval,
+            })
+    }
 
-                let busy_time = match self.busy_times.next() {
-                    Some(x) => x,
-                    None => {
-                        let ret = self.interval;
-                        self.interval.start = self.interval.end;
-                        return Some(ret);
-                    }
+    /// Gets the occurrences that take up some of the same time as
+    /// `event`, which needn't be in the calendar, ordered by interval.
+    /// All-day events don't take up time, so never conflict. Only the
+    /// first occurrence of a recurring `event` is checked.
+    pub fn conflicts<'a>(&'a self, event: &Event) -> Vec<Occurrence<'a>> {
+        if event.all_day.is_some() {
+            return Vec::new();
+        }
+        self.events_overlapping(event.interval.start..event.interval.end)
+            .filter(|x| x.event.all_day.is_none())
+            .filter(|x| {
+                let occurrence = Event {
+                    interval: x.interval,
+                    ..Event::dummy()
                 };
+                event.overlap(&occurrence).is_some()
+            })
+            .collect()
+    }
 
-                let ret = Some(Interval {
-                    start: self.interval.start,
-                    end: busy_time.start,
-                });
-                self.interval.start = busy_time.end;
+    /// Gets the times within a range that events take up, merged so
+    /// that they don't overlap, in order and cut to the range.
+    /// All-day events, like birthdays or holidays, don't take up time.
+    pub fn busy_times_in(&self, range: Range<DateTime<Utc>>) -> IntervalSet<DateTime<Utc>> {
+        let range_interval = Interval {
+            start: range.start,
+            end: range.end,
+        };
+        self.events_overlapping(range)
+            .filter(|x| x.event.all_day.is_none())
+            .filter_map(|x| x.interval.intersection(&range_interval))
+            .collect()
+    }
 
-                ret
-            }
+    /// Gets the times within a range that no event takes up.
+    /// All-day events, like birthdays or holidays, don't take up time.
+    pub fn free_time_in(&self, range: Range<DateTime<Utc>>) -> IntervalSet<DateTime<Utc>> {
+        let bound = Interval {
+            start: range.start,
+            end: range.end,
+        };
+        self.busy_times_in(range).complement(bound)
+    }
+
+    /// Adds an event, returning its newly assigned ID. Any number of
+    /// events may share the same interval.
+    pub fn add_event(&mut self, event: Event) -> EventId {
+        let id = self.next_id;
+        self.put_event(id, event);
+        id
+    }
+
+    /// Adds or replaces the event with a specific ID, e.g. when
+    /// replaying a store. Later IDs handed out by `add_event` will be
+    /// greater than `id`.
+    fn put_event(&mut self, id: EventId, event: Event) {
+        self.remove_event(id);
+        self.index(id, &event);
+        self.intervals.insert(id, event.interval);
+        self.events.insert(CmpEvent::new(id, event));
+        self.reserve_ids(id.next());
+    }
+
+    /// Files the event with the given ID under `recurring` if it has a
+    /// recurrence rule, and under `single` otherwise.
+    fn index(&mut self, id: EventId, event: &Event) {
+        if event.recurrence.is_some() {
+            self.recurring.insert(id);
+        } else {
+            self.single.insert(event.interval, id);
         }
+    }
 
-        FreeTime {
-            interval: Interval {
-                start: range.start,
-                end: range.end,
-            },
-            busy_times: self.events_in(range).map(|x| x.interval),
+    /// Takes the event with the given ID, keyed by `interval`, out of
+    /// `recurring` and `single`.
+    fn unindex(&mut self, id: EventId, interval: Interval<DateTime<Utc>>) {
+        self.recurring.remove(&id);
+        self.single.remove(interval, id);
+    }
+
+    /// Replays a record read from a store.
+    fn apply(&mut self, record: Record<Event>) {
+        match record {
+            Record::Add(id, event) | Record::Update(id, event) => self.put_event(id, event),
+            Record::Remove(id) => {
+                self.remove_event(id);
+                self.reserve_ids(id.next());
+            }
+            Record::NextId(id) => self.reserve_ids(id),
         }
     }
 
-    pub fn add_event(&mut self, event: Event) -> bool {
-        self.events.insert(CmpEvent::from_event(event))
+    /// Makes sure `add_event` never hands out an ID less than `next_id`.
+    fn reserve_ids(&mut self, next_id: EventId) {
+        self.next_id = max(self.next_id, next_id);
+    }
+
+    /// Removes the event with the given ID, returning it.
+    pub fn remove_event(&mut self, id: EventId) -> Option<Event> {
+        let interval = self.intervals.remove(&id)?;
+        self.unindex(id, interval);
+        self.events
+            .take(&CmpEvent::key(id, interval))
+            .map(|x| x.event)
+    }
+
+    /// Replaces the event with the given ID, keeping the ID, and
+    /// returns the old event. Returns `None` if there is no such event.
+    pub fn update_event(&mut self, id: EventId, event: Event) -> Option<Event> {
+        let old_interval = *self.intervals.get(&id)?;
+        let old = self.events.take(&CmpEvent::key(id, old_interval))?.event;
+        self.unindex(id, old_interval);
+        self.index(id, &event);
+        self.intervals.insert(id, event.interval);
+        self.events.insert(CmpEvent::new(id, event));
+        Some(old)
     }
 
+    /// Finds the first of `proposed_times` that has at least `duration`
+    /// free within `range`, returning the free part of it. Proposed
+    /// times are looked at one at a time, in order, so the free time
+    /// found is always within a single one of them.
     pub fn find_time<T>(
         &self,
-        mut proposed_times: T,
+        proposed_times: T,
         range: Range<DateTime<Utc>>,
         duration: Duration,
     ) -> Option<Interval<DateTime<Utc>>>
     where
         T: Iterator<Item = Interval<DateTime<Utc>>>,
     {
-        let mut proposed_time = proposed_times.next()?;
-        let mut free_times = self.free_time_in(range);
-        let mut free_time = free_times.next()?;
-
-        loop {
-            if let Some(x) = proposed_time.intersection(&free_time) {
-                if x.end.signed_duration_since(x.start) >= duration {
-                    return Some(x);
-                }
-            }
-            match proposed_time.cmp(&free_time) {
-                Ordering::Less => proposed_time = proposed_times.next()?,
-                Ordering::Greater => free_time = free_times.next()?,
-                Ordering::Equal => {
-                    free_time = free_times.next()?;
-                    proposed_time = proposed_times.next()?;
-                }
-            }
-        }
+        let free_times = self.free_time_in(range);
+        proposed_times
+            .flat_map(|proposed| {
+                free_times
+                    .iter()
+                    .filter_map(move |x| proposed.intersection(x))
+            })
+            .find(|x| x.end.signed_duration_since(x.start) >= duration)
     }
 }
 
+/// A `Cal` backed by an append-only log of changes on disk. Every
+/// change is written to the log before being applied in memory. The
+/// log is compacted once enough of it describes events that have since
+/// been changed or removed.
+///
+/// Each record is framed with its length and a CRC-32 checksum, so a
+/// torn write or corrupt record is detected when the store is opened
+/// rather than silently ending the log early.
 #[derive(Debug)]
 pub struct PersistentCal {
     cal: Cal,
     store: File,
+    store_path: PathBuf,
+    /// Number of records in the store, including ones superseded by
+    /// later records.
+    records: u64,
+    recovery: Option<Recovery>,
+}
+
+/// Describes a damaged store that was repaired when opened.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub struct Recovery {
+    /// Offset of the torn record at the end of the store. The store was
+    /// truncated here.
+    pub offset: u64,
+    /// Number of intact records read before `offset`.
+    pub records: u64,
+    /// Number of bytes cut off the end of the store.
+    pub discarded: u64,
 }
 
+impl std::fmt::Display for Recovery {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(
+            f,
+            "recovered {} records, discarded {} bytes from offset {}",
+            self.records, self.discarded, self.offset
+        )
+    }
+}
+
+/// A single change in a `PersistentCal` store. `E` is `Event` when
+/// reading and `&Event` when writing, which serialize identically.
+#[derive(Debug, Deserialize, Serialize)]
+enum Record<E> {
+    Add(EventId, E),
+    Update(EventId, E),
+    Remove(EventId),
+    /// Records that IDs below this one have been handed out. Written by
+    /// compaction, which drops the records of removed events.
+    NextId(EventId),
+}
+
+impl<E> Record<E> {
+    /// Converts the event in the record, e.g. from an older layout.
+    fn map<T, F: FnOnce(E) -> T>(self, f: F) -> Record<T> {
+        match self {
+            Record::Add(id, event) => Record::Add(id, f(event)),
+            Record::Update(id, event) => Record::Update(id, f(event)),
+            Record::Remove(id) => Record::Remove(id),
+            Record::NextId(id) => Record::NextId(id),
+        }
+    }
+}
+
+/// Size of the length and checksum preceding each record.
+const FRAME_HEADER_SIZE: usize = 8;
+/// Records claiming to be larger than this are assumed to be corrupt.
+const MAX_RECORD_SIZE: u32 = 16 << 20;
+
+/// Compact once at least this many records are garbage and they
+/// outnumber live ones...
+const COMPACT_MIN_GARBAGE: u64 = 64;
+/// ...or once the store is at least this big and a quarter garbage.
+const COMPACT_MAX_SIZE: u64 = 1 << 20;
+
+#[allow(dead_code)]
 impl PersistentCal {
     pub fn create<P: AsRef<Path>>(store_path: P) -> io::Result<PersistentCal> {
+        let mut store = File::create(store_path.as_ref())?;
+        write_header(&mut store)?;
         Ok(PersistentCal {
             cal: Cal::new(),
-            store: File::create(store_path.as_ref())?,
+            store: store,
+            store_path: store_path.as_ref().to_path_buf(),
+            records: 0,
+            recovery: None,
         })
     }
 
+    /// Opens an existing store. If it ends in a torn record, as left by
+    /// a crash part way through appending it, that record is truncated
+    /// away and the damage is described by `recovery()`. A bad record
+    /// with more of the store after it can't be explained by a crash,
+    /// so opening fails with `io::ErrorKind::InvalidData` and the store
+    /// is left alone.
     pub fn open<P: AsRef<Path>>(store_path: P) -> io::Result<PersistentCal> {
+        Self::open_impl(store_path.as_ref(), false)
+    }
+
+    /// Like `open`, but fails with `io::ErrorKind::InvalidData` instead
+    /// of repairing a damaged store.
+    pub fn open_strict<P: AsRef<Path>>(store_path: P) -> io::Result<PersistentCal> {
+        Self::open_impl(store_path.as_ref(), true)
+    }
+
+    fn open_impl(store_path: &Path, strict: bool) -> io::Result<PersistentCal> {
         let mut cal = Cal::new();
-        {
-            let mut file = File::open(store_path.as_ref())?;
-            while let Ok(event) = bincode::deserialize_from(&mut file) {
-                cal.add_event(event);
+        let mut records = 0;
+        let (intact_len, tail, total_len) = {
+            let file = File::open(store_path)?;
+            let total_len = file.metadata()?.len();
+            let mut reader = BufReader::new(file);
+
+            match read_header(&mut reader)? {
+                Header::Version(STORE_VERSION) => (),
+                Header::Version(version) if version > STORE_VERSION => {
+                    return Err(io::Error::new(
+                        io::ErrorKind::InvalidData,
+                        format!(
+                            "calendar store {} has version {}, but only versions up to {} are supported",
+                            store_path.display(),
+                            version,
+                            STORE_VERSION
+                        ),
+                    ));
+                }
+                Header::Version(version) => {
+                    drop(reader);
+                    migrate::upgrade(store_path, version, strict)?;
+                    return Self::open_impl(store_path, strict);
+                }
+                Header::Legacy => {
+                    drop(reader);
+                    migrate::upgrade(store_path, 0, strict)?;
+                    return Self::open_impl(store_path, strict);
+                }
+                Header::Torn => (),
+            }
+
+            let (len, tail) = read_records(reader, |record| {
+                cal.apply(record);
+                records += 1;
+            })?;
+            (STORE_HEADER_SIZE + len, tail, total_len)
+        };
+
+        if tail == Tail::Corrupt {
+            return Err(damaged_store(store_path, tail, intact_len, records));
+        }
+
+        let mut recovery = None;
+        if intact_len != total_len {
+            // A torn header counts as damage at offset 0.
+            let offset = if intact_len > total_len {
+                0
+            } else {
+                intact_len
+            };
+            let damage = Recovery {
+                offset: offset,
+                records: records,
+                discarded: total_len - offset,
+            };
+            if strict {
+                return Err(damaged_store(
+                    store_path,
+                    tail,
+                    damage.offset,
+                    damage.records,
+                ));
+            }
+
+            if offset == 0 {
+                write_store(store_path, &cal)?;
+            } else {
+                OpenOptions::new()
+                    .write(true)
+                    .open(store_path)?
+                    .set_len(offset)?;
             }
+            recovery = Some(damage);
         }
 
         Ok(PersistentCal {
             cal: cal,
-            store: OpenOptions::new().append(true).open(store_path.as_ref())?,
+            store: OpenOptions::new().append(true).open(store_path)?,
+            store_path: store_path.to_path_buf(),
+            records: records,
+            recovery: recovery,
         })
     }
 
@@ -175,28 +636,324 @@ impl PersistentCal {
         &self.cal
     }
 
-    pub fn add_event(&mut self, event: Event) -> io::Result<()> {
-        match bincode::serialize_into(&self.store, &event) {
-            Ok(()) => {
-                assert!(
-                    self.cal.add_event(event),
-                    "Need to handle Cal::add_event() failing"
-                );
-                Ok(())
-            }
-            Err(err) => match *err {
-                bincode::ErrorKind::Io(io_err) => Err(io_err),
-                _ => panic!("Unexpected error from bincode::serialize_into()"),
-            },
+    /// Describes the damage repaired by `open`, if there was any.
+    pub fn recovery(&self) -> Option<Recovery> {
+        self.recovery
+    }
+
+    pub fn add_event(&mut self, event: Event) -> io::Result<EventId> {
+        let id = self.cal.next_id;
+        self.append(&Record::Add(id, &event))?;
+        self.cal.put_event(id, event);
+        self.compact_if_needed()?;
+        Ok(id)
+    }
+
+    /// Replaces the event with the given ID, returning the old event,
+    /// or `None` if there is no such event.
+    pub fn update_event(&mut self, id: EventId, event: Event) -> io::Result<Option<Event>> {
+        if self.cal.get(id).is_none() {
+            return Ok(None);
+        }
+
+        self.append(&Record::Update(id, &event))?;
+        let old = self.cal.update_event(id, event);
+        self.compact_if_needed()?;
+        Ok(old)
+    }
+
+    /// Removes the event with the given ID, returning it, or `None` if
+    /// there is no such event.
+    pub fn remove_event(&mut self, id: EventId) -> io::Result<Option<Event>> {
+        if self.cal.get(id).is_none() {
+            return Ok(None);
+        }
+
+        self.append(&Record::Remove::<&Event>(id))?;
+        let old = self.cal.remove_event(id);
+        self.compact_if_needed()?;
+        Ok(old)
+    }
+
+    /// Rewrites the store to hold only the live events. A crash part
+    /// way through leaves the old store intact.
+    pub fn compact(&mut self) -> io::Result<()> {
+        self.records = write_store(&self.store_path, &self.cal)?;
+        self.store = OpenOptions::new().append(true).open(&self.store_path)?;
+        Ok(())
+    }
+
+    fn needs_compaction(&self) -> io::Result<bool> {
+        // The record written by compaction to remember the next ID
+        // isn't garbage.
+        let live = self.cal.len() as u64 + 1;
+        let garbage = self.records.saturating_sub(live);
+
+        if garbage >= COMPACT_MIN_GARBAGE && garbage >= live {
+            return Ok(true);
+        }
+
+        Ok(garbage * 4 >= self.records && self.store.metadata()?.len() >= COMPACT_MAX_SIZE)
+    }
+
+    fn compact_if_needed(&mut self) -> io::Result<()> {
+        if self.needs_compaction()? {
+            self.compact()?;
+        }
+        Ok(())
+    }
+
+    fn append(&mut self, record: &Record<&Event>) -> io::Result<()> {
+        write_record(&mut self.store, record)?;
+        self.records += 1;
+        Ok(())
+    }
+}
+
+/// The first thing in a store, followed by `STORE_VERSION` as a
+/// little-endian `u32`. Stores from before the header was introduced
+/// are version 0.
+const STORE_MAGIC: [u8; 8] = *b"calbot\0\0";
+/// The version of the store layout written by this code. Bump this
+/// whenever the layout of `Record` or `Event` changes, and teach
+/// `migrate::upgrade` to convert from the old layout.
+const STORE_VERSION: u32 = 4;
+const STORE_HEADER_SIZE: u64 = 12;
+
+enum Header {
+    Version(u32),
+    /// The store starts with something other than a header.
+    Legacy,
+    /// The store was cut off part way through its header.
+    Torn,
+}
+
+fn write_header<W: Write>(writer: &mut W) -> io::Result<()> {
+    let mut header = Vec::with_capacity(STORE_HEADER_SIZE as usize);
+    header.extend_from_slice(&STORE_MAGIC);
+    header.extend_from_slice(&STORE_VERSION.to_le_bytes());
+    writer.write_all(&header)
+}
+
+fn read_header<R: Read>(reader: &mut R) -> io::Result<Header> {
+    let mut header = [0; STORE_HEADER_SIZE as usize];
+    let len = read_full(reader, &mut header)?;
+    let magic_len = std::cmp::min(len, STORE_MAGIC.len());
+    if header[..magic_len] != STORE_MAGIC[..magic_len] {
+        return Ok(Header::Legacy);
+    }
+
+    if len == 0 {
+        // An empty store holds no events in any version.
+        Ok(Header::Legacy)
+    } else if len < header.len() {
+        Ok(Header::Torn)
+    } else {
+        let mut version = [0; 4];
+        version.copy_from_slice(&header[STORE_MAGIC.len()..]);
+        Ok(Header::Version(u32::from_le_bytes(version)))
+    }
+}
+
+/// Replaces the store at `store_path` with one holding exactly the
+/// events in `cal`, returning the number of records written. The new
+/// store is written to a temporary file which then replaces the old
+/// one, so a crash part way through leaves the old store intact.
+fn write_store(store_path: &Path, cal: &Cal) -> io::Result<u64> {
+    let mut temp_name = OsString::from(".");
+    temp_name.push(store_path.file_name().unwrap_or_default());
+    temp_name.push(".new");
+    let temp_path = store_path.with_file_name(temp_name);
+
+    let mut records = 0;
+    {
+        let mut temp = BufWriter::new(File::create(&temp_path)?);
+        write_header(&mut temp)?;
+        write_record(&mut temp, &Record::NextId::<&Event>(cal.next_id))?;
+        records += 1;
+        for (id, event) in cal.iter() {
+            write_record(&mut temp, &Record::Add(id, event))?;
+            records += 1;
+        }
+        temp.into_inner()?.sync_all()?;
+    }
+
+    fs::rename(&temp_path, store_path)?;
+    Ok(records)
+}
+
+/// Writes a record framed by its length and checksum. The frame is
+/// written with a single call so that a crash is unlikely to leave
+/// more than one record torn.
+fn write_record<W: Write>(writer: &mut W, record: &Record<&Event>) -> io::Result<()> {
+    let payload = bincode::serialize(record).expect("Unexpected error from bincode::serialize()");
+    let mut frame = Vec::with_capacity(FRAME_HEADER_SIZE + payload.len());
+    frame.extend_from_slice(&(payload.len() as u32).to_le_bytes());
+    frame.extend_from_slice(&crc32fast::hash(&payload).to_le_bytes());
+    frame.extend_from_slice(&payload);
+    writer.write_all(&frame)
+}
+
+/// What `read_records` found after the last intact record.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+enum Tail {
+    /// Nothing: every record was intact.
+    Clean,
+    /// A bad record with nothing after it, as left by a crash part way
+    /// through appending it.
+    Torn,
+    /// A bad record with more of the store after it.
+    Corrupt,
+}
+
+/// Reads framed records, passing each to `f`, until the end of the
+/// store or the first torn or corrupt record. Returns the number of
+/// bytes read up to the end of the last intact record, and what came
+/// after it.
+fn read_records<E, R, F>(mut reader: R, mut f: F) -> io::Result<(u64, Tail)>
+where
+    E: DeserializeOwned,
+    R: Read,
+    F: FnMut(Record<E>),
+{
+    let mut offset = 0;
+    loop {
+        let mut header = [0; FRAME_HEADER_SIZE];
+        match read_full(&mut reader, &mut header)? {
+            0 => return Ok((offset, Tail::Clean)),
+            n if n < header.len() => return Ok((offset, Tail::Torn)),
+            _ => (),
+        }
+
+        let mut len = [0; 4];
+        let mut checksum = [0; 4];
+        len.copy_from_slice(&header[0..4]);
+        checksum.copy_from_slice(&header[4..8]);
+        let len = u32::from_le_bytes(len);
+        if len > MAX_RECORD_SIZE {
+            return Ok((offset, bad_record_tail(reader, len.into())?));
+        }
+
+        let mut payload = vec![0; len as usize];
+        if read_full(&mut reader, &mut payload)? < payload.len() {
+            return Ok((offset, Tail::Torn));
+        }
+        if crc32fast::hash(&payload) != u32::from_le_bytes(checksum) {
+            return Ok((offset, bad_record_tail(reader, 0)?));
+        }
+
+        match bincode::deserialize(&payload) {
+            Ok(record) => f(record),
+            Err(_) => return Ok((offset, bad_record_tail(reader, 0)?)),
         }
+        offset += (FRAME_HEADER_SIZE + payload.len()) as u64;
     }
 }
 
+/// The error for a store with damage `tail` at `offset`, after
+/// `records` intact records, that won't be repaired.
+fn damaged_store(store_path: &Path, tail: Tail, offset: u64, records: u64) -> io::Error {
+    let damage = match tail {
+        Tail::Corrupt => "corrupt",
+        Tail::Clean | Tail::Torn => "damaged",
+    };
+    io::Error::new(
+        io::ErrorKind::InvalidData,
+        format!(
+            "calendar store {} is {} at offset {} after {} intact records",
+            store_path.display(),
+            damage,
+            offset,
+            records
+        ),
+    )
+}
+
+/// Classifies a bad record whose frame claims `unread` more bytes than
+/// have been read from `reader`. It's torn if the store ends before the
+/// frame does.
+fn bad_record_tail<R: Read>(mut reader: R, unread: u64) -> io::Result<Tail> {
+    if io::copy(&mut reader, &mut io::sink())? <= unread {
+        Ok(Tail::Torn)
+    } else {
+        Ok(Tail::Corrupt)
+    }
+}
+
+/// Reads until `buf` is full or the reader is exhausted, returning the
+/// number of bytes read.
+fn read_full<R: Read>(reader: &mut R, buf: &mut [u8]) -> io::Result<usize> {
+    let mut read = 0;
+    while read < buf.len() {
+        match reader.read(&mut buf[read..]) {
+            Ok(0) => break,
+            Ok(n) => read += n,
+            Err(ref err) if err.kind() == io::ErrorKind::Interrupted => {}
+            Err(err) => return Err(err),
+        }
+    }
+    Ok(read)
+}
+
 #[derive(Clone, Debug, Deserialize, Eq, PartialEq, Serialize)]
 pub struct Event {
     pub organizer: String,
     pub description: String,
+    /// For recurring events, the interval of the first occurrence.
+    /// For all-day events, the span of `all_day`'s days.
     pub interval: Interval<DateTime<Utc>>,
+    /// For all-day events the rule works in days: it's applied to
+    /// `AllDay::rule_time` of the first day, and its exceptions and
+    /// end are given the same way, whatever the event's zone is.
+    pub recurrence: Option<Recurrence>,
+    /// The days covered by an all-day event, or `None` for events that
+    /// start and end at particular times.
+    pub all_day: Option<AllDay>,
+    /// How many minutes before each occurrence to remind the chat of
+    /// it, or `None` to go by the chat's settings.
+    pub remind_minutes_before: Option<u32>,
+}
+
+/// The local calendar days covered by an all-day event. They're tied
+/// to the days rather than to UTC times, so they always start and end
+/// at midnight in `zone`, whatever its offset is on those days.
+#[derive(Clone, Copy, Debug, Deserialize, Eq, PartialEq, Serialize)]
+pub struct AllDay {
+    pub start: NaiveDate,
+    /// The day after the last day. Always after `start`.
+    pub end: NaiveDate,
+    /// The zone whose days these are.
+    pub zone: Tz,
+}
+
+impl AllDay {
+    /// Gets the span of UTC times these days cover.
+    pub fn interval(&self) -> Interval<DateTime<Utc>> {
+        Interval {
+            start: local_time::day_start(&self.zone, self.start),
+            end: local_time::day_start(&self.zone, self.end),
+        }
+    }
+
+    /// Gets the last day covered.
+    pub fn last(&self) -> NaiveDate {
+        self.end.pred()
+    }
+
+    /// Gets the same number of days starting on `start` instead.
+    pub fn starting_on(&self, start: NaiveDate) -> AllDay {
+        AllDay {
+            start: start,
+            end: start + (self.end - self.start),
+            zone: self.zone,
+        }
+    }
+
+    /// Gets the time standing in for `date` in the recurrence rules of
+    /// all-day events.
+    pub fn rule_time(date: NaiveDate) -> DateTime<Utc> {
+        Utc.from_utc_date(&date).and_hms(0, 0, 0)
+    }
 }
 
 #[allow(dead_code)]
@@ -213,6 +970,9 @@ impl Event {
                 start: start,
                 end: start + duration,
             },
+            recurrence: None,
+            all_day: None,
+            remind_minutes_before: None,
         }
     }
 
@@ -222,13 +982,13 @@ impl Event {
 
     /// There is really no event default, this is a convieneince method, hence why its private
     fn dummy() -> Event {
-        use chrono::TimeZone;
         Event::from_datetime_duration(Utc.ymd(2019, 1, 1).and_hms(0, 0, 0), Duration::hours(1))
     }
 }
 
 #[derive(Clone, Debug, Deserialize, Serialize)]
 struct CmpEvent {
+    id: EventId,
     event: Event,
 }
 
@@ -248,17 +1008,40 @@ impl CmpEvent {
             ..Event::dummy()
         };
 
-        CmpEvent { event: event }
+        CmpEvent::from_event(event)
     }
 
+    /// Makes a `CmpEvent` for use as a lookup key. It sorts before
+    /// any real event with the same interval.
     fn from_event(event: Event) -> CmpEvent {
-        CmpEvent { event: event }
+        CmpEvent::new(EventId(0), event)
+    }
+
+    /// Makes the lookup key for the event with the given ID and
+    /// interval.
+    fn key(id: EventId, interval: Interval<DateTime<Utc>>) -> CmpEvent {
+        CmpEvent {
+            id: id,
+            ..CmpEvent::from_interval(interval)
+        }
+    }
+
+    fn new(id: EventId, event: Event) -> CmpEvent {
+        CmpEvent {
+            id: id,
+            event: event,
+        }
     }
 }
 
+/// Orders events by interval, using the event ID to tell apart events
+/// that share an interval.
 impl Ord for CmpEvent {
     fn cmp(&self, other: &CmpEvent) -> Ordering {
-        self.event.interval.cmp(&other.event.interval)
+        self.event
+            .interval
+            .cmp(&other.event.interval)
+            .then(self.id.cmp(&other.id))
     }
 }
 
@@ -272,7 +1055,7 @@ impl Eq for CmpEvent {}
 
 impl PartialEq for CmpEvent {
     fn eq(&self, other: &CmpEvent) -> bool {
-        self.event.interval.eq(&other.event.interval)
+        self.cmp(other) == Ordering::Equal
     }
 }
 
@@ -280,6 +1063,7 @@ impl PartialEq for CmpEvent {
 mod tests {
     use super::*;
     use chrono::TimeZone;
+    use std::cmp;
     use std::vec::Vec;
 
     #[test]
@@ -360,6 +1144,9 @@ mod tests {
                 start: Utc.ymd(2019, 01, 01).and_hms(0, 0, 0),
                 end: Utc.ymd(2019, 01, 01).and_hms(1, 0, 0),
             },
+            recurrence: None,
+            all_day: None,
+            remind_minutes_before: None,
         });
         let event_b = CmpEvent::from_event(Event {
             organizer: "aaaa".to_string(),
@@ -368,6 +1155,9 @@ mod tests {
                 start: Utc.ymd(2020, 12, 31).and_hms(0, 0, 0),
                 end: Utc.ymd(2020, 12, 31).and_hms(0, 0, 0),
             },
+            recurrence: None,
+            all_day: None,
+            remind_minutes_before: None,
         });
         let event_c = CmpEvent::from_event(Event {
             organizer: "aaaa".to_string(),
@@ -376,6 +1166,9 @@ mod tests {
                 start: Utc.ymd(2019, 01, 01).and_hms(0, 0, 0),
                 end: Utc.ymd(2019, 01, 01).and_hms(2, 0, 0),
             },
+            recurrence: None,
+            all_day: None,
+            remind_minutes_before: None,
         });
 
         assert_eq!(event_a.cmp(&event_b), Ordering::Less);
@@ -417,12 +1210,15 @@ mod tests {
             .next()
             .unwrap();
 
-        assert_eq!(*e, event);
+        assert_eq!(*e.event, event);
     }
 
     #[test]
     fn test_free_time_in() {
-        let event = Event::from_date(Utc.ymd(2019, 1, 1).and_hms(0, 0, 0));
+        // Events that take no time don't split free time, so this one
+        // has a length.
+        let event =
+            Event::from_datetime_duration(Utc.ymd(2019, 1, 1).and_hms(0, 0, 0), Duration::hours(1));
         let mut cal = Cal::new();
         cal.add_event(event.clone());
 
@@ -430,10 +1226,643 @@ mod tests {
             event.interval.start - Duration::days(1)..event.interval.end + Duration::days(1),
         );
 
-        assert_eq!(free_times.count(), 2);
+        assert_eq!(free_times.iter().count(), 2);
 
         let free_times = cal.free_time_in(event.interval.start..event.interval.end);
-        assert_eq!(free_times.count(), 0);
+        assert!(free_times.is_empty());
+    }
+
+    #[test]
+    fn busy_times_include_earlier_events() {
+        use crate::cal::recurrence::Frequency;
+
+        let at = |hour| Utc.ymd(2019, 1, 2).and_hms(hour, 0, 0);
+        let mut cal = Cal::new();
+        // 22:00 the day before until 2:00.
+        cal.add_event(Event::from_datetime_duration(
+            at(0) - Duration::hours(2),
+            Duration::hours(4),
+        ));
+        // 23:00-1:00 every day.
+        cal.add_event(Event {
+            recurrence: Some(Recurrence::new(Frequency::Daily)),
+            ..Event::from_datetime_duration(
+                Utc.ymd(2018, 12, 1).and_hms(23, 0, 0),
+                Duration::hours(2),
+            )
+        });
+
+        assert_eq!(
+            cal.busy_times_in(at(1)..at(23))
+                .into_iter()
+                .collect::<Vec<_>>(),
+            vec![Interval {
+                start: at(1),
+                end: at(2),
+            }]
+        );
+        assert_eq!(
+            cal.free_time_in(at(1)..at(23))
+                .into_iter()
+                .collect::<Vec<_>>(),
+            vec![Interval {
+                start: at(2),
+                end: at(23),
+            }]
+        );
+    }
+
+    #[test]
+    fn events_overlapping_include_earlier_starts() {
+        use crate::cal::recurrence::Frequency;
+
+        let at = |hour| Utc.ymd(2019, 1, 2).and_hms(hour, 0, 0);
+        let mut cal = Cal::new();
+        // 22:00 the day before until 2:00.
+        let late = cal.add_event(Event::from_datetime_duration(
+            at(0) - Duration::hours(2),
+            Duration::hours(4),
+        ));
+        // 23:00-1:00 every day.
+        let nightly = cal.add_event(Event {
+            recurrence: Some(Recurrence::new(Frequency::Daily)),
+            ..Event::from_datetime_duration(
+                Utc.ymd(2018, 12, 1).and_hms(23, 0, 0),
+                Duration::hours(2),
+            )
+        });
+        let ping = cal.add_event(Event::from_datetime_duration(at(1), Duration::zero()));
+        let early = cal.add_event(Event::from_datetime_duration(at(0), Duration::hours(1)));
+
+        let found = |cal: &Cal, range| {
+            cal.events_overlapping(range)
+                .map(|x| (x.id, x.interval.start))
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(
+            found(&cal, at(0) + Duration::minutes(30)..at(12)),
+            vec![
+                (late, at(0) - Duration::hours(2)),
+                (nightly, at(0) - Duration::hours(1)),
+                (early, at(0)),
+                (ping, at(1)),
+            ]
+        );
+        assert_eq!(
+            found(&cal, at(1)..at(23) + Duration::minutes(1)),
+            vec![
+                (late, at(0) - Duration::hours(2)),
+                (ping, at(1)),
+                (nightly, at(23))
+            ]
+        );
+
+        // The index follows events as they change.
+        cal.update_event(
+            late,
+            Event::from_datetime_duration(at(12), Duration::hours(1)),
+        );
+        cal.remove_event(ping);
+        assert_eq!(found(&cal, at(1)..at(12)), vec![]);
+        assert_eq!(found(&cal, at(12)..at(13)), vec![(late, at(12))]);
+    }
+
+    #[test]
+    fn conflicts_are_overlapping_events() {
+        use crate::cal::recurrence::Frequency;
+
+        let at = |hour| Utc.ymd(2019, 1, 2).and_hms(hour, 0, 0);
+        let mut cal = Cal::new();
+        let lunch = cal.add_event(Event::from_datetime_duration(at(12), Duration::hours(1)));
+        // 9:00-10:00 every day.
+        let standup = cal.add_event(Event {
+            recurrence: Some(Recurrence::new(Frequency::Daily)),
+            ..Event::from_datetime_duration(
+                Utc.ymd(2018, 12, 1).and_hms(9, 0, 0),
+                Duration::hours(1),
+            )
+        });
+        let all_day = AllDay {
+            start: NaiveDate::from_ymd(2019, 1, 2),
+            end: NaiveDate::from_ymd(2019, 1, 3),
+            zone: chrono_tz::UTC,
+        };
+        cal.add_event(Event {
+            interval: all_day.interval(),
+            all_day: Some(all_day),
+            ..Event::dummy()
+        });
+
+        let conflicts = |event: Event| {
+            cal.conflicts(&event)
+                .iter()
+                .map(|x| (x.id, x.interval.start))
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(
+            conflicts(Event::from_datetime_duration(at(9), Duration::hours(4))),
+            vec![(standup, at(9)), (lunch, at(12))]
+        );
+        assert_eq!(
+            conflicts(Event::from_datetime_duration(at(10), Duration::hours(2))),
+            vec![]
+        );
+        assert_eq!(
+            conflicts(Event::from_datetime_duration(at(12), Duration::zero())),
+            vec![]
+        );
+    }
+
+    #[test]
+    fn event_ids_round_trip() {
+        assert_eq!(EventId(42).to_string(), "42");
+        assert_eq!("42".parse(), Ok(EventId(42)));
+        assert!("x".parse::<EventId>().is_err());
+    }
+
+    #[test]
+    fn free_time_around_overlapping_events() {
+        let at = |hour| Utc.ymd(2019, 1, 1).and_hms(hour, 0, 0);
+        let mut cal = Cal::new();
+        cal.add_event(Event::from_datetime_duration(at(9), Duration::hours(3)));
+        cal.add_event(Event::from_datetime_duration(at(10), Duration::hours(1)));
+        cal.add_event(Event::from_datetime_duration(at(14), Duration::hours(4)));
+        let all_day = AllDay {
+            start: NaiveDate::from_ymd(2019, 1, 1),
+            end: NaiveDate::from_ymd(2019, 1, 2),
+            zone: chrono_tz::UTC,
+        };
+        cal.add_event(Event {
+            interval: all_day.interval(),
+            all_day: Some(all_day),
+            ..Event::dummy()
+        });
+
+        let free_times: Vec<_> = cal.free_time_in(at(8)..at(16)).into_iter().collect();
+        assert_eq!(
+            free_times,
+            vec![
+                Interval {
+                    start: at(8),
+                    end: at(9),
+                },
+                Interval {
+                    start: at(12),
+                    end: at(14),
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn event_ids_are_unique() {
+        let mut cal = Cal::new();
+        let id_a = cal.add_event(Event::from_date(Utc.ymd(2019, 1, 1).and_hms(0, 0, 0)));
+        let id_b = cal.add_event(Event::from_date(Utc.ymd(2019, 1, 2).and_hms(0, 0, 0)));
+        assert_ne!(id_a, id_b);
+
+        // IDs of removed events must not be handed out again.
+        cal.remove_event(id_b).unwrap();
+        let id_c = cal.add_event(Event::from_date(Utc.ymd(2019, 1, 3).and_hms(0, 0, 0)));
+        assert_ne!(id_b, id_c);
+        assert_ne!(id_a, id_c);
+    }
+
+    #[test]
+    fn get_and_remove_event() {
+        let event = Event::from_date(Utc.ymd(2019, 1, 1).and_hms(0, 0, 0));
+        let mut cal = Cal::new();
+        let id = cal.add_event(event.clone());
+
+        assert_eq!(cal.get(id), Some(&event));
+        assert_eq!(cal.remove_event(id), Some(event));
+        assert_eq!(cal.get(id), None);
+        assert_eq!(cal.remove_event(id), None);
+        assert_eq!(
+            cal.events_in(
+                Utc.ymd(2018, 1, 1).and_hms(0, 0, 0)..Utc.ymd(2020, 1, 1).and_hms(0, 0, 0)
+            )
+            .count(),
+            0
+        );
+    }
+
+    #[test]
+    fn update_event() {
+        let event_a = Event::from_date(Utc.ymd(2019, 1, 1).and_hms(0, 0, 0));
+        let event_b = Event {
+            description: "moved".to_string(),
+            ..Event::from_date(Utc.ymd(2019, 1, 5).and_hms(0, 0, 0))
+        };
+        let mut cal = Cal::new();
+        let id = cal.add_event(event_a.clone());
+
+        assert_eq!(cal.update_event(id, event_b.clone()), Some(event_a));
+        assert_eq!(cal.get(id), Some(&event_b));
+
+        let range = Utc.ymd(2019, 1, 4).and_hms(0, 0, 0)..Utc.ymd(2019, 1, 6).and_hms(0, 0, 0);
+        assert_eq!(
+            cal.events_in(range).map(|x| x.event).collect::<Vec<_>>(),
+            vec![&event_b]
+        );
+    }
+
+    #[test]
+    fn events_can_share_interval() {
+        let event_a = Event {
+            description: "room a".to_string(),
+            ..Event::dummy()
+        };
+        let event_b = Event {
+            description: "room b".to_string(),
+            ..Event::dummy()
+        };
+        let mut cal = Cal::new();
+        let id_a = cal.add_event(event_a.clone());
+        let id_b = cal.add_event(event_b.clone());
+        let id_c = cal.add_event(event_b.clone());
+
+        let range = event_a.interval.start - Duration::days(1)..event_a.interval.end;
+        assert_eq!(
+            cal.events_in(range.clone())
+                .map(|x| x.event)
+                .collect::<Vec<_>>(),
+            vec![&event_a, &event_b, &event_b]
+        );
+
+        cal.remove_event(id_b).unwrap();
+        assert_eq!(cal.get(id_a), Some(&event_a));
+        assert_eq!(cal.get(id_c), Some(&event_b));
+        assert_eq!(cal.events_in(range).count(), 2);
+    }
+
+    #[test]
+    fn recurring_events_in() {
+        use crate::cal::recurrence::End;
+        use crate::cal::recurrence::Frequency;
+
+        let daily = Event {
+            description: "standup".to_string(),
+            recurrence: Some(Recurrence {
+                end: End::Count(10),
+                ..Recurrence::new(Frequency::Daily)
+            }),
+            ..Event::from_datetime_duration(
+                Utc.ymd(2019, 1, 1).and_hms(9, 0, 0),
+                Duration::minutes(15),
+            )
+        };
+        let single =
+            Event::from_datetime_duration(Utc.ymd(2019, 1, 3).and_hms(8, 0, 0), Duration::hours(2));
+
+        let mut cal = Cal::new();
+        let daily_id = cal.add_event(daily.clone());
+        let single_id = cal.add_event(single.clone());
+
+        let range = Utc.ymd(2019, 1, 2).and_hms(12, 0, 0)..Utc.ymd(2019, 1, 4).and_hms(12, 0, 0);
+        let occurrences: Vec<_> = cal.events_in(range).collect();
+        assert_eq!(
+            occurrences,
+            vec![
+                Occurrence {
+                    id: single_id,
+                    event: &single,
+                    interval: single.interval,
+                },
+                Occurrence {
+                    id: daily_id,
+                    event: &daily,
+                    interval: Interval {
+                        start: Utc.ymd(2019, 1, 3).and_hms(9, 0, 0),
+                        end: Utc.ymd(2019, 1, 3).and_hms(9, 15, 0),
+                    },
+                },
+                Occurrence {
+                    id: daily_id,
+                    event: &daily,
+                    interval: Interval {
+                        start: Utc.ymd(2019, 1, 4).and_hms(9, 0, 0),
+                        end: Utc.ymd(2019, 1, 4).and_hms(9, 15, 0),
+                    },
+                },
+            ]
+        );
+
+        // Past the rule's end there should be nothing.
+        let range = Utc.ymd(2019, 1, 11).and_hms(0, 0, 0)..Utc.ymd(2019, 2, 1).and_hms(0, 0, 0);
+        assert_eq!(cal.events_in(range).count(), 0);
+
+        cal.remove_event(daily_id).unwrap();
+        let range = Utc.ymd(2019, 1, 1).and_hms(0, 0, 0)..Utc.ymd(2019, 2, 1).and_hms(0, 0, 0);
+        assert_eq!(cal.events_in(range).count(), 1);
+    }
+
+    #[test]
+    fn all_day_events_in() {
+        use crate::cal::recurrence::Frequency;
+        use chrono::NaiveDate;
+
+        let zone = chrono_tz::Europe::Berlin;
+        let trip_days = AllDay {
+            start: NaiveDate::from_ymd(2019, 3, 29),
+            end: NaiveDate::from_ymd(2019, 4, 2),
+            zone: zone,
+        };
+        let trip = Event {
+            interval: trip_days.interval(),
+            all_day: Some(trip_days),
+            ..Event::dummy()
+        };
+        // Started in winter time, so the rule's days would be off by
+        // an hour in summer if they weren't anchored to local days.
+        let monthly_days = AllDay {
+            start: NaiveDate::from_ymd(2019, 1, 1),
+            end: NaiveDate::from_ymd(2019, 1, 2),
+            zone: zone,
+        };
+        let monthly = Event {
+            interval: monthly_days.interval(),
+            recurrence: Some(Recurrence::new(Frequency::Monthly(
+                recurrence::MonthDay::Day(1),
+            ))),
+            all_day: Some(monthly_days),
+            ..Event::dummy()
+        };
+
+        let mut cal = Cal::new();
+        let trip_id = cal.add_event(trip.clone());
+        let monthly_id = cal.add_event(monthly.clone());
+
+        let april_1 = local_time::day_range(&zone, NaiveDate::from_ymd(2019, 4, 1));
+        let occurrences: Vec<_> = cal.events_in(april_1.clone()).collect();
+        assert_eq!(
+            occurrences,
+            vec![
+                Occurrence {
+                    id: trip_id,
+                    event: &trip,
+                    interval: trip.interval,
+                },
+                Occurrence {
+                    id: monthly_id,
+                    event: &monthly,
+                    interval: Interval {
+                        start: april_1.start,
+                        end: april_1.end,
+                    },
+                },
+            ]
+        );
+        assert_eq!(occurrences[0].days(), Some(trip_days));
+        assert_eq!(
+            occurrences[1].days().map(|x| x.start),
+            Some(NaiveDate::from_ymd(2019, 4, 1))
+        );
+
+        // Neither covers the day after.
+        let april_2 = local_time::day_range(&zone, NaiveDate::from_ymd(2019, 4, 2));
+        assert_eq!(cal.events_in(april_2).count(), 0);
+    }
+
+    #[test]
+    fn find_time_around_recurring_event() {
+        use crate::cal::recurrence::Frequency;
+
+        // Busy 9:00-17:00 every day.
+        let mut cal = Cal::new();
+        cal.add_event(Event {
+            recurrence: Some(Recurrence::new(Frequency::Daily)),
+            ..Event::from_datetime_duration(
+                Utc.ymd(2019, 1, 1).and_hms(9, 0, 0),
+                Duration::hours(8),
+            )
+        });
+
+        let proposed = vec![Interval {
+            start: Utc.ymd(2019, 3, 5).and_hms(16, 0, 0),
+            end: Utc.ymd(2019, 3, 5).and_hms(19, 0, 0),
+        }];
+        let range = Utc.ymd(2019, 3, 5).and_hms(0, 0, 0)..Utc.ymd(2019, 3, 6).and_hms(0, 0, 0);
+        assert_eq!(
+            cal.find_time(proposed.into_iter(), range, Duration::hours(1)),
+            Some(Interval {
+                start: Utc.ymd(2019, 3, 5).and_hms(17, 0, 0),
+                end: Utc.ymd(2019, 3, 5).and_hms(19, 0, 0),
+            })
+        );
+    }
+
+    #[test]
+    fn find_time_later_in_proposed_time() {
+        let at = |hour| Utc.ymd(2019, 1, 1).and_hms(hour, 0, 0);
+        let mut cal = Cal::new();
+        cal.add_event(Event::from_datetime_duration(at(9), Duration::hours(1)));
+        cal.add_event(Event::from_datetime_duration(at(11), Duration::hours(2)));
+
+        // 10:00-11:00 is too short, but 13:00-17:00 is in the same
+        // proposed time.
+        let proposed = vec![Interval {
+            start: at(9),
+            end: at(17),
+        }];
+        assert_eq!(
+            cal.find_time(proposed.into_iter(), at(0)..at(23), Duration::hours(2)),
+            Some(Interval {
+                start: at(13),
+                end: at(17),
+            })
+        );
+    }
+
+    #[test]
+    fn find_time_within_one_proposed_time() {
+        let at = |hour| Utc.ymd(2019, 1, 1).and_hms(hour, 0, 0);
+        let cal = Cal::new();
+
+        // 9:00-11:00 is free, but nobody proposed all of it.
+        let proposed = vec![
+            Interval {
+                start: at(9),
+                end: at(10),
+            },
+            Interval {
+                start: at(10),
+                end: at(11),
+            },
+        ];
+        assert_eq!(
+            cal.find_time(proposed.into_iter(), at(0)..at(23), Duration::hours(2)),
+            None
+        );
+
+        // Only the first proposed time that fits is looked at.
+        let proposed = vec![
+            Interval {
+                start: at(9),
+                end: at(12),
+            },
+            Interval {
+                start: at(10),
+                end: at(11),
+            },
+        ]
+        .into_iter()
+        .chain(std::iter::repeat_with(|| panic!("read too far")));
+        assert_eq!(
+            cal.find_time(proposed, at(0)..at(23), Duration::hours(2)),
+            Some(Interval {
+                start: at(9),
+                end: at(12),
+            })
+        );
+    }
+
+    extern crate quickcheck;
+
+    /// Minutes in the time that random calendars are made in.
+    const ORACLE_MINUTES: i64 = 3 * 24 * 60;
+
+    /// Makes a calendar from random events. Each is a start and length
+    /// in minutes, and whether it repeats daily. Returns it along with
+    /// whether each minute is busy, found by marking every minute of
+    /// every occurrence one at a time.
+    fn oracle_cal(events: &[(u16, u16, bool)]) -> (Cal, Vec<bool>) {
+        use crate::cal::recurrence::Frequency;
+
+        let origin = Utc.ymd(2019, 1, 1).and_hms(0, 0, 0);
+        let mut cal = Cal::new();
+        let mut busy = vec![false; ORACLE_MINUTES as usize];
+        for &(start, length, daily) in events {
+            let start = i64::from(start) % ORACLE_MINUTES;
+            let length = i64::from(length) % (12 * 60);
+            cal.add_event(Event {
+                recurrence: if daily {
+                    Some(Recurrence::new(Frequency::Daily))
+                } else {
+                    None
+                },
+                ..Event::from_datetime_duration(
+                    origin + Duration::minutes(start),
+                    Duration::minutes(length),
+                )
+            });
+
+            let mut occurrence = start;
+            while occurrence < ORACLE_MINUTES {
+                for minute in occurrence..cmp::min(occurrence + length, ORACLE_MINUTES) {
+                    busy[minute as usize] = true;
+                }
+                if !daily {
+                    break;
+                }
+                occurrence += 24 * 60;
+            }
+        }
+        (cal, busy)
+    }
+
+    /// Turns runs of minutes in `range` for which `pred` holds into
+    /// intervals.
+    fn minute_runs(range: Range<i64>, pred: impl Fn(i64) -> bool) -> IntervalSet<DateTime<Utc>> {
+        let origin = Utc.ymd(2019, 1, 1).and_hms(0, 0, 0);
+        let mut runs = IntervalSet::new();
+        let mut run_start = None;
+        for minute in range.start..=range.end {
+            match (run_start, minute < range.end && pred(minute)) {
+                (None, true) => run_start = Some(minute),
+                (Some(start), false) => {
+                    runs.insert(Interval {
+                        start: origin + Duration::minutes(start),
+                        end: origin + Duration::minutes(minute),
+                    });
+                    run_start = None;
+                }
+                _ => (),
+            }
+        }
+        runs
+    }
+
+    fn oracle_range(a: u16, b: u16) -> Range<i64> {
+        let a = i64::from(a) % ORACLE_MINUTES;
+        let b = i64::from(b) % ORACLE_MINUTES;
+        cmp::min(a, b)..cmp::max(a, b)
+    }
+
+    fn to_utc_range(range: &Range<i64>) -> Range<DateTime<Utc>> {
+        let origin = Utc.ymd(2019, 1, 1).and_hms(0, 0, 0);
+        origin + Duration::minutes(range.start)..origin + Duration::minutes(range.end)
+    }
+
+    #[test]
+    fn busy_times_match_oracle() {
+        fn prop(events: Vec<(u16, u16, bool)>, a: u16, b: u16) -> bool {
+            let (cal, busy) = oracle_cal(&events);
+            let range = oracle_range(a, b);
+            cal.busy_times_in(to_utc_range(&range)) == minute_runs(range, |x| busy[x as usize])
+        }
+        quickcheck::quickcheck(prop as fn(Vec<(u16, u16, bool)>, u16, u16) -> bool);
+    }
+
+    #[test]
+    fn free_time_matches_oracle() {
+        fn prop(events: Vec<(u16, u16, bool)>, a: u16, b: u16) -> bool {
+            let (cal, busy) = oracle_cal(&events);
+            let range = oracle_range(a, b);
+            cal.free_time_in(to_utc_range(&range)) == minute_runs(range, |x| !busy[x as usize])
+        }
+        quickcheck::quickcheck(prop as fn(Vec<(u16, u16, bool)>, u16, u16) -> bool);
+    }
+
+    /// Checks `events_overlapping` against expanding every occurrence
+    /// of every event and keeping the ones that overlap.
+    #[test]
+    fn events_overlapping_match_scan() {
+        use crate::cal::recurrence::Frequency;
+
+        fn prop(events: Vec<(u16, u16, bool)>, a: u16, b: u16) -> bool {
+            let origin = Utc.ymd(2019, 1, 1).and_hms(0, 0, 0);
+            let range = to_utc_range(&oracle_range(a, b));
+            let range_interval = Interval {
+                start: range.start,
+                end: range.end,
+            };
+            let mut cal = Cal::new();
+            let mut expected = Vec::new();
+            for &(start, length, daily) in &events {
+                let start = origin + Duration::minutes(i64::from(start) % ORACLE_MINUTES);
+                let length = Duration::minutes(i64::from(length) % (12 * 60));
+                let id = cal.add_event(Event {
+                    recurrence: if daily {
+                        Some(Recurrence::new(Frequency::Daily))
+                    } else {
+                        None
+                    },
+                    ..Event::from_datetime_duration(start, length)
+                });
+
+                let mut occurrence = start;
+                while occurrence < range.end {
+                    let interval = Interval {
+                        start: occurrence,
+                        end: occurrence + length,
+                    };
+                    if interval_tree::overlaps(&interval, &range_interval) {
+                        expected.push((interval, id));
+                    }
+                    if !daily {
+                        break;
+                    }
+                    occurrence = occurrence + Duration::days(1);
+                }
+            }
+            expected.sort();
+
+            cal.events_overlapping(range)
+                .map(|x| (x.interval, x.id))
+                .eq(expected)
+        }
+        quickcheck::quickcheck(prop as fn(Vec<(u16, u16, bool)>, u16, u16) -> bool);
     }
 
     extern crate tempfile;
@@ -486,4 +1915,313 @@ mod tests {
             &true_cal
         );
     }
+
+    #[test]
+    fn persistent_cal_keeps_duplicate_events() {
+        let store_path = tempfile::NamedTempFile::new().unwrap().into_temp_path();
+
+        let true_cal = {
+            let mut cal = PersistentCal::create(&store_path).unwrap();
+            cal.add_event(Event::dummy()).unwrap();
+            cal.add_event(Event::dummy()).unwrap();
+            cal.get_cal().clone()
+        };
+
+        let cal = PersistentCal::open(&store_path).unwrap();
+        assert_eq!(cal.get_cal(), &true_cal);
+        assert_eq!(
+            cal.get_cal()
+                .events_in(Event::dummy().interval.start..Event::dummy().interval.end)
+                .count(),
+            2
+        );
+    }
+
+    #[test]
+    fn persistent_cal_reads_back_updates_and_removals() {
+        let store_path = tempfile::NamedTempFile::new().unwrap().into_temp_path();
+
+        let (true_cal, removed_id) = {
+            let mut cal = PersistentCal::create(&store_path).unwrap();
+            let id_a = cal.add_event(Event::dummy()).unwrap();
+            let id_b = cal.add_event(Event::dummy()).unwrap();
+            let updated = Event {
+                description: "updated".to_string(),
+                ..Event::dummy()
+            };
+            assert_eq!(
+                cal.update_event(id_a, updated).unwrap(),
+                Some(Event::dummy())
+            );
+            assert_eq!(cal.remove_event(id_b).unwrap(), Some(Event::dummy()));
+            assert_eq!(cal.remove_event(id_b).unwrap(), None);
+            (cal.get_cal().clone(), id_b)
+        };
+
+        let mut cal = PersistentCal::open(&store_path).unwrap();
+        assert_eq!(cal.get_cal(), &true_cal);
+        assert_ne!(cal.add_event(Event::dummy()).unwrap(), removed_id);
+    }
+
+    #[test]
+    fn persistent_cal_compact() {
+        use std::fs::metadata;
+
+        let store_path = tempfile::NamedTempFile::new().unwrap().into_temp_path();
+        let mut cal = PersistentCal::create(&store_path).unwrap();
+        let keep = cal.add_event(Event::dummy()).unwrap();
+        let mut last_removed = keep;
+        for _ in 0..10 {
+            last_removed = cal.add_event(Event::dummy()).unwrap();
+            cal.remove_event(last_removed).unwrap();
+        }
+        let size_before = metadata(&store_path).unwrap().len();
+
+        cal.compact().unwrap();
+        assert!(metadata(&store_path).unwrap().len() < size_before);
+
+        let true_cal = cal.get_cal().clone();
+        drop(cal);
+        let mut cal = PersistentCal::open(&store_path).unwrap();
+        assert_eq!(cal.get_cal(), &true_cal);
+        assert_eq!(cal.get_cal().get(keep), Some(&Event::dummy()));
+        // Removed events' IDs must survive compaction too.
+        assert!(cal.add_event(Event::dummy()).unwrap() > last_removed);
+    }
+
+    #[test]
+    fn persistent_cal_compacts_automatically() {
+        use std::fs::metadata;
+
+        let store_path = tempfile::NamedTempFile::new().unwrap().into_temp_path();
+        let mut cal = PersistentCal::create(&store_path).unwrap();
+        let id = cal.add_event(Event::dummy()).unwrap();
+        let record_size = metadata(&store_path).unwrap().len();
+
+        for _ in 0..COMPACT_MIN_GARBAGE + 10 {
+            cal.update_event(id, Event::dummy()).unwrap();
+        }
+
+        assert!(metadata(&store_path).unwrap().len() < record_size * COMPACT_MIN_GARBAGE);
+        assert_eq!(
+            PersistentCal::open(&store_path).unwrap().get_cal(),
+            cal.get_cal()
+        );
+    }
+
+    /// Writes a store with three records and returns the offsets at
+    /// which each record ends.
+    fn write_three_records(store_path: &Path) -> Vec<u64> {
+        use std::fs::metadata;
+
+        let mut cal = PersistentCal::create(store_path).unwrap();
+        (0..3)
+            .map(|_| {
+                cal.add_event(Event::dummy()).unwrap();
+                metadata(store_path).unwrap().len()
+            })
+            .collect()
+    }
+
+    #[test]
+    fn persistent_cal_truncates_torn_tail() {
+        use std::fs::metadata;
+
+        let store_path = tempfile::NamedTempFile::new().unwrap().into_temp_path();
+        let ends = write_three_records(&store_path);
+        OpenOptions::new()
+            .write(true)
+            .open(&store_path)
+            .unwrap()
+            .set_len(ends[2] - 3)
+            .unwrap();
+
+        let mut cal = PersistentCal::open(&store_path).unwrap();
+        assert_eq!(
+            cal.recovery(),
+            Some(Recovery {
+                offset: ends[1],
+                records: 2,
+                discarded: ends[2] - 3 - ends[1],
+            })
+        );
+        assert_eq!(cal.get_cal().len(), 2);
+        assert_eq!(metadata(&store_path).unwrap().len(), ends[1]);
+
+        // New records must land after the intact ones, not the garbage.
+        cal.add_event(Event::dummy()).unwrap();
+        let cal = PersistentCal::open(&store_path).unwrap();
+        assert_eq!(cal.recovery(), None);
+        assert_eq!(cal.get_cal().len(), 3);
+    }
+
+    #[test]
+    fn persistent_cal_detects_corrupt_record() {
+        use std::io::Seek;
+        use std::io::SeekFrom;
+
+        let store_path = tempfile::NamedTempFile::new().unwrap().into_temp_path();
+        let ends = write_three_records(&store_path);
+        {
+            let mut file = OpenOptions::new().write(true).open(&store_path).unwrap();
+            file.seek(SeekFrom::Start(ends[0] + FRAME_HEADER_SIZE as u64 + 2))
+                .unwrap();
+            file.write_all(&[0xff, 0xff]).unwrap();
+        }
+
+        let bytes = std::fs::read(&store_path).unwrap();
+
+        let err = PersistentCal::open_strict(&store_path).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+
+        // Damage with intact records after it isn't a torn append, so
+        // even a repairing open must leave the store alone.
+        let err = PersistentCal::open(&store_path).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+        assert_eq!(std::fs::read(&store_path).unwrap(), bytes);
+    }
+
+    #[test]
+    fn persistent_cal_truncates_corrupt_last_record() {
+        use std::fs::metadata;
+        use std::io::Seek;
+        use std::io::SeekFrom;
+
+        let store_path = tempfile::NamedTempFile::new().unwrap().into_temp_path();
+        let ends = write_three_records(&store_path);
+        {
+            let mut file = OpenOptions::new().write(true).open(&store_path).unwrap();
+            file.seek(SeekFrom::Start(ends[1] + FRAME_HEADER_SIZE as u64 + 2))
+                .unwrap();
+            file.write_all(&[0xff, 0xff]).unwrap();
+        }
+
+        let cal = PersistentCal::open(&store_path).unwrap();
+        assert_eq!(
+            cal.recovery(),
+            Some(Recovery {
+                offset: ends[1],
+                records: 2,
+                discarded: ends[2] - ends[1],
+            })
+        );
+        assert_eq!(metadata(&store_path).unwrap().len(), ends[1]);
+    }
+
+    #[test]
+    fn persistent_cal_rejects_bad_length_mid_store() {
+        use std::io::Seek;
+        use std::io::SeekFrom;
+
+        let store_path = tempfile::NamedTempFile::new().unwrap().into_temp_path();
+        let ends = write_three_records(&store_path);
+        {
+            let mut file = OpenOptions::new().write(true).open(&store_path).unwrap();
+            file.seek(SeekFrom::Start(ends[0])).unwrap();
+            file.write_all(&4u32.to_le_bytes()).unwrap();
+        }
+        let bytes = std::fs::read(&store_path).unwrap();
+
+        let err = PersistentCal::open(&store_path).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+        assert_eq!(std::fs::read(&store_path).unwrap(), bytes);
+    }
+
+    #[test]
+    fn persistent_cal_strict_open_accepts_intact_store() {
+        let store_path = tempfile::NamedTempFile::new().unwrap().into_temp_path();
+        write_three_records(&store_path);
+
+        let cal = PersistentCal::open_strict(&store_path).unwrap();
+        assert_eq!(cal.recovery(), None);
+        assert_eq!(cal.get_cal().len(), 3);
+    }
+
+    #[test]
+    fn persistent_cal_reads_current_layout() {
+        // A store holding a single event with ID 0. If this test
+        // breaks, the store layout has changed: bump `STORE_VERSION`
+        // and add a migration rather than updating these bytes.
+        let mut bytes = Vec::new();
+        bytes.extend_from_slice(b"calbot\0\0");
+        bytes.extend_from_slice(&[4, 0, 0, 0]); // Version
+        bytes.extend_from_slice(&[95, 0, 0, 0]); // Record length
+        bytes.extend_from_slice(&[142, 89, 77, 76]); // Record checksum
+        bytes.extend_from_slice(&[0, 0, 0, 0]); // Record::Add
+        bytes.extend_from_slice(&[0; 8]); // EventId
+        bytes.extend_from_slice(&[3, 0, 0, 0, 0, 0, 0, 0]);
+        bytes.extend_from_slice(b"ann");
+        bytes.extend_from_slice(&[5, 0, 0, 0, 0, 0, 0, 0]);
+        bytes.extend_from_slice(b"lunch");
+        bytes.extend_from_slice(&[20, 0, 0, 0, 0, 0, 0, 0]);
+        bytes.extend_from_slice(b"2019-01-01T00:00:00Z");
+        bytes.extend_from_slice(&[20, 0, 0, 0, 0, 0, 0, 0]);
+        bytes.extend_from_slice(b"2019-01-01T01:00:00Z");
+        bytes.push(0); // No recurrence
+        bytes.push(0); // Not all day
+        bytes.push(0); // No reminder
+
+        let event = Event {
+            organizer: "ann".to_string(),
+            description: "lunch".to_string(),
+            ..Event::dummy()
+        };
+
+        let store_path = tempfile::NamedTempFile::new().unwrap().into_temp_path();
+        std::fs::write(&store_path, &bytes).unwrap();
+        let cal = PersistentCal::open_strict(&store_path).unwrap();
+        assert_eq!(
+            cal.get_cal().iter().collect::<Vec<_>>(),
+            vec![(EventId(0), &event)]
+        );
+
+        // Writing the same event must produce the same bytes.
+        let store_path = tempfile::NamedTempFile::new().unwrap().into_temp_path();
+        PersistentCal::create(&store_path)
+            .unwrap()
+            .add_event(event)
+            .unwrap();
+        assert_eq!(std::fs::read(&store_path).unwrap(), bytes);
+    }
+
+    #[test]
+    fn persistent_cal_rejects_newer_version() {
+        let store_path = tempfile::NamedTempFile::new().unwrap().into_temp_path();
+        let mut bytes = STORE_MAGIC.to_vec();
+        bytes.extend_from_slice(&(STORE_VERSION + 1).to_le_bytes());
+        std::fs::write(&store_path, &bytes).unwrap();
+
+        let err = PersistentCal::open(&store_path).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+        assert_eq!(std::fs::read(&store_path).unwrap(), bytes);
+    }
+
+    #[test]
+    fn persistent_cal_recovers_torn_header() {
+        let store_path = tempfile::NamedTempFile::new().unwrap().into_temp_path();
+        std::fs::write(&store_path, &STORE_MAGIC[..5]).unwrap();
+
+        assert_eq!(
+            PersistentCal::open_strict(&store_path).unwrap_err().kind(),
+            io::ErrorKind::InvalidData
+        );
+
+        let mut cal = PersistentCal::open(&store_path).unwrap();
+        assert_eq!(
+            cal.recovery(),
+            Some(Recovery {
+                offset: 0,
+                records: 0,
+                discarded: 5,
+            })
+        );
+        cal.add_event(Event::dummy()).unwrap();
+        assert_eq!(
+            PersistentCal::open_strict(&store_path)
+                .unwrap()
+                .get_cal()
+                .len(),
+            1
+        );
+    }
 }
@@ -0,0 +1,573 @@
+use crate::local_time;
+
+use std::cmp::max;
+use std::collections::BTreeSet;
+use std::collections::VecDeque;
+use std::iter;
+
+use chrono::DateTime;
+use chrono::Datelike;
+use chrono::Duration;
+use chrono::NaiveDate;
+use chrono::NaiveDateTime;
+use chrono::Utc;
+use chrono::Weekday;
+use chrono_tz::Tz;
+use serde::Deserialize;
+use serde::Serialize;
+
+/// A rule describing how an event repeats, modeled after iCalendar's
+/// RRULE. Occurrences are computed from the start of the event's
+/// first occurrence, which always counts as an occurrence itself, even
+/// if it isn't on one of the rule's days.
+#[derive(Clone, Debug, Deserialize, Eq, PartialEq, Serialize)]
+pub struct Recurrence {
+    pub frequency: Frequency,
+    /// The zone the rule is followed in. Occurrences fall on its days
+    /// and at the first occurrence's time of day there, whatever its
+    /// offset from UTC is at the time. The rules of all-day events work
+    /// in days, and are followed in UTC.
+    pub zone: Tz,
+    /// Repeat every `interval` days, weeks, months, or years depending
+    /// on `frequency`. Zero is treated as one.
+    pub interval: u32,
+    pub end: End,
+    /// Start times of occurrences that have been cancelled. These
+    /// still count towards `End::Count`.
+    pub exceptions: BTreeSet<DateTime<Utc>>,
+}
+
+#[derive(Clone, Debug, Deserialize, Eq, PartialEq, Serialize)]
+pub enum Frequency {
+    Daily,
+    /// Repeats on the given days of the week. If empty, repeats on the
+    /// weekday of the first occurrence.
+    Weekly(Vec<Weekday>),
+    Monthly(MonthDay),
+    /// Repeats on the month and day of the first occurrence. Events
+    /// first occurring on February 29th only repeat in leap years.
+    Yearly,
+}
+
+#[derive(Clone, Copy, Debug, Deserialize, Eq, PartialEq, Serialize)]
+pub enum MonthDay {
+    /// The given day of the month. Months without that day are
+    /// skipped.
+    Day(u32),
+    /// The nth given weekday of the month, e.g. the 2nd Tuesday.
+    /// Negative values count back from the end of the month, so -1 is
+    /// the last such weekday. Months without it are skipped.
+    NthWeekday(i32, Weekday),
+}
+
+#[derive(Clone, Copy, Debug, Deserialize, Eq, PartialEq, Serialize)]
+pub enum End {
+    Never,
+    /// Stops after this many occurrences, including the first.
+    Count(u32),
+    /// Stops after this time. An occurrence starting exactly at it is
+    /// included.
+    Until(DateTime<Utc>),
+}
+
+/// If this many periods in a row have no occurrences, the rule is
+/// assumed to never produce another (e.g. the 30th of February).
+const MAX_EMPTY_PERIODS: u32 = 1000;
+
+#[allow(dead_code)]
+impl Recurrence {
+    /// Makes a rule repeating every period forever, followed in UTC.
+    pub fn new(frequency: Frequency) -> Recurrence {
+        Recurrence {
+            frequency: frequency,
+            zone: chrono_tz::UTC,
+            interval: 1,
+            end: End::Never,
+            exceptions: BTreeSet::new(),
+        }
+    }
+
+    /// Gets the start times of each occurrence of an event whose first
+    /// occurrence starts at `first`, in order.
+    pub fn starts<'a>(&'a self, first: DateTime<Utc>) -> impl Iterator<Item = DateTime<Utc>> + 'a {
+        self.starts_from(first, first)
+    }
+
+    /// Like `starts`, but only yields occurrences starting at or after
+    /// `from`. Where possible this skips straight to the right period
+    /// instead of walking every occurrence since `first`.
+    pub fn starts_from<'a>(
+        &'a self,
+        first: DateTime<Utc>,
+        from: DateTime<Utc>,
+    ) -> impl Iterator<Item = DateTime<Utc>> + 'a {
+        let period = match self.end {
+            // Every occurrence must be counted, so we can't skip any.
+            End::Count(_) => 0,
+            _ => self.periods_before(first, from),
+        };
+
+        Starts {
+            recurrence: self,
+            first: first,
+            period: period,
+            // Later periods can only have later occurrences.
+            pending: if period == 0 {
+                iter::once(first).collect()
+            } else {
+                VecDeque::new()
+            },
+            count: 0,
+            done: false,
+        }
+        .skip_while(move |x| *x < from)
+    }
+
+    fn interval(&self) -> u32 {
+        max(self.interval, 1)
+    }
+
+    fn local(&self, time: DateTime<Utc>) -> NaiveDateTime {
+        time.with_timezone(&self.zone).naive_local()
+    }
+
+    /// Conservatively estimates how many periods can be skipped
+    /// without missing an occurrence at or after `from`.
+    fn periods_before(&self, first: DateTime<Utc>, from: DateTime<Utc>) -> u32 {
+        if from <= first {
+            return 0;
+        }
+        let first = self.local(first);
+        let from = self.local(from);
+
+        let units = match self.frequency {
+            Frequency::Daily => from.signed_duration_since(first).num_days(),
+            Frequency::Weekly(_) => from.signed_duration_since(first).num_weeks(),
+            Frequency::Monthly(_) => {
+                i64::from(from.year() * 12 + from.month0() as i32)
+                    - i64::from(first.year() * 12 + first.month0() as i32)
+            }
+            Frequency::Yearly => i64::from(from.year() - first.year()),
+        };
+
+        max(units / i64::from(self.interval()) - 1, 0) as u32
+    }
+
+    /// Gets the dates in the period `offset` units after the first
+    /// occurrence's period, in order.
+    fn period_dates(&self, first: NaiveDate, offset: u32) -> Vec<NaiveDate> {
+        match self.frequency {
+            Frequency::Daily => vec![first + Duration::days(i64::from(offset))],
+            Frequency::Weekly(ref days) => {
+                let week_start = first
+                    - Duration::days(first.weekday().num_days_from_monday() as _)
+                    + Duration::weeks(i64::from(offset));
+                if days.is_empty() {
+                    return vec![
+                        week_start + Duration::days(first.weekday().num_days_from_monday() as _),
+                    ];
+                }
+
+                let mut day_offsets: Vec<u32> =
+                    days.iter().map(Weekday::num_days_from_monday).collect();
+                day_offsets.sort();
+                day_offsets.dedup();
+                day_offsets
+                    .into_iter()
+                    .map(|x| week_start + Duration::days(i64::from(x)))
+                    .collect()
+            }
+            Frequency::Monthly(day) => {
+                let month = first.year() as i64 * 12 + first.month0() as i64 + i64::from(offset);
+                let (year, month) = ((month / 12) as i32, (month % 12) as u32 + 1);
+                let date = match day {
+                    MonthDay::Day(d) => NaiveDate::from_ymd_opt(year, month, d),
+                    MonthDay::NthWeekday(n, weekday) => nth_weekday(year, month, n, weekday),
+                };
+                date.into_iter().collect()
+            }
+            Frequency::Yearly => {
+                NaiveDate::from_ymd_opt(first.year() + offset as i32, first.month(), first.day())
+                    .into_iter()
+                    .collect()
+            }
+        }
+    }
+}
+
+/// Gets the nth `weekday` of a month, counting from the end if `n` is
+/// negative.
+fn nth_weekday(year: i32, month: u32, n: i32, weekday: Weekday) -> Option<NaiveDate> {
+    let target = weekday.num_days_from_monday() as i32;
+    if n > 0 {
+        let first = NaiveDate::from_ymd_opt(year, month, 1)?;
+        let offset = (target - first.weekday().num_days_from_monday() as i32 + 7) % 7;
+        let day = 1 + offset + 7 * (n - 1);
+        NaiveDate::from_ymd_opt(year, month, day as u32)
+    } else if n < 0 {
+        let next_month = if month == 12 {
+            NaiveDate::from_ymd_opt(year + 1, 1, 1)?
+        } else {
+            NaiveDate::from_ymd_opt(year, month + 1, 1)?
+        };
+        let last = next_month.pred();
+        let offset = (last.weekday().num_days_from_monday() as i32 - target + 7) % 7;
+        let day = last.day() as i32 - offset - 7 * (-n - 1);
+        if day < 1 {
+            None
+        } else {
+            NaiveDate::from_ymd_opt(year, month, day as u32)
+        }
+    } else {
+        None
+    }
+}
+
+struct Starts<'a> {
+    recurrence: &'a Recurrence,
+    first: DateTime<Utc>,
+    /// Index of the next period to expand, in units of
+    /// `recurrence.interval`.
+    period: u32,
+    /// Candidate start times from the last expanded period, or the
+    /// first occurrence before any are expanded.
+    pending: VecDeque<DateTime<Utc>>,
+    /// Number of occurrences yielded or skipped as exceptions so far.
+    count: u32,
+    done: bool,
+}
+
+impl<'a> Starts<'a> {
+    /// Expands periods until one has a candidate start time. Returns
+    /// false if there are none left.
+    fn fill(&mut self) -> bool {
+        let zone = self.recurrence.zone;
+        let first_local = self.recurrence.local(self.first);
+        let (first_date, time) = (first_local.date(), first_local.time());
+
+        for _ in 0..MAX_EMPTY_PERIODS {
+            let offset = match self.period.checked_mul(self.recurrence.interval()) {
+                Some(x) => x,
+                None => return false,
+            };
+            self.period += 1;
+
+            let first = self.first;
+            self.pending.extend(
+                self.recurrence
+                    .period_dates(first_date, offset)
+                    .into_iter()
+                    .map(|date| local_time::to_utc_nearest(&zone, date.and_time(time)))
+                    // The first occurrence is already counted.
+                    .filter(|start| *start > first),
+            );
+            if !self.pending.is_empty() {
+                return true;
+            }
+        }
+
+        false
+    }
+}
+
+impl<'a> Iterator for Starts<'a> {
+    type Item = DateTime<Utc>;
+
+    fn next(&mut self) -> Option<DateTime<Utc>> {
+        while !self.done {
+            if self.pending.is_empty() && !self.fill() {
+                self.done = true;
+                break;
+            }
+            let start = self.pending.pop_front().unwrap();
+
+            let finished = match self.recurrence.end {
+                End::Never => false,
+                End::Count(n) => self.count >= n,
+                End::Until(until) => start > until,
+            };
+            if finished {
+                self.done = true;
+                break;
+            }
+
+            self.count += 1;
+            if !self.recurrence.exceptions.contains(&start) {
+                return Some(start);
+            }
+        }
+
+        None
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use chrono::TimeZone;
+    use chrono_tz::America::Los_Angeles;
+
+    fn take_dates(recurrence: &Recurrence, first: DateTime<Utc>, n: usize) -> Vec<NaiveDate> {
+        recurrence
+            .starts(first)
+            .take(n)
+            .map(|x| x.naive_utc().date())
+            .collect()
+    }
+
+    fn dates(ymds: &[(i32, u32, u32)]) -> Vec<NaiveDate> {
+        ymds.iter()
+            .map(|&(y, m, d)| NaiveDate::from_ymd(y, m, d))
+            .collect()
+    }
+
+    #[test]
+    fn daily_with_interval_and_count() {
+        let recurrence = Recurrence {
+            interval: 2,
+            end: End::Count(3),
+            ..Recurrence::new(Frequency::Daily)
+        };
+        let first = Utc.ymd(2019, 1, 30).and_hms(9, 30, 0);
+
+        let starts: Vec<_> = recurrence.starts(first).collect();
+        assert_eq!(
+            starts,
+            vec![
+                first,
+                Utc.ymd(2019, 2, 1).and_hms(9, 30, 0),
+                Utc.ymd(2019, 2, 3).and_hms(9, 30, 0),
+            ]
+        );
+    }
+
+    #[test]
+    fn weekly_on_weekdays() {
+        // 2019-01-02 is a Wednesday.
+        let recurrence = Recurrence::new(Frequency::Weekly(vec![
+            Weekday::Fri,
+            Weekday::Mon,
+            Weekday::Wed,
+        ]));
+        let first = Utc.ymd(2019, 1, 2).and_hms(12, 0, 0);
+
+        assert_eq!(
+            take_dates(&recurrence, first, 5),
+            dates(&[
+                (2019, 1, 2),
+                (2019, 1, 4),
+                (2019, 1, 7),
+                (2019, 1, 9),
+                (2019, 1, 11)
+            ])
+        );
+    }
+
+    #[test]
+    fn weekly_defaults_to_first_weekday() {
+        let recurrence = Recurrence {
+            interval: 2,
+            ..Recurrence::new(Frequency::Weekly(vec![]))
+        };
+        let first = Utc.ymd(2019, 1, 2).and_hms(12, 0, 0);
+
+        assert_eq!(
+            take_dates(&recurrence, first, 3),
+            dates(&[(2019, 1, 2), (2019, 1, 16), (2019, 1, 30)])
+        );
+    }
+
+    #[test]
+    fn monthly_by_day_skips_short_months() {
+        let recurrence = Recurrence::new(Frequency::Monthly(MonthDay::Day(31)));
+        let first = Utc.ymd(2019, 1, 31).and_hms(0, 0, 0);
+
+        assert_eq!(
+            take_dates(&recurrence, first, 4),
+            dates(&[(2019, 1, 31), (2019, 3, 31), (2019, 5, 31), (2019, 7, 31)])
+        );
+    }
+
+    #[test]
+    fn monthly_by_nth_weekday() {
+        let second_tuesday =
+            Recurrence::new(Frequency::Monthly(MonthDay::NthWeekday(2, Weekday::Tue)));
+        let first = Utc.ymd(2019, 1, 8).and_hms(0, 0, 0);
+        assert_eq!(
+            take_dates(&second_tuesday, first, 3),
+            dates(&[(2019, 1, 8), (2019, 2, 12), (2019, 3, 12)])
+        );
+
+        let last_friday =
+            Recurrence::new(Frequency::Monthly(MonthDay::NthWeekday(-1, Weekday::Fri)));
+        let first = Utc.ymd(2019, 1, 25).and_hms(0, 0, 0);
+        assert_eq!(
+            take_dates(&last_friday, first, 3),
+            dates(&[(2019, 1, 25), (2019, 2, 22), (2019, 3, 29)])
+        );
+    }
+
+    #[test]
+    fn first_occurrence_always_counts() {
+        // 2019-01-02 is a Wednesday.
+        let recurrence = Recurrence {
+            end: End::Count(3),
+            ..Recurrence::new(Frequency::Weekly(vec![Weekday::Fri]))
+        };
+        let first = Utc.ymd(2019, 1, 2).and_hms(12, 0, 0);
+
+        assert_eq!(
+            take_dates(&recurrence, first, 5),
+            dates(&[(2019, 1, 2), (2019, 1, 4), (2019, 1, 11)])
+        );
+    }
+
+    #[test]
+    fn zoned_rules_keep_local_times() {
+        // 9:00 on Mondays in Los Angeles, across the end of daylight
+        // time on 2019-11-03.
+        let recurrence = Recurrence {
+            zone: Los_Angeles,
+            ..Recurrence::new(Frequency::Weekly(vec![]))
+        };
+        let first = Los_Angeles
+            .ymd(2019, 10, 28)
+            .and_hms(9, 0, 0)
+            .with_timezone(&Utc);
+        assert_eq!(
+            recurrence.starts(first).take(2).collect::<Vec<_>>(),
+            vec![
+                Utc.ymd(2019, 10, 28).and_hms(16, 0, 0),
+                Utc.ymd(2019, 11, 4).and_hms(17, 0, 0),
+            ]
+        );
+
+        // 20:00 on Mondays and Wednesdays is the next day in UTC.
+        let recurrence = Recurrence {
+            zone: Los_Angeles,
+            ..Recurrence::new(Frequency::Weekly(vec![Weekday::Mon, Weekday::Wed]))
+        };
+        let first = Los_Angeles
+            .ymd(2019, 10, 28)
+            .and_hms(20, 0, 0)
+            .with_timezone(&Utc);
+        let local: Vec<_> = recurrence
+            .starts(first)
+            .take(4)
+            .map(|x| x.with_timezone(&Los_Angeles).naive_local())
+            .collect();
+        assert_eq!(
+            local,
+            vec![
+                NaiveDate::from_ymd(2019, 10, 28).and_hms(20, 0, 0),
+                NaiveDate::from_ymd(2019, 10, 30).and_hms(20, 0, 0),
+                NaiveDate::from_ymd(2019, 11, 4).and_hms(20, 0, 0),
+                NaiveDate::from_ymd(2019, 11, 6).and_hms(20, 0, 0),
+            ]
+        );
+
+        // The 31st at 23:00 is on the 1st in UTC.
+        let recurrence = Recurrence {
+            zone: Los_Angeles,
+            ..Recurrence::new(Frequency::Monthly(MonthDay::Day(31)))
+        };
+        let first = Los_Angeles
+            .ymd(2019, 1, 31)
+            .and_hms(23, 0, 0)
+            .with_timezone(&Utc);
+        let local: Vec<_> = recurrence
+            .starts(first)
+            .take(2)
+            .map(|x| x.with_timezone(&Los_Angeles).naive_local())
+            .collect();
+        assert_eq!(
+            local,
+            vec![
+                NaiveDate::from_ymd(2019, 1, 31).and_hms(23, 0, 0),
+                NaiveDate::from_ymd(2019, 3, 31).and_hms(23, 0, 0),
+            ]
+        );
+    }
+
+    #[test]
+    fn yearly_on_leap_day() {
+        let recurrence = Recurrence::new(Frequency::Yearly);
+        let first = Utc.ymd(2020, 2, 29).and_hms(0, 0, 0);
+
+        assert_eq!(
+            take_dates(&recurrence, first, 3),
+            dates(&[(2020, 2, 29), (2024, 2, 29), (2028, 2, 29)])
+        );
+    }
+
+    #[test]
+    fn until_is_inclusive() {
+        let recurrence = Recurrence {
+            end: End::Until(Utc.ymd(2019, 1, 3).and_hms(8, 0, 0)),
+            ..Recurrence::new(Frequency::Daily)
+        };
+        let first = Utc.ymd(2019, 1, 1).and_hms(8, 0, 0);
+
+        assert_eq!(recurrence.starts(first).count(), 3);
+    }
+
+    #[test]
+    fn exceptions_are_skipped_but_counted() {
+        let mut recurrence = Recurrence {
+            end: End::Count(3),
+            ..Recurrence::new(Frequency::Daily)
+        };
+        recurrence
+            .exceptions
+            .insert(Utc.ymd(2019, 1, 2).and_hms(8, 0, 0));
+        let first = Utc.ymd(2019, 1, 1).and_hms(8, 0, 0);
+
+        assert_eq!(
+            take_dates(&recurrence, first, 10),
+            dates(&[(2019, 1, 1), (2019, 1, 3)])
+        );
+    }
+
+    #[test]
+    fn impossible_rule_terminates() {
+        // Every 12 months from February never reaches a 30th, so only
+        // the first occurrence happens.
+        let recurrence = Recurrence {
+            interval: 12,
+            ..Recurrence::new(Frequency::Monthly(MonthDay::Day(30)))
+        };
+        let first = Utc.ymd(2019, 2, 1).and_hms(0, 0, 0);
+
+        assert_eq!(recurrence.starts(first).collect::<Vec<_>>(), vec![first]);
+    }
+
+    #[test]
+    fn starts_from_matches_full_iteration() {
+        let first = Utc.ymd(2019, 1, 31).and_hms(10, 0, 0);
+        let from = Utc.ymd(2021, 6, 15).and_hms(0, 0, 0);
+        let rules = vec![
+            Recurrence::new(Frequency::Daily),
+            Recurrence::new(Frequency::Weekly(vec![Weekday::Tue, Weekday::Sun])),
+            Recurrence {
+                interval: 5,
+                ..Recurrence::new(Frequency::Monthly(MonthDay::Day(31)))
+            },
+            Recurrence::new(Frequency::Monthly(MonthDay::NthWeekday(-2, Weekday::Thu))),
+            Recurrence {
+                interval: 3,
+                ..Recurrence::new(Frequency::Yearly)
+            },
+        ];
+
+        for rule in rules {
+            let expected: Vec<_> = rule
+                .starts(first)
+                .skip_while(|x| *x < from)
+                .take(5)
+                .collect();
+            let actual: Vec<_> = rule.starts_from(first, from).take(5).collect();
+            assert_eq!(actual, expected, "{:?}", rule);
+        }
+    }
+}
@@ -0,0 +1,454 @@
+//! State kept for each chat. Every chat has its own directory holding
+//! its calendar store and settings, so chats never see each other's
+//! events.
+
+use crate::cal::Event;
+use crate::cal::EventId;
+use crate::cal::PersistentCal;
+
+use std::collections::hash_map::Entry;
+use std::collections::BTreeSet;
+use std::collections::HashMap;
+use std::fs;
+use std::fs::File;
+use std::io;
+use std::io::Write;
+use std::path::Path;
+use std::path::PathBuf;
+
+use chrono::DateTime;
+use chrono::NaiveDate;
+use chrono::NaiveTime;
+use chrono::Utc;
+use chrono_tz::Tz;
+use serde::de::DeserializeOwned;
+use serde::Deserialize;
+use serde::Serialize;
+
+/// The chats the bot has seen, with their state loaded lazily.
+pub struct Chats {
+    dir: PathBuf,
+    chats: HashMap<i64, Chat>,
+}
+
+struct Chat {
+    dir: PathBuf,
+    cal: PersistentCal,
+    settings: Settings,
+    sent_reminders: BTreeSet<SentReminder>,
+    /// The last day the chat was sent a digest for.
+    last_digest: Option<NaiveDate>,
+}
+
+/// A chat's settings, saved as JSON next to its calendar.
+#[derive(Clone, Debug, Deserialize, Eq, PartialEq, Serialize)]
+#[serde(default)]
+pub struct Settings {
+    /// The zone that times are read and shown in.
+    pub timezone: Tz,
+    /// Whether /add_event refuses events that overlap others, rather
+    /// than adding them and offering to cancel.
+    pub reject_overlaps: bool,
+    /// How many minutes before events to remind the chat of them, for
+    /// events that don't say, or `None` to only remind of those that
+    /// do.
+    pub remind_minutes_before: Option<u32>,
+    /// The local time to post each day's agenda at, or `None` to not
+    /// post it.
+    pub digest: Option<NaiveTime>,
+}
+
+/// Records that the chat was reminded of an occurrence of an event,
+/// so that it isn't reminded again.
+#[derive(Clone, Copy, Debug, Deserialize, Eq, Ord, PartialEq, PartialOrd, Serialize)]
+pub struct SentReminder {
+    pub event: EventId,
+    /// When the occurrence starts.
+    pub start: DateTime<Utc>,
+}
+
+impl Default for Settings {
+    fn default() -> Settings {
+        Settings {
+            timezone: DEFAULT_TIMEZONE,
+            reject_overlaps: false,
+            remind_minutes_before: None,
+            digest: None,
+        }
+    }
+}
+
+/// The zone of chats that haven't set one.
+pub const DEFAULT_TIMEZONE: Tz = chrono_tz::America::Los_Angeles;
+
+const CAL_FILE: &'static str = "cal";
+
+const SETTINGS_FILE: &'static str = "settings.json";
+
+const SENT_REMINDERS_FILE: &'static str = "reminders.json";
+
+const LAST_DIGEST_FILE: &'static str = "digest.json";
+
+impl Chats {
+    /// Creates a `Chats` keeping its state under `dir`, creating `dir`
+    /// if needed.
+    pub fn new<P: AsRef<Path>>(dir: P) -> io::Result<Chats> {
+        fs::create_dir_all(dir.as_ref())?;
+        Ok(Chats {
+            dir: dir.as_ref().to_path_buf(),
+            chats: HashMap::new(),
+        })
+    }
+
+    /// Moves the calendar store at `path` into a chat that hasn't got
+    /// one yet. Stores from before each chat had its own calendar are
+    /// brought in this way.
+    pub fn adopt_cal<P: AsRef<Path>>(&mut self, path: P, chat_id: i64) -> io::Result<()> {
+        let chat_dir = self.dir.join(chat_id.to_string());
+        let cal_path = chat_dir.join(CAL_FILE);
+        if self.chats.contains_key(&chat_id) || cal_path.exists() {
+            return Err(io::Error::new(
+                io::ErrorKind::AlreadyExists,
+                format!("chat {} already has a calendar", chat_id),
+            ));
+        }
+        fs::create_dir_all(&chat_dir)?;
+        fs::rename(path, cal_path)
+    }
+
+    /// Lists the chats with state under the directory, including ones
+    /// that haven't been loaded yet.
+    pub fn chat_ids(&self) -> io::Result<Vec<i64>> {
+        let mut ids = Vec::new();
+        for entry in fs::read_dir(&self.dir)? {
+            let entry = entry?;
+            if !entry.file_type()?.is_dir() {
+                continue;
+            }
+            if let Some(id) = entry.file_name().to_str().and_then(|x| x.parse().ok()) {
+                ids.push(id);
+            }
+        }
+        ids.sort();
+        Ok(ids)
+    }
+
+    /// Gets a chat's calendar.
+    pub fn cal(&mut self, chat_id: i64) -> io::Result<&mut PersistentCal> {
+        Ok(&mut self.chat(chat_id)?.cal)
+    }
+
+    /// Gets a chat's settings.
+    pub fn settings(&mut self, chat_id: i64) -> io::Result<&Settings> {
+        Ok(&self.chat(chat_id)?.settings)
+    }
+
+    /// Changes a chat's settings and saves them. If the time zone
+    /// changes, all-day events are moved to cover the same days in the
+    /// new zone.
+    pub fn set_settings(&mut self, chat_id: i64, settings: Settings) -> io::Result<()> {
+        let chat = self.chat(chat_id)?;
+
+        if settings.timezone != chat.settings.timezone {
+            let moved: Vec<_> = chat
+                .cal
+                .get_cal()
+                .iter()
+                .filter_map(|(id, event)| {
+                    let mut all_day = event.all_day?;
+                    all_day.zone = settings.timezone;
+                    let event = Event {
+                        interval: all_day.interval(),
+                        all_day: Some(all_day),
+                        ..event.clone()
+                    };
+                    Some((id, event))
+                })
+                .collect();
+            for (id, event) in moved {
+                chat.cal.update_event(id, event)?;
+            }
+        }
+
+        write_json(&chat.dir, SETTINGS_FILE, &settings)?;
+        chat.settings = settings;
+        Ok(())
+    }
+
+    /// Gets the reminders sent to a chat for occurrences that haven't
+    /// started yet.
+    pub fn sent_reminders(&mut self, chat_id: i64) -> io::Result<&BTreeSet<SentReminder>> {
+        Ok(&self.chat(chat_id)?.sent_reminders)
+    }
+
+    /// Records that a chat was sent a reminder, and saves the record.
+    /// Records of occurrences that started by `now` are dropped, since
+    /// they're never reminded of again anyway.
+    pub fn reminder_sent(
+        &mut self,
+        chat_id: i64,
+        reminder: SentReminder,
+        now: DateTime<Utc>,
+    ) -> io::Result<()> {
+        let chat = self.chat(chat_id)?;
+        let mut sent = chat.sent_reminders.clone();
+        sent.insert(reminder);
+        sent.retain(|x| x.start > now);
+        write_json(&chat.dir, SENT_REMINDERS_FILE, &sent)?;
+        chat.sent_reminders = sent;
+        Ok(())
+    }
+
+    /// Gets the last day a chat was sent a digest for, if any.
+    pub fn last_digest(&mut self, chat_id: i64) -> io::Result<Option<NaiveDate>> {
+        Ok(self.chat(chat_id)?.last_digest)
+    }
+
+    /// Records that a chat was sent the digest for `date`, and saves
+    /// the record.
+    pub fn digest_sent(&mut self, chat_id: i64, date: NaiveDate) -> io::Result<()> {
+        let chat = self.chat(chat_id)?;
+        write_json(&chat.dir, LAST_DIGEST_FILE, &Some(date))?;
+        chat.last_digest = Some(date);
+        Ok(())
+    }
+
+    /// Gets a chat's state, loading it or creating it the first time
+    /// it's needed.
+    fn chat(&mut self, chat_id: i64) -> io::Result<&mut Chat> {
+        match self.chats.entry(chat_id) {
+            Entry::Occupied(entry) => Ok(entry.into_mut()),
+            Entry::Vacant(entry) => {
+                let chat_dir = self.dir.join(chat_id.to_string());
+                fs::create_dir_all(&chat_dir)?;
+
+                let cal = PersistentCal::open_or_create(chat_dir.join(CAL_FILE))?;
+                if let Some(recovery) = cal.recovery() {
+                    println!("Calendar for chat {} was damaged: {}", chat_id, recovery);
+                }
+
+                let settings = read_json(&chat_dir, SETTINGS_FILE)?;
+                let sent_reminders = read_json(&chat_dir, SENT_REMINDERS_FILE)?;
+                let last_digest = read_json(&chat_dir, LAST_DIGEST_FILE)?;
+
+                Ok(entry.insert(Chat {
+                    dir: chat_dir,
+                    cal: cal,
+                    settings: settings,
+                    sent_reminders: sent_reminders,
+                    last_digest: last_digest,
+                }))
+            }
+        }
+    }
+}
+
+/// Reads a JSON file in `dir`, or gets the default value if there's
+/// no such file.
+fn read_json<T: Default + DeserializeOwned>(dir: &Path, name: &str) -> io::Result<T> {
+    match fs::read(dir.join(name)) {
+        Ok(json) => Ok(serde_json::from_slice(&json)?),
+        Err(ref err) if err.kind() == io::ErrorKind::NotFound => Ok(T::default()),
+        Err(err) => Err(err),
+    }
+}
+
+/// Writes `value` as a JSON file in `dir`, replacing any file there.
+fn write_json<T: Serialize>(dir: &Path, name: &str, value: &T) -> io::Result<()> {
+    // Write and sync a temporary file first so that a crash can't
+    // leave the file half written.
+    let temp_path = dir.join(format!(".{}.new", name));
+    let mut temp = File::create(&temp_path)?;
+    temp.write_all(&serde_json::to_vec_pretty(value)?)?;
+    temp.sync_all()?;
+    fs::rename(&temp_path, dir.join(name))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::cal::interval::Interval;
+    use crate::cal::AllDay;
+
+    use chrono::TimeZone;
+    use chrono::Utc;
+
+    extern crate tempfile;
+
+    fn event() -> Event {
+        Event {
+            organizer: String::new(),
+            description: String::from("lunch"),
+            interval: Interval {
+                start: Utc.ymd(2019, 1, 1).and_hms(12, 0, 0),
+                end: Utc.ymd(2019, 1, 1).and_hms(13, 0, 0),
+            },
+            recurrence: None,
+            all_day: None,
+            remind_minutes_before: None,
+        }
+    }
+
+    #[test]
+    fn chats_have_separate_cals() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut chats = Chats::new(dir.path()).unwrap();
+
+        let id = chats.cal(1).unwrap().add_event(event()).unwrap();
+        assert_eq!(chats.cal(1).unwrap().get_cal().len(), 1);
+        assert_eq!(chats.cal(-2).unwrap().get_cal().len(), 0);
+        drop(chats);
+
+        let mut chats = Chats::new(dir.path()).unwrap();
+        assert_eq!(chats.cal(1).unwrap().get_cal().get(id), Some(&event()));
+        assert_eq!(chats.cal(-2).unwrap().get_cal().len(), 0);
+    }
+
+    #[test]
+    fn cals_can_be_adopted() {
+        let dir = tempfile::tempdir().unwrap();
+        let legacy_path = dir.path().join("cal");
+        let id = PersistentCal::create(&legacy_path)
+            .unwrap()
+            .add_event(event())
+            .unwrap();
+        let mut chats = Chats::new(dir.path().join("chats")).unwrap();
+
+        chats.adopt_cal(&legacy_path, 5).unwrap();
+        assert!(!legacy_path.exists());
+        assert_eq!(chats.cal(5).unwrap().get_cal().get(id), Some(&event()));
+
+        // Chats never lose a calendar they already have.
+        PersistentCal::create(&legacy_path).unwrap();
+        let err = chats.adopt_cal(&legacy_path, 5).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::AlreadyExists);
+        assert!(legacy_path.exists());
+        drop(chats);
+        let mut chats = Chats::new(dir.path().join("chats")).unwrap();
+        assert_eq!(
+            chats.adopt_cal(&legacy_path, 5).unwrap_err().kind(),
+            io::ErrorKind::AlreadyExists
+        );
+        assert_eq!(chats.cal(5).unwrap().get_cal().len(), 1);
+    }
+
+    #[test]
+    fn settings_are_saved() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut chats = Chats::new(dir.path()).unwrap();
+        assert_eq!(chats.settings(1).unwrap(), &Settings::default());
+
+        let settings = Settings {
+            timezone: chrono_tz::Europe::Berlin,
+            reject_overlaps: true,
+            remind_minutes_before: Some(10),
+            digest: Some(NaiveTime::from_hms(8, 0, 0)),
+        };
+        chats.set_settings(1, settings.clone()).unwrap();
+        assert_eq!(chats.settings(1).unwrap(), &settings);
+        drop(chats);
+
+        let mut chats = Chats::new(dir.path()).unwrap();
+        assert_eq!(chats.settings(1).unwrap(), &settings);
+        assert_eq!(chats.settings(2).unwrap(), &Settings::default());
+        assert_eq!(
+            fs::read_to_string(dir.path().join("1").join(SETTINGS_FILE)).unwrap(),
+            "{\n  \"timezone\": \"Europe/Berlin\",\n  \"reject_overlaps\": true,\n  \"remind_minutes_before\": 10,\n  \"digest\": \"08:00:00\"\n}"
+        );
+
+        // Settings saved before a setting existed get its default.
+        fs::write(
+            dir.path().join("1").join(SETTINGS_FILE),
+            "{\"timezone\": \"Europe/Berlin\"}",
+        )
+        .unwrap();
+        let mut chats = Chats::new(dir.path()).unwrap();
+        assert!(!chats.settings(1).unwrap().reject_overlaps);
+    }
+
+    #[test]
+    fn sent_reminders_are_saved() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut chats = Chats::new(dir.path()).unwrap();
+        let lunch = chats.cal(1).unwrap().add_event(event()).unwrap();
+        let at = |hour| Utc.ymd(2019, 1, 1).and_hms(hour, 0, 0);
+        let reminder = |hour| SentReminder {
+            event: lunch,
+            start: at(hour),
+        };
+
+        chats.reminder_sent(1, reminder(12), at(11)).unwrap();
+        chats.reminder_sent(-2, reminder(13), at(11)).unwrap();
+        drop(chats);
+
+        let mut chats = Chats::new(dir.path()).unwrap();
+        assert_eq!(chats.chat_ids().unwrap(), vec![-2, 1]);
+        assert_eq!(
+            chats.sent_reminders(1).unwrap().iter().collect::<Vec<_>>(),
+            vec![&reminder(12)]
+        );
+
+        // Once an occurrence has started, its record is dropped.
+        chats.reminder_sent(1, reminder(14), at(12)).unwrap();
+        assert_eq!(
+            chats.sent_reminders(1).unwrap().iter().collect::<Vec<_>>(),
+            vec![&reminder(14)]
+        );
+    }
+
+    #[test]
+    fn last_digest_is_saved() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut chats = Chats::new(dir.path()).unwrap();
+        assert_eq!(chats.last_digest(1).unwrap(), None);
+
+        chats
+            .digest_sent(1, NaiveDate::from_ymd(2019, 1, 1))
+            .unwrap();
+        drop(chats);
+
+        let mut chats = Chats::new(dir.path()).unwrap();
+        assert_eq!(
+            chats.last_digest(1).unwrap(),
+            Some(NaiveDate::from_ymd(2019, 1, 1))
+        );
+        assert_eq!(chats.last_digest(2).unwrap(), None);
+    }
+
+    #[test]
+    fn all_day_events_follow_timezone() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut chats = Chats::new(dir.path()).unwrap();
+        let all_day = AllDay {
+            start: NaiveDate::from_ymd(2019, 1, 1),
+            end: NaiveDate::from_ymd(2019, 1, 2),
+            zone: DEFAULT_TIMEZONE,
+        };
+        let holiday = chats
+            .cal(1)
+            .unwrap()
+            .add_event(Event {
+                interval: all_day.interval(),
+                all_day: Some(all_day),
+                ..event()
+            })
+            .unwrap();
+        let lunch = chats.cal(1).unwrap().add_event(event()).unwrap();
+
+        let settings = Settings {
+            timezone: chrono_tz::Asia::Tokyo,
+            ..Settings::default()
+        };
+        chats.set_settings(1, settings).unwrap();
+        let cal = chats.cal(1).unwrap().get_cal();
+        assert_eq!(
+            cal.get(holiday).unwrap().interval,
+            Interval {
+                start: Utc.ymd(2018, 12, 31).and_hms(15, 0, 0),
+                end: Utc.ymd(2019, 1, 1).and_hms(15, 0, 0),
+            }
+        );
+        assert_eq!(cal.get(lunch), Some(&event()));
+    }
+}
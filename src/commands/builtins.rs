@@ -0,0 +1,2193 @@
+//! The commands the bot comes with.
+
+use crate::cal;
+use crate::cal::interval::Interval;
+use crate::commands::reply_text;
+use crate::commands::reply_with;
+use crate::commands::Button;
+use crate::commands::Command;
+use crate::commands::Context;
+use crate::commands::Reply;
+use crate::commands::ReplyFuture;
+use crate::commands::Router;
+use crate::local_time;
+use crate::local_time::LocalTimeError;
+use crate::reminders::MAX_REMIND_MINUTES;
+use crate::tg;
+use crate::when;
+use crate::when::ParseError;
+
+use std::cmp;
+use std::io;
+use std::ops::Range;
+
+use chrono::DateTime;
+use chrono::Datelike;
+use chrono::Duration;
+use chrono::NaiveDate;
+use chrono::NaiveTime;
+use chrono::TimeZone;
+use chrono::Utc;
+use chrono::Weekday;
+use chrono_tz::Tz;
+
+/// Registers the commands the bot comes with.
+pub fn register(router: &mut Router) {
+    router.register(Echo);
+    router.register(AddEvent);
+    router.register(Today);
+    router.register(Tomorrow);
+    router.register(Week);
+    router.register(Month);
+    router.register(Agenda);
+    router.register(Free);
+    router.register(FindTime);
+    router.register(Timezone);
+    router.register(DoubleBooking);
+    router.register(Reminders);
+    router.register(Digest);
+    router.register(Export);
+    router.register(Import);
+}
+
+pub struct Echo;
+
+impl Command for Echo {
+    type Args = String;
+
+    fn name(&self) -> &'static str {
+        "echo"
+    }
+
+    fn description(&self) -> &'static str {
+        "Repeats what you say"
+    }
+
+    fn usage(&self) -> &'static str {
+        "<text>"
+    }
+
+    fn parse_args(&self, text: &str, _ctx: &Context) -> Result<String, String> {
+        if text.is_empty() {
+            return Err(String::from("Say something after /echo"));
+        }
+        Ok(String::from(text))
+    }
+
+    fn run(&self, text: String, _ctx: &mut Context) -> ReplyFuture {
+        reply_text(text)
+    }
+}
+
+pub struct AddEvent;
+
+impl AddEvent {
+    /// Adds an event unless it overlaps others and the chat rejects
+    /// overlaps. If it overlaps others, the reply says which, and has
+    /// buttons to keep or cancel it.
+    fn add(&self, event: cal::Event, ctx: &mut Context) -> io::Result<Reply> {
+        let zone = ctx.timezone;
+        let chat_id = ctx.chat_id();
+        let reject = ctx.chats.settings(chat_id)?.reject_overlaps;
+        let cal = ctx.chats.cal(chat_id)?;
+
+        let conflicts: Vec<_> = cal
+            .get_cal()
+            .conflicts(&event)
+            .iter()
+            .map(|x| {
+                format!(
+                    "{}, {}",
+                    x.event.description,
+                    format_slot(x.interval, &zone)
+                )
+            })
+            .collect();
+        if conflicts.is_empty() {
+            cal.add_event(event)?;
+            return Ok(Reply::Text(String::from("Added event successfully")));
+        }
+        if reject {
+            return Ok(Reply::Text(format!(
+                "Didn't add the event, since it overlaps with:\n{}",
+                conflicts.join("\n")
+            )));
+        }
+
+        let id = cal.add_event(event)?;
+        let keep = Button {
+            label: String::from("Keep it"),
+            data: format!("keep {}", id),
+        };
+        let cancel = Button {
+            label: String::from("Cancel it"),
+            data: format!("cancel {}", id),
+        };
+        Ok(Reply::Buttons(
+            format!(
+                "Added the event, but it overlaps with:\n{}",
+                conflicts.join("\n")
+            ),
+            vec![keep, cancel],
+        ))
+    }
+
+    /// Keeps or cancels an event that was added despite overlapping
+    /// others.
+    fn resolve(&self, cancel: bool, id: cal::EventId, ctx: &mut Context) -> io::Result<Reply> {
+        let chat_id = ctx.chat_id();
+        let cal = ctx.chats.cal(chat_id)?;
+        let description = match cal.get_cal().get(id) {
+            Some(event) => event.description.clone(),
+            None => return Ok(Reply::Text(String::from("That event is already gone"))),
+        };
+        if cancel {
+            cal.remove_event(id)?;
+            Ok(Reply::Text(format!("Cancelled {}", description)))
+        } else {
+            Ok(Reply::Text(format!("Kept {}", description)))
+        }
+    }
+}
+
+impl Command for AddEvent {
+    type Args = cal::Event;
+
+    fn name(&self) -> &'static str {
+        "add_event"
+    }
+
+    fn description(&self) -> &'static str {
+        "Adds an event, like /add_event tomorrow 3pm lunch"
+    }
+
+    fn usage(&self) -> &'static str {
+        "<when> <what> [remind <length> before]"
+    }
+
+    fn parse_args(&self, text: &str, ctx: &Context) -> Result<cal::Event, String> {
+        let mut event =
+            parse_event(text, &ctx.timezone, ctx.now).map_err(|x| parse_error_reply(&x))?;
+        let (description, lead) = match split_reminder(&event.description) {
+            Some((description, lead)) => (String::from(description), lead),
+            None => return Ok(event),
+        };
+        event.description = description;
+        event.remind_minutes_before = Some(reminder_minutes(lead)?);
+        Ok(event)
+    }
+
+    fn run(&self, event: cal::Event, ctx: &mut Context) -> ReplyFuture {
+        reply_with(self.add(event, ctx))
+    }
+
+    fn on_button(&self, data: &str, ctx: &mut Context) -> ReplyFuture {
+        let mut words = data.split(' ');
+        let action = words.next();
+        match (action, words.next().and_then(|x| x.parse().ok())) {
+            (Some("keep"), Some(id)) => reply_with(self.resolve(false, id, ctx)),
+            (Some("cancel"), Some(id)) => reply_with(self.resolve(true, id, ctx)),
+            _ => reply_text(String::from("That button doesn't do anything anymore")),
+        }
+    }
+}
+
+pub struct Today;
+
+impl Command for Today {
+    type Args = ();
+
+    fn name(&self) -> &'static str {
+        "today"
+    }
+
+    fn description(&self) -> &'static str {
+        "Lists today's events"
+    }
+
+    fn parse_args(&self, _text: &str, _ctx: &Context) -> Result<(), String> {
+        Ok(())
+    }
+
+    fn run(&self, _args: (), ctx: &mut Context) -> ReplyFuture {
+        let zone = ctx.timezone;
+        let range = local_time::day_range(&zone, today(ctx));
+        let chat_id = ctx.chat_id();
+        reply_with(ctx.chats.cal(chat_id).map(|cal| {
+            let mut response = itertools::join(
+                cal.get_cal()
+                    .events_overlapping(range)
+                    .map(|x| pretty_print_event(&x, &zone)),
+                "\n\n",
+            );
+            if response.is_empty() {
+                response = String::from("No events today");
+            }
+            Reply::Text(response)
+        }))
+    }
+}
+
+pub struct Tomorrow;
+
+impl Command for Tomorrow {
+    type Args = ();
+
+    fn name(&self) -> &'static str {
+        "tomorrow"
+    }
+
+    fn description(&self) -> &'static str {
+        "Lists tomorrow's events"
+    }
+
+    fn parse_args(&self, _text: &str, _ctx: &Context) -> Result<(), String> {
+        Ok(())
+    }
+
+    fn run(&self, _args: (), ctx: &mut Context) -> ReplyFuture {
+        let tomorrow = today(ctx).succ();
+        list_days(ctx, tomorrow..tomorrow.succ(), "No events tomorrow")
+    }
+}
+
+pub struct Week;
+
+impl Command for Week {
+    type Args = ();
+
+    fn name(&self) -> &'static str {
+        "week"
+    }
+
+    fn description(&self) -> &'static str {
+        "Lists the events in the next 7 days"
+    }
+
+    fn parse_args(&self, _text: &str, _ctx: &Context) -> Result<(), String> {
+        Ok(())
+    }
+
+    fn run(&self, _args: (), ctx: &mut Context) -> ReplyFuture {
+        list_next_days(ctx, WEEK_DAYS)
+    }
+}
+
+pub struct Month;
+
+impl Command for Month {
+    type Args = ();
+
+    fn name(&self) -> &'static str {
+        "month"
+    }
+
+    fn description(&self) -> &'static str {
+        "Lists the events in the next 30 days"
+    }
+
+    fn parse_args(&self, _text: &str, _ctx: &Context) -> Result<(), String> {
+        Ok(())
+    }
+
+    fn run(&self, _args: (), ctx: &mut Context) -> ReplyFuture {
+        list_next_days(ctx, MONTH_DAYS)
+    }
+}
+
+pub struct Agenda;
+
+impl Command for Agenda {
+    /// How many days to list.
+    type Args = i64;
+
+    fn name(&self) -> &'static str {
+        "agenda"
+    }
+
+    fn description(&self) -> &'static str {
+        "Lists the events in the next few days, like /agenda 3"
+    }
+
+    fn usage(&self) -> &'static str {
+        "<days>"
+    }
+
+    fn parse_args(&self, text: &str, _ctx: &Context) -> Result<i64, String> {
+        let text = text.trim();
+        let digits = text
+            .find(|c: char| !c.is_ascii_digit())
+            .unwrap_or(text.len());
+        let unit = text[digits..].trim();
+        let days = match text[..digits].parse() {
+            Ok(days) if ["", "d", "day", "days"].contains(&unit) => days,
+            _ => return Err(String::from("Say how many days to list, like /agenda 3")),
+        };
+        if !(1..=MAX_AGENDA_DAYS).contains(&days) {
+            return Err(format!("Can list between 1 and {} days", MAX_AGENDA_DAYS));
+        }
+        Ok(days)
+    }
+
+    fn run(&self, days: i64, ctx: &mut Context) -> ReplyFuture {
+        list_next_days(ctx, days)
+    }
+}
+
+/// What to look for free time in.
+pub struct FreeQuery {
+    range: Range<DateTime<Utc>>,
+    /// How long a gap has to be to be listed.
+    min_length: Duration,
+}
+
+pub struct Free;
+
+impl Command for Free {
+    type Args = FreeQuery;
+
+    fn name(&self) -> &'static str {
+        "free"
+    }
+
+    fn description(&self) -> &'static str {
+        "Lists the free time in a day or between two times, like /free tomorrow 9:00-17:00"
+    }
+
+    fn usage(&self) -> &'static str {
+        "<when> [at least <length>]"
+    }
+
+    fn parse_args(&self, text: &str, ctx: &Context) -> Result<FreeQuery, String> {
+        let usage = |err: &dyn std::fmt::Display| {
+            format!(
+                "{}\n\nFor example:\n/free friday\n/free tomorrow 9:00-17:00 at least 30m",
+                err
+            )
+        };
+        let (range, rest) = parse_range(text, &ctx.timezone, ctx.now).map_err(|x| usage(&x))?;
+        let rest = rest.trim();
+        let min_length = if rest.is_empty() {
+            Duration::zero()
+        } else {
+            let length = rest
+                .get(..FREE_MIN_PREFIX.len())
+                .filter(|x| x.eq_ignore_ascii_case(FREE_MIN_PREFIX))
+                .and_then(|_| when::parse_duration(&rest[FREE_MIN_PREFIX.len()..]));
+            match length {
+                Some(length) => length,
+                None => {
+                    return Err(usage(&format!(
+                        "Expected a length like \"at least 30m\", not \"{}\"",
+                        rest
+                    )))
+                }
+            }
+        };
+        Ok(FreeQuery {
+            range: range,
+            min_length: min_length,
+        })
+    }
+
+    fn run(&self, query: FreeQuery, ctx: &mut Context) -> ReplyFuture {
+        let zone = ctx.timezone;
+        let min_length = query.min_length;
+        let chat_id = ctx.chat_id();
+        reply_with(ctx.chats.cal(chat_id).map(|cal| {
+            let first = query.range.start.with_timezone(&zone).date().naive_local();
+            let gaps: Vec<_> = cal
+                .get_cal()
+                .free_time_in(query.range)
+                .into_iter()
+                .filter(|x| x.end.signed_duration_since(x.start) >= min_length)
+                .map(|x| {
+                    format!(
+                        "{}-{}",
+                        format_time_on(x.start, &zone, first),
+                        format_time_on(x.end, &zone, first)
+                    )
+                })
+                .collect();
+            if gaps.is_empty() {
+                return Reply::Text(String::from("No free time then"));
+            }
+            Reply::Text(format!(
+                "Free on {}:\n{}",
+                first.format("%A %-m/%-d"),
+                gaps.join("\n")
+            ))
+        }))
+    }
+}
+
+/// What /find_time looks for.
+pub struct FindTimeQuery {
+    length: Duration,
+    /// The days to look in, in the chat's time zone.
+    days: Range<NaiveDate>,
+    /// Whether to skip Saturdays and Sundays.
+    weekdays_only: bool,
+    /// The times of day to look between. If the second is earlier, it's
+    /// on the next day.
+    hours: (NaiveTime, NaiveTime),
+}
+
+pub struct FindTime;
+
+impl FindTime {
+    /// Books the slot the button offered, unless it's been taken since.
+    fn book(&self, slot: Interval<DateTime<Utc>>, ctx: &mut Context) -> io::Result<Reply> {
+        let zone = ctx.timezone;
+        let chat_id = ctx.chat_id();
+        let cal = ctx.chats.cal(chat_id)?;
+        if slot.start < ctx.now {
+            return Ok(Reply::Text(String::from("That time has passed")));
+        }
+        if !cal.get_cal().busy_times_in(slot.start..slot.end).is_empty() {
+            return Ok(Reply::Text(String::from("That time isn't free anymore")));
+        }
+        cal.add_event(cal::Event {
+            organizer: String::new(),
+            description: String::from(BOOKED_DESCRIPTION),
+            interval: slot,
+            recurrence: None,
+            all_day: None,
+            remind_minutes_before: None,
+        })?;
+        Ok(Reply::Text(format!("Booked {}", format_slot(slot, &zone))))
+    }
+}
+
+impl Command for FindTime {
+    type Args = FindTimeQuery;
+
+    fn name(&self) -> &'static str {
+        "find_time"
+    }
+
+    fn description(&self) -> &'static str {
+        "Finds the earliest free time, like /find_time 45m this week weekdays 9-17"
+    }
+
+    fn usage(&self) -> &'static str {
+        "<length> [day|this week|next week] [weekdays] [hours]"
+    }
+
+    fn parse_args(&self, text: &str, ctx: &Context) -> Result<FindTimeQuery, String> {
+        let words: Vec<&str> = text.split_whitespace().collect();
+        let (length, mut ndx) = (1..=cmp::min(words.len(), 4))
+            .rev()
+            .filter_map(|len| when::parse_duration(&words[..len].join(" ")).map(|x| (x, len)))
+            .next()
+            .filter(|x| x.0 > Duration::zero())
+            .ok_or_else(|| {
+                String::from("Say how long to find time for, like /find_time 45m this week 9-17")
+            })?;
+
+        let today = today(ctx);
+        let mut query = FindTimeQuery {
+            length: length,
+            days: today..today + Duration::days(WEEK_DAYS),
+            weekdays_only: false,
+            hours: (
+                NaiveTime::from_hms(WORKDAY_HOURS.0, 0, 0),
+                NaiveTime::from_hms(WORKDAY_HOURS.1, 0, 0),
+            ),
+        };
+        while ndx < words.len() {
+            let word = words[ndx].to_ascii_lowercase();
+            let next = words.get(ndx + 1).map(|x| x.to_ascii_lowercase());
+            let monday = today - Duration::days(i64::from(today.weekday().num_days_from_monday()));
+            match (word.as_str(), next.as_ref().map(String::as_str)) {
+                ("weekdays", _) => query.weekdays_only = true,
+                ("this", Some("week")) => {
+                    query.days = today..monday + Duration::weeks(1);
+                    ndx += 1;
+                }
+                ("next", Some("week")) => {
+                    query.days = monday + Duration::weeks(1)..monday + Duration::weeks(2);
+                    ndx += 1;
+                }
+                _ => match when::parse_time_range(&word) {
+                    Some(hours) => query.hours = hours,
+                    None => {
+                        let (date, len) = parse_day(&words[ndx..], ctx).ok_or_else(|| {
+                            format!(
+                                "Expected a day, \"this week\", \"next week\", \"weekdays\" \
+                                 or hours like 9-17, not \"{}\"",
+                                words[ndx]
+                            )
+                        })?;
+                        query.days = date..date.succ();
+                        ndx += len - 1;
+                    }
+                },
+            }
+            ndx += 1;
+        }
+        Ok(query)
+    }
+
+    fn run(&self, query: FindTimeQuery, ctx: &mut Context) -> ReplyFuture {
+        let zone = ctx.timezone;
+        let now = ctx.now;
+        let (open, close) = query.hours;
+        let mut proposed = Vec::new();
+        let mut date = query.days.start;
+        while date < query.days.end {
+            let weekend = date.weekday() == Weekday::Sat || date.weekday() == Weekday::Sun;
+            let close_date = if close <= open { date.succ() } else { date };
+            let start = local_time::to_utc(&zone, date.and_time(open));
+            let end = local_time::to_utc(&zone, close_date.and_time(close));
+            match (start, end) {
+                (Ok(start), Ok(end)) if end > now && !(query.weekdays_only && weekend) => {
+                    proposed.push(Interval {
+                        start: cmp::max(start, now),
+                        end: end,
+                    });
+                }
+                _ => (),
+            }
+            date = date.succ();
+        }
+
+        let length = query.length;
+        let chat_id = ctx.chat_id();
+        reply_with(ctx.chats.cal(chat_id).map(|cal| {
+            let range = match (proposed.first(), proposed.last()) {
+                (Some(first), Some(last)) => first.start..last.end,
+                _ => return Reply::Text(format!("No free {} then", format_length(length))),
+            };
+            let slot = match cal.get_cal().find_time(proposed.into_iter(), range, length) {
+                Some(free) => Interval {
+                    start: free.start,
+                    end: free.start + length,
+                },
+                None => return Reply::Text(format!("No free {} then", format_length(length))),
+            };
+            let book = Button {
+                label: String::from("Book it"),
+                data: format!("{} {}", slot.start.timestamp(), slot.end.timestamp()),
+            };
+            Reply::Buttons(
+                format!(
+                    "The earliest free {} is {}",
+                    format_length(length),
+                    format_slot(slot, &zone)
+                ),
+                vec![book],
+            )
+        }))
+    }
+
+    fn on_button(&self, data: &str, ctx: &mut Context) -> ReplyFuture {
+        // The data comes from the client, so it may not be a slot this
+        // command offered.
+        let times: Option<Vec<DateTime<Utc>>> = data
+            .split(' ')
+            .map(|x| {
+                x.parse()
+                    .ok()
+                    .and_then(|x| Utc.timestamp_opt(x, 0).single())
+            })
+            .collect();
+        match times.as_deref() {
+            Some(&[start, end]) if start < end && end - start <= Duration::days(MAX_EVENT_DAYS) => {
+                let slot = Interval {
+                    start: start,
+                    end: end,
+                };
+                reply_with(self.book(slot, ctx))
+            }
+            _ => reply_text(String::from("That button doesn't do anything anymore")),
+        }
+    }
+}
+
+pub struct Timezone;
+
+impl Timezone {
+    fn set(&self, zone: Tz, ctx: &mut Context) -> io::Result<Reply> {
+        let chat_id = ctx.chat_id();
+        let mut settings = ctx.chats.settings(chat_id)?.clone();
+        settings.timezone = zone;
+        ctx.chats.set_settings(chat_id, settings)?;
+        Ok(Reply::Text(format!("Time zone set to {}", zone.name())))
+    }
+}
+
+impl Command for Timezone {
+    /// The zone to change to, or `None` to show the current one.
+    type Args = Option<Tz>;
+
+    fn name(&self) -> &'static str {
+        "timezone"
+    }
+
+    fn description(&self) -> &'static str {
+        "Shows or sets the chat's time zone, like /timezone Europe/Berlin"
+    }
+
+    fn usage(&self) -> &'static str {
+        "[zone]"
+    }
+
+    fn parse_args(&self, text: &str, _ctx: &Context) -> Result<Option<Tz>, String> {
+        let name = text.trim();
+        if name.is_empty() {
+            return Ok(None);
+        }
+        name.parse().map(Some).map_err(|_| {
+            format!(
+                "Unknown time zone {}. Use a name from the IANA time zone database, like America/Los_Angeles",
+                name
+            )
+        })
+    }
+
+    fn run(&self, zone: Option<Tz>, ctx: &mut Context) -> ReplyFuture {
+        match zone {
+            Some(zone) => reply_with(self.set(zone, ctx)),
+            None => reply_text(format!("This chat's time zone is {}", ctx.timezone.name())),
+        }
+    }
+}
+
+pub struct DoubleBooking;
+
+impl DoubleBooking {
+    fn set(&self, reject: bool, ctx: &mut Context) -> io::Result<Reply> {
+        let chat_id = ctx.chat_id();
+        let mut settings = ctx.chats.settings(chat_id)?.clone();
+        settings.reject_overlaps = reject;
+        ctx.chats.set_settings(chat_id, settings)?;
+        Ok(Reply::Text(String::from(if reject {
+            "Events that overlap others will be rejected"
+        } else {
+            "Events may overlap others"
+        })))
+    }
+}
+
+impl Command for DoubleBooking {
+    /// Whether to reject overlapping events, or `None` to show the
+    /// current setting.
+    type Args = Option<bool>;
+
+    fn name(&self) -> &'static str {
+        "double_booking"
+    }
+
+    fn description(&self) -> &'static str {
+        "Shows or sets whether events may overlap, like /double_booking reject"
+    }
+
+    fn usage(&self) -> &'static str {
+        "[allow|reject]"
+    }
+
+    fn parse_args(&self, text: &str, _ctx: &Context) -> Result<Option<bool>, String> {
+        match text.trim().to_ascii_lowercase().as_str() {
+            "" => Ok(None),
+            "allow" => Ok(Some(false)),
+            "reject" => Ok(Some(true)),
+            _ => Err(String::from(
+                "Say \"allow\" to let events overlap, or \"reject\" to refuse ones that do",
+            )),
+        }
+    }
+
+    fn run(&self, reject: Option<bool>, ctx: &mut Context) -> ReplyFuture {
+        match reject {
+            Some(reject) => reply_with(self.set(reject, ctx)),
+            None => {
+                let chat_id = ctx.chat_id();
+                reply_with(ctx.chats.settings(chat_id).map(|x| {
+                    Reply::Text(String::from(if x.reject_overlaps {
+                        "Events that overlap others are rejected"
+                    } else {
+                        "Events may overlap others"
+                    }))
+                }))
+            }
+        }
+    }
+}
+
+pub struct Reminders;
+
+impl Reminders {
+    fn set(&self, minutes: Option<u32>, ctx: &mut Context) -> io::Result<Reply> {
+        let chat_id = ctx.chat_id();
+        let mut settings = ctx.chats.settings(chat_id)?.clone();
+        settings.remind_minutes_before = minutes;
+        ctx.chats.set_settings(chat_id, settings)?;
+        Ok(Reply::Text(match minutes {
+            Some(minutes) => format!(
+                "Will remind {} before events",
+                format_length(Duration::minutes(i64::from(minutes)))
+            ),
+            None => String::from("Will only remind of events that ask"),
+        }))
+    }
+}
+
+impl Command for Reminders {
+    /// The minutes before events to remind, `Some(None)` to stop, or
+    /// `None` to show the current setting.
+    type Args = Option<Option<u32>>;
+
+    fn name(&self) -> &'static str {
+        "reminders"
+    }
+
+    fn description(&self) -> &'static str {
+        "Shows or sets how long before events to remind the chat, like /reminders 10m"
+    }
+
+    fn usage(&self) -> &'static str {
+        "[<length>|off]"
+    }
+
+    fn parse_args(&self, text: &str, _ctx: &Context) -> Result<Option<Option<u32>>, String> {
+        let text = text.trim();
+        if text.is_empty() {
+            return Ok(None);
+        }
+        if text.eq_ignore_ascii_case("off") {
+            return Ok(Some(None));
+        }
+        let lead = when::parse_duration(text).ok_or_else(|| {
+            String::from(
+                "Say how long before events to remind, like /reminders 10m, or /reminders off",
+            )
+        })?;
+        Ok(Some(Some(reminder_minutes(lead)?)))
+    }
+
+    fn run(&self, minutes: Option<Option<u32>>, ctx: &mut Context) -> ReplyFuture {
+        match minutes {
+            Some(minutes) => reply_with(self.set(minutes, ctx)),
+            None => {
+                let chat_id = ctx.chat_id();
+                reply_with(ctx.chats.settings(chat_id).map(|x| {
+                    Reply::Text(match x.remind_minutes_before {
+                        Some(minutes) => format!(
+                            "Reminding {} before events",
+                            format_length(Duration::minutes(i64::from(minutes)))
+                        ),
+                        None => String::from("Only reminding of events that ask"),
+                    })
+                }))
+            }
+        }
+    }
+}
+
+pub struct Digest;
+
+impl Digest {
+    fn set(&self, time: Option<NaiveTime>, ctx: &mut Context) -> io::Result<Reply> {
+        let chat_id = ctx.chat_id();
+        let mut settings = ctx.chats.settings(chat_id)?.clone();
+        settings.digest = time;
+        ctx.chats.set_settings(chat_id, settings)?;
+        Ok(Reply::Text(match time {
+            Some(time) => {
+                // Today's digest waits for tomorrow if its time has
+                // passed, rather than being posted straight away.
+                let local = ctx.now.with_timezone(&ctx.timezone).naive_local();
+                if local.time() >= time {
+                    ctx.chats.digest_sent(chat_id, local.date())?;
+                }
+                format!(
+                    "Will post the day's agenda at {} every day",
+                    time.format("%H:%M")
+                )
+            }
+            None => String::from("Won't post a daily agenda"),
+        }))
+    }
+}
+
+impl Command for Digest {
+    /// The local time to post the agenda at, `Some(None)` to stop, or
+    /// `None` to show the current setting.
+    type Args = Option<Option<NaiveTime>>;
+
+    fn name(&self) -> &'static str {
+        "digest"
+    }
+
+    fn description(&self) -> &'static str {
+        "Shows or sets when to post the day's agenda, like /digest on 8:00"
+    }
+
+    fn usage(&self) -> &'static str {
+        "[on <time>|off]"
+    }
+
+    fn parse_args(&self, text: &str, _ctx: &Context) -> Result<Option<Option<NaiveTime>>, String> {
+        let text = text.trim();
+        if text.is_empty() {
+            return Ok(None);
+        }
+        if text.eq_ignore_ascii_case("off") {
+            return Ok(Some(None));
+        }
+        let usage = || {
+            String::from("Say when to post the day's agenda, like /digest on 8:00, or /digest off")
+        };
+        let mut words = text.splitn(2, char::is_whitespace);
+        match words.next() {
+            Some(on) if on.eq_ignore_ascii_case("on") => (),
+            _ => return Err(usage()),
+        }
+        let time = words.next().and_then(when::parse_time).ok_or_else(usage)?;
+        Ok(Some(Some(time)))
+    }
+
+    fn run(&self, time: Option<Option<NaiveTime>>, ctx: &mut Context) -> ReplyFuture {
+        match time {
+            Some(time) => reply_with(self.set(time, ctx)),
+            None => {
+                let chat_id = ctx.chat_id();
+                reply_with(ctx.chats.settings(chat_id).map(|x| {
+                    Reply::Text(match x.digest {
+                        Some(time) => {
+                            format!("Posting the day's agenda at {}", time.format("%H:%M"))
+                        }
+                        None => String::from("Not posting a daily agenda"),
+                    })
+                }))
+            }
+        }
+    }
+}
+
+pub struct Export;
+
+impl Command for Export {
+    type Args = ();
+
+    fn name(&self) -> &'static str {
+        "export"
+    }
+
+    fn description(&self) -> &'static str {
+        "Sends the calendar as an .ics file"
+    }
+
+    fn parse_args(&self, _text: &str, _ctx: &Context) -> Result<(), String> {
+        Ok(())
+    }
+
+    fn run(&self, _args: (), ctx: &mut Context) -> ReplyFuture {
+        // Event IDs are only unique within a chat.
+        let chat_id = ctx.chat_id();
+        let uid_domain = format!("chat{}.{}", chat_id, ICS_UID_DOMAIN);
+        let now = ctx.now;
+        reply_with(ctx.chats.cal(chat_id).map(|cal| {
+            let ics = cal::ics::write_calendar(cal.get_cal(), &uid_domain, now);
+            Reply::Document(tg::InputFile {
+                file_name: String::from("calendar.ics"),
+                contents: ics.into_bytes(),
+            })
+        }))
+    }
+}
+
+pub struct Import;
+
+impl Import {
+    fn import(&self, file_id: &str, ctx: &mut Context) -> io::Result<Reply> {
+        let contents = match (ctx.download)(file_id) {
+            Some(contents) => contents,
+            None => return Ok(Reply::Text(String::from("Couldn't download the file"))),
+        };
+        let cal = ctx.chats.cal(ctx.message.chat.id)?;
+        let (ids, skipped) = cal::ics::import(cal, &contents, &ctx.timezone)?;
+        Ok(Reply::Text(format_import_report(ids.len(), &skipped)))
+    }
+}
+
+impl Command for Import {
+    /// The ID of the file to import.
+    type Args = String;
+
+    fn name(&self) -> &'static str {
+        "import"
+    }
+
+    fn description(&self) -> &'static str {
+        "Adds the events in an .ics file sent with it or replied to"
+    }
+
+    fn parse_args(&self, _text: &str, ctx: &Context) -> Result<String, String> {
+        let message = ctx.message;
+        message
+            .document
+            .as_ref()
+            .or_else(|| {
+                message
+                    .reply_to_message
+                    .as_ref()
+                    .and_then(|x| x.document.as_ref())
+            })
+            .map(|x| x.file_id.clone())
+            .ok_or_else(|| {
+                String::from(
+                    "Send an .ics file with /import as its caption, or reply to one with /import",
+                )
+            })
+    }
+
+    fn run(&self, file_id: String, ctx: &mut Context) -> ReplyFuture {
+        reply_with(self.import(&file_id, ctx))
+    }
+}
+
+/// Parses out a date expression (see `when`) and an event description
+/// from the message body. Times are local to `zone`, and relative
+/// dates are resolved against `now`.
+fn parse_event(
+    text: &str,
+    zone: &chrono_tz::Tz,
+    now: DateTime<Utc>,
+) -> Result<cal::Event, ParseError> {
+    use chrono::Duration;
+
+    let (when, description) = when::parse(text, now.with_timezone(zone).naive_local())?;
+    // Errors past this point are about the expression as a whole.
+    let used = text[..text.len() - description.len()].trim_end();
+    let expression = when::Span::new(text, used.len() - used.trim_start().len()..used.len());
+
+    let (start, end, all_day) = if when.all_day {
+        let days = match when.length {
+            None => 1,
+            Some(when::Length::For(duration)) if duration.num_seconds() % (24 * 3600) == 0 => {
+                duration.num_days()
+            }
+            Some(_) => return Err(ParseError::PartialDays(expression)),
+        };
+        let end = Some(days)
+            .filter(|x| *x <= MAX_EVENT_DAYS)
+            .and_then(|x| when.date.checked_add_signed(Duration::days(x)))
+            .ok_or_else(|| ParseError::OutOfRange(expression.clone()))?;
+        let all_day = cal::AllDay {
+            start: when.date,
+            end: end,
+            zone: *zone,
+        };
+        let interval = all_day.interval();
+        (interval.start, interval.end, Some(all_day))
+    } else {
+        let time = match when.time {
+            Some(time) => time,
+            None => return Err(ParseError::MissingTime(expression)),
+        };
+        let to_utc = |local| {
+            local_time::to_utc(zone, local).map_err(|err| match err {
+                LocalTimeError::Ambiguous => ParseError::AmbiguousTime(expression.clone()),
+                LocalTimeError::Nonexistent => ParseError::NonexistentTime(expression.clone()),
+            })
+        };
+        let start = to_utc(when.date.and_time(time))?;
+        let end = match when.length {
+            None => start + Duration::hours(DEFAULT_EVENT_HOURS),
+            Some(when::Length::For(duration)) => end_after(start, duration, &expression)?,
+            Some(when::Length::Until(end)) => to_utc(end)?,
+        };
+        (start, end, None)
+    };
+    if end <= start {
+        return Err(ParseError::EndsBeforeStart(expression));
+    }
+
+    Ok(cal::Event {
+        organizer: String::new(),
+        description: String::from(description),
+        interval: Interval {
+            start: start,
+            end: end,
+        },
+        recurrence: None,
+        all_day: all_day,
+        remind_minutes_before: None,
+    })
+}
+
+/// Splits a request for a reminder, like "remind 10m before", off the
+/// end of an event's description, returning the rest of it and how
+/// long before the event to remind.
+fn split_reminder(description: &str) -> Option<(&str, Duration)> {
+    // Lowercasing ASCII keeps byte offsets the same.
+    let lower = description.to_ascii_lowercase();
+    let request = lower.trim_end().strip_suffix(REMIND_SUFFIX)?;
+    let ndx = request.rfind(REMIND_PREFIX)?;
+    if ndx > 0 && !request[..ndx].ends_with(' ') {
+        return None;
+    }
+    let lead = when::parse_duration(&request[ndx + REMIND_PREFIX.len()..])?;
+    Some((description[..ndx].trim_end(), lead))
+}
+
+/// Checks that a reminder's lead time is one the bot can keep to, and
+/// gets it in minutes.
+fn reminder_minutes(lead: Duration) -> Result<u32, String> {
+    let max = Duration::minutes(i64::from(MAX_REMIND_MINUTES));
+    if lead <= Duration::zero() || lead > max {
+        return Err(format!(
+            "Can remind between 1m and {} before events",
+            format_length(max)
+        ));
+    }
+    Ok(lead.num_minutes() as u32)
+}
+
+/// Parses the times to look in at the start of `text`: a whole day,
+/// like "friday", or a time and length, like "tomorrow 9:00-17:00". A
+/// time without a length lasts until the end of its day. Returns the
+/// range along with the rest of `text`.
+fn parse_range<'a>(
+    text: &'a str,
+    zone: &Tz,
+    now: DateTime<Utc>,
+) -> Result<(Range<DateTime<Utc>>, &'a str), ParseError> {
+    let (when, rest) = when::parse(text, now.with_timezone(zone).naive_local())?;
+    let used = text[..text.len() - rest.len()].trim_end();
+    let expression = when::Span::new(text, used.len() - used.trim_start().len()..used.len());
+
+    let to_utc = |local| {
+        local_time::to_utc(zone, local).map_err(|err| match err {
+            LocalTimeError::Ambiguous => ParseError::AmbiguousTime(expression.clone()),
+            LocalTimeError::Nonexistent => ParseError::NonexistentTime(expression.clone()),
+        })
+    };
+    let start = match when.time {
+        Some(time) => to_utc(when.date.and_time(time))?,
+        None => local_time::day_start(zone, when.date),
+    };
+    let end = match when.length {
+        None => local_time::day_start(zone, when.date.succ()),
+        Some(when::Length::For(duration)) => end_after(start, duration, &expression)?,
+        Some(when::Length::Until(end)) => to_utc(end)?,
+    };
+    if end <= start {
+        return Err(ParseError::EndsBeforeStart(expression));
+    }
+    Ok((start..end, rest))
+}
+
+/// Gets the end of something that starts at `start` and lasts
+/// `length`, as given in `expression`. Lengths over `MAX_EVENT_DAYS`
+/// are out of range.
+fn end_after(
+    start: DateTime<Utc>,
+    length: Duration,
+    expression: &when::Span,
+) -> Result<DateTime<Utc>, ParseError> {
+    if length > Duration::days(MAX_EVENT_DAYS) {
+        return Err(ParseError::OutOfRange(expression.clone()));
+    }
+    start
+        .checked_add_signed(length)
+        .ok_or_else(|| ParseError::OutOfRange(expression.clone()))
+}
+
+/// Explains why an /add_event couldn't be understood, with an example
+/// of how to write it instead.
+fn parse_error_reply(err: &ParseError) -> String {
+    let example = match *err {
+        ParseError::MissingWhen(_) | ParseError::NoSuchDate(_) => {
+            "/add_event oct 20 3pm lunch with Sam"
+        }
+        ParseError::BadTime(_) | ParseError::TimeOutOfRange(_) => {
+            "/add_event tomorrow 3:30pm coffee\n/add_event tomorrow 15:30 coffee"
+        }
+        ParseError::MissingTime(_) => {
+            "/add_event friday 9am-5pm offsite\n/add_event friday all day offsite"
+        }
+        ParseError::AmbiguousTime(_) | ParseError::NonexistentTime(_) => {
+            "/add_event sunday 10am for 2h brunch"
+        }
+        ParseError::PartialDays(_) => "/add_event friday all day for 3 days trip",
+        ParseError::EndsBeforeStart(_) => "/add_event tomorrow 3pm-4:30pm review",
+        ParseError::OutOfRange(_) => "/add_event in 2 weeks 3pm review",
+    };
+    format!("{}\n\nFor example:\n{}", err, example)
+}
+
+/// Gets the date in the chat when the message arrived.
+fn today(ctx: &Context) -> NaiveDate {
+    ctx.now.with_timezone(&ctx.timezone).date().naive_local()
+}
+
+/// Replies with the events in `days` days starting today.
+fn list_next_days(ctx: &mut Context, days: i64) -> ReplyFuture {
+    let today = today(ctx);
+    let empty = if days == 1 {
+        String::from("No events today")
+    } else {
+        format!("No events in the next {} days", days)
+    };
+    list_days(ctx, today..today + Duration::days(days), &empty)
+}
+
+/// Replies with the events on `days` in the chat's time zone, grouped
+/// by day, or with `empty` if there are none.
+fn list_days(ctx: &mut Context, days: Range<NaiveDate>, empty: &str) -> ReplyFuture {
+    let zone = ctx.timezone;
+    let range = Range {
+        start: local_time::day_start(&zone, days.start),
+        end: local_time::day_start(&zone, days.end),
+    };
+    let chat_id = ctx.chat_id();
+    reply_with(ctx.chats.cal(chat_id).map(|cal| {
+        let mut response = format_days(cal.get_cal().events_overlapping(range), &zone, days.start);
+        if response.is_empty() {
+            response = String::from(empty);
+        }
+        Reply::Text(response)
+    }))
+}
+
+/// Lists occurrences under a heading for the day each starts on. Ones
+/// that started before `first` are listed under it. The days are
+/// separated by blank lines, so long lists split between days.
+pub fn format_days<'a, I>(occurrences: I, zone: &Tz, first: NaiveDate) -> String
+where
+    I: Iterator<Item = cal::Occurrence<'a>>,
+{
+    let mut result = String::new();
+    let mut current = None;
+    for occurrence in occurrences {
+        let start = match occurrence.days() {
+            Some(days) => days.start,
+            None => occurrence
+                .interval
+                .start
+                .with_timezone(zone)
+                .date()
+                .naive_local(),
+        };
+        let day = cmp::max(start, first);
+        if current != Some(day) {
+            if current.is_some() {
+                result.push_str("\n\n");
+            }
+            result.push_str(&day.format("%A %-m/%-d").to_string());
+            current = Some(day);
+        }
+        result.push('\n');
+        result.push_str(&format_day_entry(&occurrence, zone, day));
+    }
+    result
+}
+
+/// Describes an occurrence listed under `day`. Times on other days
+/// are shown with their date.
+fn format_day_entry(occurrence: &cal::Occurrence, zone: &Tz, day: NaiveDate) -> String {
+    let when = match occurrence.days() {
+        Some(days) if days.last() == days.start => String::from("All day"),
+        Some(days) => format!(
+            "All day, {} to {}",
+            days.start.format("%-m/%-d"),
+            days.last().format("%-m/%-d")
+        ),
+        None => {
+            let format_time = |time| format_time_on(time, zone, day);
+            let interval = occurrence.interval;
+            if interval.start == interval.end {
+                format_time(interval.start)
+            } else {
+                format!(
+                    "{}-{}",
+                    format_time(interval.start),
+                    format_time(interval.end)
+                )
+            }
+        }
+    };
+    format!("{}: {}", when, occurrence.event.description)
+}
+
+fn pretty_print_event(occurrence: &cal::Occurrence, zone: &chrono_tz::Tz) -> String {
+    let mut result = String::new();
+    match occurrence.days() {
+        Some(days) if days.last() == days.start => {
+            result.push_str(&days.start.format("On %-m/%-d/%Y, all day").to_string());
+        }
+        Some(days) => {
+            result.push_str(&days.start.format("From %-m/%-d/%Y").to_string());
+            result.push_str(&days.last().format(" to %-m/%-d/%Y, all day").to_string());
+        }
+        None => {
+            result.push_str("On ");
+            result.push_str(
+                &occurrence
+                    .interval
+                    .start
+                    .with_timezone(zone)
+                    .format("%-m/%-d/%Y at %H:%M:%S")
+                    .to_string(),
+            );
+        }
+    }
+    result.push_str(":\n");
+    result.push_str(&occurrence.event.description);
+    result
+}
+
+/// Parses a day like "friday" or "oct 20" at the start of `words`,
+/// returning it and how many words it took.
+fn parse_day(words: &[&str], ctx: &Context) -> Option<(NaiveDate, usize)> {
+    let text = words.join(" ");
+    let now = ctx.now.with_timezone(&ctx.timezone).naive_local();
+    let (when, rest) = when::parse(&text, now).ok()?;
+    if when.time.is_some() || when.length.is_some() || when.all_day {
+        return None;
+    }
+    Some((when.date, words.len() - rest.split_whitespace().count()))
+}
+
+/// Describes a time slot, like "Thursday 10/17 12:00-12:45".
+fn format_slot(slot: Interval<DateTime<Utc>>, zone: &Tz) -> String {
+    let day = slot.start.with_timezone(zone).date().naive_local();
+    format!(
+        "{} {}-{}",
+        day.format("%A %-m/%-d"),
+        format_time_on(slot.start, zone, day),
+        format_time_on(slot.end, zone, day)
+    )
+}
+
+/// Writes a length like "45m" or "1h30m".
+fn format_length(length: Duration) -> String {
+    let minutes = length.num_minutes();
+    match (minutes / 60, minutes % 60) {
+        (0, minutes) => format!("{}m", minutes),
+        (hours, 0) => format!("{}h", hours),
+        (hours, minutes) => format!("{}h{}m", hours, minutes),
+    }
+}
+
+/// Formats a time for a list under `day`, with its date if it's on
+/// another day.
+fn format_time_on(time: DateTime<Utc>, zone: &Tz, day: NaiveDate) -> String {
+    let local = time.with_timezone(zone);
+    if local.date().naive_local() == day {
+        local.format("%H:%M").to_string()
+    } else {
+        local.format("%-m/%-d %H:%M").to_string()
+    }
+}
+
+/// Describes the result of an /import, listing at most
+/// `MAX_SKIPPED_SHOWN` of the skipped components.
+fn format_import_report(imported: usize, skipped: &[cal::ics::Skipped]) -> String {
+    let mut result = format!("Imported {} events", imported);
+    if !skipped.is_empty() {
+        result.push_str(&format!("\n\nSkipped {}:", skipped.len()));
+        for s in skipped.iter().take(MAX_SKIPPED_SHOWN) {
+            result.push('\n');
+            result.push_str(&s.to_string());
+        }
+        if skipped.len() > MAX_SKIPPED_SHOWN {
+            result.push_str(&format!(
+                "\n...and {} more",
+                skipped.len() - MAX_SKIPPED_SHOWN
+            ));
+        }
+    }
+    result
+}
+
+/// Makes exported events' UIDs globally unique.
+const ICS_UID_DOMAIN: &'static str = "calendar-bot";
+
+/// How long events last if no length is given.
+const DEFAULT_EVENT_HOURS: i64 = 1;
+
+/// The longest an event can be given a length for.
+const MAX_EVENT_DAYS: i64 = 10 * 366;
+
+const WEEK_DAYS: i64 = 7;
+
+const MONTH_DAYS: i64 = 30;
+
+/// Keeps /agenda from reading through years of recurrences.
+const MAX_AGENDA_DAYS: i64 = 366;
+
+/// Comes before the shortest gap /free should list.
+const FREE_MIN_PREFIX: &'static str = "at least ";
+
+/// The hours /find_time looks in if none are given.
+const WORKDAY_HOURS: (u32, u32) = (9, 17);
+
+/// What times booked with /find_time are called.
+const BOOKED_DESCRIPTION: &'static str = "Meeting";
+
+/// Asks for a reminder at the end of an event's description, as in
+/// "lunch remind 10m before".
+const REMIND_PREFIX: &'static str = "remind ";
+
+const REMIND_SUFFIX: &'static str = " before";
+
+/// Keeps /import replies from growing too long for one message.
+const MAX_SKIPPED_SHOWN: usize = 10;
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::chats::Chats;
+
+    use chrono::NaiveDate;
+    use chrono::TimeZone;
+    use chrono_tz::America::Los_Angeles;
+    use futures::Future;
+
+    extern crate tempfile;
+
+    /// Wednesday, October 16th 2019 at 10:00 in Los Angeles.
+    fn now() -> DateTime<Utc> {
+        Utc.ymd(2019, 10, 16).and_hms(17, 0, 0)
+    }
+
+    fn span(start: usize, text: &str) -> when::Span {
+        when::Span {
+            start: start,
+            text: String::from(text),
+        }
+    }
+
+    /// Sends `message` as a command at `now()`, with `download`
+    /// standing in for Telegram's file downloads.
+    fn send_with(
+        chats: &mut Chats,
+        message: tg::Message,
+        download: &dyn Fn(&str) -> Option<String>,
+    ) -> Option<Reply> {
+        let mut router = Router::new();
+        register(&mut router);
+
+        let text = message.text.clone().unwrap_or_default();
+        let (name, body) = match text.find(' ') {
+            Some(ndx) => (&text[1..ndx], &text[ndx + 1..]),
+            None => (&text[1..], ""),
+        };
+        let timezone = chats.settings(message.chat.id).unwrap().timezone;
+        let mut ctx = Context {
+            chats: chats,
+            message: &message,
+            now: now(),
+            timezone: timezone,
+            download: download,
+        };
+        router
+            .dispatch(name, body, &mut ctx)
+            .map(|x| x.wait().unwrap())
+    }
+
+    /// Presses a button with the given data, as made by the router.
+    fn press(chats: &mut Chats, data: &str) -> String {
+        let mut router = Router::new();
+        register(&mut router);
+
+        let message = message("");
+        let timezone = chats.settings(message.chat.id).unwrap().timezone;
+        let mut ctx = Context {
+            chats: chats,
+            message: &message,
+            now: now(),
+            timezone: timezone,
+            download: &|_| None,
+        };
+        match router
+            .dispatch_button(data, &mut ctx)
+            .map(|x| x.wait().unwrap())
+        {
+            Some(Reply::Text(text)) => text,
+            reply => panic!("unexpected reply {:?}", reply),
+        }
+    }
+
+    fn message(text: &str) -> tg::Message {
+        tg::Message {
+            chat: tg::Chat { id: 1 },
+            text: Some(String::from(text)),
+            ..tg::Message::default()
+        }
+    }
+
+    fn send(chats: &mut Chats, text: &str) -> String {
+        match send_with(chats, message(text), &|_| None) {
+            Some(Reply::Text(text)) => text,
+            reply => panic!("unexpected reply {:?}", reply),
+        }
+    }
+
+    #[test]
+    fn help_command() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut chats = Chats::new(dir.path()).unwrap();
+        assert_eq!(
+            send(&mut chats, "/help"),
+            "Commands:\n\
+             /echo <text> - Repeats what you say\n\
+             /add_event <when> <what> [remind <length> before] - Adds an event, like /add_event tomorrow 3pm lunch\n\
+             /today - Lists today's events\n\
+             /tomorrow - Lists tomorrow's events\n\
+             /week - Lists the events in the next 7 days\n\
+             /month - Lists the events in the next 30 days\n\
+             /agenda <days> - Lists the events in the next few days, like /agenda 3\n\
+             /free <when> [at least <length>] - Lists the free time in a day or between two times, like /free tomorrow 9:00-17:00\n\
+             /find_time <length> [day|this week|next week] [weekdays] [hours] - Finds the earliest free time, like /find_time 45m this week weekdays 9-17\n\
+             /timezone [zone] - Shows or sets the chat's time zone, like /timezone Europe/Berlin\n\
+             /double_booking [allow|reject] - Shows or sets whether events may overlap, like /double_booking reject\n\
+             /reminders [<length>|off] - Shows or sets how long before events to remind the chat, like /reminders 10m\n\
+             /digest [on <time>|off] - Shows or sets when to post the day's agenda, like /digest on 8:00\n\
+             /export - Sends the calendar as an .ics file\n\
+             /import - Adds the events in an .ics file sent with it or replied to\n\
+             /help - Lists these commands"
+        );
+    }
+
+    #[test]
+    fn echo_command() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut chats = Chats::new(dir.path()).unwrap();
+        assert_eq!(send(&mut chats, "/echo hi there"), "hi there");
+        assert_eq!(send(&mut chats, "/echo"), "Say something after /echo");
+    }
+
+    #[test]
+    fn add_event_and_today_commands() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut chats = Chats::new(dir.path()).unwrap();
+        assert_eq!(send(&mut chats, "/today"), "No events today");
+
+        assert_eq!(
+            send(&mut chats, "/add_event today 3pm lunch"),
+            "Added event successfully"
+        );
+        assert_eq!(
+            send(&mut chats, "/add_event tomorrow 9am standup"),
+            "Added event successfully"
+        );
+        assert!(send(&mut chats, "/add_event lunch").starts_with("Expected a date or time"));
+        assert_eq!(
+            send(&mut chats, "/today"),
+            "On 10/16/2019 at 15:00:00:\nlunch"
+        );
+    }
+
+    #[test]
+    fn lists_include_events_already_going() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut chats = Chats::new(dir.path()).unwrap();
+        let start = Los_Angeles.ymd(2019, 10, 15).and_hms(22, 0, 0);
+        chats
+            .cal(1)
+            .unwrap()
+            .add_event(cal::Event {
+                organizer: String::new(),
+                description: String::from("late shift"),
+                interval: Interval {
+                    start: start.with_timezone(&Utc),
+                    end: (start + Duration::hours(3)).with_timezone(&Utc),
+                },
+                recurrence: None,
+                all_day: None,
+                remind_minutes_before: None,
+            })
+            .unwrap();
+        send(&mut chats, "/add_event today 3pm-4pm lunch");
+
+        assert_eq!(
+            send(&mut chats, "/today"),
+            "On 10/15/2019 at 22:00:00:\nlate shift\n\n\
+             On 10/16/2019 at 15:00:00:\nlunch"
+        );
+        assert_eq!(
+            send(&mut chats, "/week"),
+            "Wednesday 10/16\n\
+             10/15 22:00-01:00: late shift\n\
+             15:00-16:00: lunch"
+        );
+    }
+
+    #[test]
+    fn add_event_warns_of_overlaps() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut chats = Chats::new(dir.path()).unwrap();
+        send(&mut chats, "/add_event today 3pm-4pm lunch");
+
+        let overlapping = "/add_event today 3:30pm-5pm meeting";
+        assert_eq!(
+            send_with(&mut chats, message(overlapping), &|_| None),
+            Some(Reply::Buttons(
+                String::from(
+                    "Added the event, but it overlaps with:\nlunch, Wednesday 10/16 15:00-16:00"
+                ),
+                vec![
+                    Button {
+                        label: String::from("Keep it"),
+                        data: String::from("add_event keep 1"),
+                    },
+                    Button {
+                        label: String::from("Cancel it"),
+                        data: String::from("add_event cancel 1"),
+                    },
+                ]
+            ))
+        );
+        assert_eq!(press(&mut chats, "add_event keep 1"), "Kept meeting");
+        assert_eq!(press(&mut chats, "add_event cancel 1"), "Cancelled meeting");
+        assert_eq!(
+            press(&mut chats, "add_event cancel 1"),
+            "That event is already gone"
+        );
+        assert_eq!(
+            send(&mut chats, "/today"),
+            "On 10/16/2019 at 15:00:00:\nlunch"
+        );
+
+        assert_eq!(
+            send(&mut chats, "/double_booking"),
+            "Events may overlap others"
+        );
+        assert_eq!(
+            send(&mut chats, "/double_booking reject"),
+            "Events that overlap others will be rejected"
+        );
+        assert_eq!(
+            send(&mut chats, overlapping),
+            "Didn't add the event, since it overlaps with:\nlunch, Wednesday 10/16 15:00-16:00"
+        );
+        assert_eq!(
+            send(&mut chats, "/add_event today 4pm-5pm meeting"),
+            "Added event successfully"
+        );
+        assert_eq!(
+            send(&mut chats, "/double_booking"),
+            "Events that overlap others are rejected"
+        );
+        assert!(send(&mut chats, "/double_booking maybe").starts_with("Say \"allow\""));
+    }
+
+    #[test]
+    fn reminder_commands() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut chats = Chats::new(dir.path()).unwrap();
+        assert_eq!(
+            send(&mut chats, "/reminders"),
+            "Only reminding of events that ask"
+        );
+        assert_eq!(
+            send(&mut chats, "/reminders 1h30m"),
+            "Will remind 1h30m before events"
+        );
+        assert_eq!(
+            send(&mut chats, "/reminders"),
+            "Reminding 1h30m before events"
+        );
+        assert_eq!(chats.settings(1).unwrap().remind_minutes_before, Some(90));
+        assert!(send(&mut chats, "/reminders 8 days").starts_with("Can remind between"));
+        assert!(send(&mut chats, "/reminders soon").starts_with("Say how long"));
+        assert_eq!(
+            send(&mut chats, "/reminders off"),
+            "Will only remind of events that ask"
+        );
+        assert_eq!(chats.settings(1).unwrap().remind_minutes_before, None);
+
+        send(
+            &mut chats,
+            "/add_event today 3pm call mom remind 1 day before",
+        );
+        send(&mut chats, "/add_event tomorrow 3pm remind the team");
+        let events: Vec<_> = chats
+            .cal(1)
+            .unwrap()
+            .get_cal()
+            .iter()
+            .map(|x| (x.1.description.clone(), x.1.remind_minutes_before))
+            .collect();
+        assert_eq!(
+            events,
+            vec![
+                (String::from("call mom"), Some(24 * 60)),
+                (String::from("remind the team"), None),
+            ]
+        );
+        assert!(send(&mut chats, "/add_event today 5pm x remind 0m before")
+            .starts_with("Can remind between"));
+    }
+
+    #[test]
+    fn digest_command() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut chats = Chats::new(dir.path()).unwrap();
+        assert_eq!(send(&mut chats, "/digest"), "Not posting a daily agenda");
+        assert_eq!(
+            send(&mut chats, "/digest on 18:30"),
+            "Will post the day's agenda at 18:30 every day"
+        );
+        assert_eq!(
+            send(&mut chats, "/digest"),
+            "Posting the day's agenda at 18:30"
+        );
+        assert_eq!(chats.last_digest(1).unwrap(), None);
+
+        // It's 10:00 in the chat, so today's 8:00 digest is skipped.
+        assert_eq!(
+            send(&mut chats, "/digest ON 8am"),
+            "Will post the day's agenda at 08:00 every day"
+        );
+        assert_eq!(
+            chats.settings(1).unwrap().digest,
+            Some(NaiveTime::from_hms(8, 0, 0))
+        );
+        assert_eq!(
+            chats.last_digest(1).unwrap(),
+            Some(NaiveDate::from_ymd(2019, 10, 16))
+        );
+
+        assert!(send(&mut chats, "/digest on").starts_with("Say when"));
+        assert!(send(&mut chats, "/digest 8:00").starts_with("Say when"));
+        assert!(send(&mut chats, "/digest on tomorrow 8:00").starts_with("Say when"));
+        assert_eq!(send(&mut chats, "/digest off"), "Won't post a daily agenda");
+        assert_eq!(chats.settings(1).unwrap().digest, None);
+    }
+
+    #[test]
+    fn split_reminder_test() {
+        assert_eq!(
+            split_reminder("lunch Remind 10m before "),
+            Some(("lunch", Duration::minutes(10)))
+        );
+        assert_eq!(
+            split_reminder("remind an hour before"),
+            Some(("", Duration::hours(1)))
+        );
+        assert_eq!(split_reminder("lunch remind me before"), None);
+        assert_eq!(split_reminder("lunch unremind 10m before"), None);
+        assert_eq!(split_reminder("lunch"), None);
+    }
+
+    #[test]
+    fn range_commands() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut chats = Chats::new(dir.path()).unwrap();
+        assert_eq!(send(&mut chats, "/tomorrow"), "No events tomorrow");
+        assert_eq!(send(&mut chats, "/week"), "No events in the next 7 days");
+
+        send(&mut chats, "/add_event today 3pm-4pm lunch");
+        send(&mut chats, "/add_event tomorrow 9am standup");
+        send(&mut chats, "/add_event tomorrow all day offsite");
+        send(&mut chats, "/add_event oct 20 10pm-1am party");
+        send(&mut chats, "/add_event nov 5 9am dentist");
+
+        assert_eq!(
+            send(&mut chats, "/tomorrow"),
+            "Thursday 10/17\nAll day: offsite\n09:00-10:00: standup"
+        );
+        assert_eq!(
+            send(&mut chats, "/week"),
+            "Wednesday 10/16\n\
+             15:00-16:00: lunch\n\
+             \n\
+             Thursday 10/17\n\
+             All day: offsite\n\
+             09:00-10:00: standup\n\
+             \n\
+             Sunday 10/20\n\
+             22:00-10/21 01:00: party"
+        );
+        assert!(send(&mut chats, "/month").ends_with("Tuesday 11/5\n09:00-10:00: dentist"));
+        assert_eq!(
+            send(&mut chats, "/agenda 1 day"),
+            "Wednesday 10/16\n15:00-16:00: lunch"
+        );
+        assert_eq!(
+            send(&mut chats, "/agenda 5d"),
+            send(&mut chats, "/agenda 5")
+        );
+        assert!(send(&mut chats, "/agenda").starts_with("Say how many days"));
+        assert!(send(&mut chats, "/agenda 3 weeks").starts_with("Say how many days"));
+        assert!(send(&mut chats, "/agenda 0").starts_with("Can list between"));
+    }
+
+    #[test]
+    fn free_command() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut chats = Chats::new(dir.path()).unwrap();
+        send(&mut chats, "/add_event tomorrow 10am-11am standup");
+        // This overlaps standup, so it's added with buttons to cancel it.
+        send_with(
+            &mut chats,
+            message("/add_event tomorrow 10:30am-noon review"),
+            &|_| None,
+        );
+        send(&mut chats, "/add_event tomorrow 4:30pm-6pm drinks");
+        send(&mut chats, "/add_event tomorrow all day offsite");
+
+        assert_eq!(
+            send(&mut chats, "/free tomorrow 9:00-17:00"),
+            "Free on Thursday 10/17:\n09:00-10:00\n12:00-16:30"
+        );
+        assert_eq!(
+            send(&mut chats, "/free tomorrow 9:00-17:00 at least 2h"),
+            "Free on Thursday 10/17:\n12:00-16:30"
+        );
+        assert_eq!(
+            send(&mut chats, "/free tomorrow"),
+            "Free on Thursday 10/17:\n00:00-10:00\n12:00-16:30\n18:00-10/18 00:00"
+        );
+        assert_eq!(
+            send(&mut chats, "/free tomorrow 10:15-11:45"),
+            "No free time then"
+        );
+        assert!(send(&mut chats, "/free").starts_with("Expected a date or time"));
+        assert!(send(&mut chats, "/free tomorrow lunch").starts_with("Expected a length"));
+        assert!(send(&mut chats, "/free tomorrow 9am for 600 weeks")
+            .starts_with("\"tomorrow 9am for 600 weeks\" is too far away"));
+    }
+
+    #[test]
+    fn find_time_command() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut chats = Chats::new(dir.path()).unwrap();
+        send(&mut chats, "/add_event tomorrow 9am-noon workshop");
+        send(&mut chats, "/add_event tomorrow 12:30pm-1pm lunch");
+
+        let offer = |chats: &mut Chats, text: &str| match send_with(chats, message(text), &|_| None)
+        {
+            Some(Reply::Buttons(text, buttons)) => {
+                assert_eq!(buttons.len(), 1);
+                assert_eq!(buttons[0].label, "Book it");
+                (text, buttons[0].data.clone())
+            }
+            reply => panic!("unexpected reply {:?}", reply),
+        };
+
+        // It's 10:00 on a Wednesday.
+        let (text, _) = offer(&mut chats, "/find_time 45m");
+        assert_eq!(text, "The earliest free 45m is Wednesday 10/16 10:00-10:45");
+        let (text, _) = offer(&mut chats, "/find_time 45m next week weekdays 9-17");
+        assert_eq!(text, "The earliest free 45m is Monday 10/21 09:00-09:45");
+        let (text, _) = offer(&mut chats, "/find_time 1 hour saturday 22-24");
+        assert_eq!(text, "The earliest free 1h is Saturday 10/19 22:00-23:00");
+
+        let (text, book) = offer(&mut chats, "/find_time 45m tomorrow 9-17");
+        assert_eq!(text, "The earliest free 45m is Thursday 10/17 13:00-13:45");
+        assert_eq!(book, "find_time 1571342400 1571345100");
+        assert_eq!(
+            press(&mut chats, &book),
+            "Booked Thursday 10/17 13:00-13:45"
+        );
+        assert_eq!(press(&mut chats, &book), "That time isn't free anymore");
+        for data in &[
+            "find_time 1571349600 1571346000",
+            "find_time 1571349600 1571349600",
+            "find_time 1571349600 99999999999",
+            "find_time 1571349600 9223372036854775807",
+            "find_time 1571349600",
+            "find_time soon",
+        ] {
+            assert_eq!(
+                press(&mut chats, data),
+                "That button doesn't do anything anymore",
+                "{}",
+                data
+            );
+        }
+        let (text, _) = offer(&mut chats, "/find_time 45m tomorrow 9-17");
+        assert_eq!(text, "The earliest free 45m is Thursday 10/17 13:45-14:30");
+
+        assert_eq!(
+            send(&mut chats, "/find_time 8h tomorrow 9-17"),
+            "No free 8h then"
+        );
+        assert!(send(&mut chats, "/find_time soon").starts_with("Say how long"));
+        assert!(send(&mut chats, "/find_time 45m whenever").starts_with("Expected a day"));
+    }
+
+    #[test]
+    fn format_days_test() {
+        let event = |description: &str, start: DateTime<Utc>, end: DateTime<Utc>| cal::Event {
+            organizer: String::new(),
+            description: String::from(description),
+            interval: Interval {
+                start: start,
+                end: end,
+            },
+            recurrence: None,
+            all_day: None,
+            remind_minutes_before: None,
+        };
+        let all_day = cal::AllDay {
+            start: NaiveDate::from_ymd(2019, 10, 14),
+            end: NaiveDate::from_ymd(2019, 10, 17),
+            zone: Los_Angeles,
+        };
+        let mut cal = cal::Cal::new();
+        cal.add_event(cal::Event {
+            interval: all_day.interval(),
+            all_day: Some(all_day),
+            ..event("trip", now(), now())
+        });
+        cal.add_event(event(
+            "party",
+            Los_Angeles
+                .ymd(2019, 10, 16)
+                .and_hms(23, 0, 0)
+                .with_timezone(&Utc),
+            Los_Angeles
+                .ymd(2019, 10, 17)
+                .and_hms(2, 0, 0)
+                .with_timezone(&Utc),
+        ));
+        cal.add_event(event("ping", now(), now()));
+
+        // The trip started before the first day, so is listed under it.
+        let first = NaiveDate::from_ymd(2019, 10, 16);
+        let range = local_time::day_range(&Los_Angeles, first);
+        assert_eq!(
+            format_days(cal.events_in(range), &Los_Angeles, first),
+            "Wednesday 10/16\n\
+             All day, 10/14 to 10/16: trip\n\
+             10:00: ping\n\
+             23:00-10/17 02:00: party"
+        );
+    }
+
+    #[test]
+    fn timezone_command() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut chats = Chats::new(dir.path()).unwrap();
+        assert_eq!(
+            send(&mut chats, "/timezone"),
+            "This chat's time zone is America/Los_Angeles"
+        );
+        assert_eq!(
+            send(&mut chats, "/timezone Europe/Berlin"),
+            "Time zone set to Europe/Berlin"
+        );
+        assert!(send(&mut chats, "/timezone Mars/Olympus").starts_with("Unknown time zone"));
+        assert_eq!(
+            chats.settings(1).unwrap().timezone,
+            chrono_tz::Europe::Berlin
+        );
+    }
+
+    #[test]
+    fn export_and_import_commands() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut chats = Chats::new(dir.path()).unwrap();
+        send(&mut chats, "/add_event today 3pm lunch");
+
+        let ics = match send_with(&mut chats, message("/export"), &|_| None) {
+            Some(Reply::Document(file)) => String::from_utf8(file.contents).unwrap(),
+            reply => panic!("unexpected reply {:?}", reply),
+        };
+        assert!(ics.contains("SUMMARY:lunch"));
+
+        assert_eq!(
+            send(&mut chats, "/import"),
+            "Send an .ics file with /import as its caption, or reply to one with /import"
+        );
+        let with_file = |file_id: &str| tg::Message {
+            document: Some(tg::Document {
+                file_id: String::from(file_id),
+                ..tg::Document::default()
+            }),
+            ..message("/import")
+        };
+        let download = |file_id: &str| {
+            if file_id == "ics" {
+                Some(ics.clone())
+            } else {
+                None
+            }
+        };
+        assert_eq!(
+            send_with(&mut chats, with_file("missing"), &download),
+            Some(Reply::Text(String::from("Couldn't download the file")))
+        );
+        assert_eq!(
+            send_with(&mut chats, with_file("ics"), &download),
+            Some(Reply::Text(String::from("Imported 1 events")))
+        );
+        assert_eq!(chats.cal(1).unwrap().get_cal().len(), 2);
+    }
+
+    #[test]
+    fn parse_event_correct_datetime() {
+        let body = "1/15/2024 7:53:29 hello world";
+        let event = parse_event(body, &Los_Angeles, now()).unwrap();
+        assert_eq!(
+            event.interval.start,
+            Utc.ymd(2024, 1, 15).and_hms(15, 53, 29)
+        );
+
+        // Daylight saving time is in effect.
+        let event = parse_event("7/15/2024 7:53:29", &Los_Angeles, now()).unwrap();
+        assert_eq!(
+            event.interval.start,
+            Utc.ymd(2024, 7, 15).and_hms(14, 53, 29)
+        );
+    }
+
+    #[test]
+    fn parse_event_description() {
+        let body = "1/1/1 1:1:1 god is dead";
+        let event = parse_event(body, &Los_Angeles, now()).unwrap();
+        assert_eq!(event.description, "god is dead");
+    }
+
+    #[test]
+    fn parse_event_no_description() {
+        let body = "1/1/1 1:1:1";
+        let event = parse_event(body, &Los_Angeles, now()).unwrap();
+        assert_eq!(event.description, "");
+    }
+
+    #[test]
+    fn parse_event_errors() {
+        assert_eq!(
+            parse_event("1/1/ 1:1:1", &Los_Angeles, now()),
+            Err(ParseError::MissingWhen(Some(span(0, "1/1/"))))
+        );
+        assert_eq!(
+            parse_event("1/1/1 1:67:1", &Los_Angeles, now()),
+            Err(ParseError::TimeOutOfRange(span(6, "1:67:1")))
+        );
+        assert_eq!(
+            parse_event("1/1/11:1:1", &Los_Angeles, now()),
+            Err(ParseError::MissingWhen(Some(span(0, "1/1/11:1:1"))))
+        );
+        assert_eq!(
+            parse_event(" 1/1/1  i forgot the time", &Los_Angeles, now()),
+            Err(ParseError::MissingTime(span(1, "1/1/1")))
+        );
+    }
+
+    #[test]
+    fn parse_event_relative() {
+        let event = parse_event("tomorrow 3pm for 90m lunch", &Los_Angeles, now()).unwrap();
+        assert_eq!(event.description, "lunch");
+        assert_eq!(
+            event.interval,
+            Interval {
+                start: Utc.ymd(2019, 10, 17).and_hms(22, 0, 0),
+                end: Utc.ymd(2019, 10, 17).and_hms(23, 30, 0),
+            }
+        );
+
+        let event = parse_event("friday 11pm-1am party", &Los_Angeles, now()).unwrap();
+        assert_eq!(
+            event.interval,
+            Interval {
+                start: Utc.ymd(2019, 10, 19).and_hms(6, 0, 0),
+                end: Utc.ymd(2019, 10, 19).and_hms(8, 0, 0),
+            }
+        );
+
+        assert_eq!(
+            parse_event("tomorrow 3pm for 0m", &Los_Angeles, now()),
+            Err(ParseError::EndsBeforeStart(span(0, "tomorrow 3pm for 0m")))
+        );
+        assert_eq!(
+            parse_event("nov 3 1am-1:30am nap", &Los_Angeles, now()),
+            Err(ParseError::AmbiguousTime(span(0, "nov 3 1am-1:30am")))
+        );
+        let text = format!("12/31/9999 3pm for{} nap", " 65535 weeks".repeat(220));
+        assert_eq!(
+            parse_event(&text, &Los_Angeles, now()),
+            Err(ParseError::OutOfRange(span(0, &text[..text.len() - 4])))
+        );
+        assert_eq!(
+            parse_event("tomorrow 3pm for 600 weeks nap", &Los_Angeles, now()),
+            Err(ParseError::OutOfRange(span(
+                0,
+                "tomorrow 3pm for 600 weeks"
+            )))
+        );
+    }
+
+    #[test]
+    fn parse_event_lengths() {
+        let interval = |text| parse_event(text, &Los_Angeles, now()).map(|x| x.interval);
+        let start = Utc.ymd(2019, 10, 17).and_hms(22, 0, 0);
+
+        assert_eq!(
+            interval("tomorrow 3pm"),
+            Ok(Interval {
+                start: start,
+                end: start + chrono::Duration::hours(1),
+            })
+        );
+        assert_eq!(
+            interval("tomorrow 3pm 2h30m"),
+            Ok(Interval {
+                start: start,
+                end: start + chrono::Duration::minutes(150),
+            })
+        );
+        assert_eq!(
+            interval("tomorrow 3pm for 15 minutes"),
+            Ok(Interval {
+                start: start,
+                end: start + chrono::Duration::minutes(15),
+            })
+        );
+        assert_eq!(
+            interval("tomorrow 3pm-4:45pm"),
+            Ok(Interval {
+                start: start,
+                end: start + chrono::Duration::minutes(105),
+            })
+        );
+        assert_eq!(
+            parse_event("tomorrow all day", &Los_Angeles, now()).map(|x| x.all_day),
+            Ok(Some(cal::AllDay {
+                start: NaiveDate::from_ymd(2019, 10, 17),
+                end: NaiveDate::from_ymd(2019, 10, 18),
+                zone: Los_Angeles,
+            }))
+        );
+        assert_eq!(
+            interval("tomorrow all day"),
+            Ok(Interval {
+                start: Utc.ymd(2019, 10, 17).and_hms(7, 0, 0),
+                end: Utc.ymd(2019, 10, 18).and_hms(7, 0, 0),
+            })
+        );
+        // The clocks go back on November 3rd.
+        assert_eq!(
+            interval("nov 2 all day for 2d"),
+            Ok(Interval {
+                start: Utc.ymd(2019, 11, 2).and_hms(7, 0, 0),
+                end: Utc.ymd(2019, 11, 4).and_hms(8, 0, 0),
+            })
+        );
+        assert_eq!(
+            interval("tomorrow all day for 36h"),
+            Err(ParseError::PartialDays(span(0, "tomorrow all day for 36h")))
+        );
+        assert_eq!(
+            interval("tomorrow all day for 5000 days"),
+            Err(ParseError::OutOfRange(span(
+                0,
+                "tomorrow all day for 5000 days"
+            )))
+        );
+        assert_eq!(
+            interval("tomorrow"),
+            Err(ParseError::MissingTime(span(0, "tomorrow")))
+        );
+    }
+
+    #[test]
+    fn parse_event_clock_changes() {
+        assert_eq!(
+            parse_event("11/3/2019 1:30:00", &Los_Angeles, now()),
+            Err(ParseError::AmbiguousTime(span(0, "11/3/2019 1:30:00")))
+        );
+        assert_eq!(
+            parse_event("3/10/2019 2:30:00", &Los_Angeles, now()),
+            Err(ParseError::NonexistentTime(span(0, "3/10/2019 2:30:00")))
+        );
+    }
+
+    #[test]
+    fn parse_error_reply_test() {
+        let err = parse_event("tomorrow 13pm lunch", &Los_Angeles, now()).unwrap_err();
+        assert_eq!(
+            parse_error_reply(&err),
+            "\"13pm\" isn't a time: with am or pm, hours go from 1 to 12\n\n\
+             For example:\n\
+             /add_event tomorrow 3:30pm coffee\n\
+             /add_event tomorrow 15:30 coffee"
+        );
+
+        let err = parse_event("lunch tomorrow", &Los_Angeles, now()).unwrap_err();
+        assert!(parse_error_reply(&err).starts_with(
+            "Expected a date or time, like \"tomorrow 3pm\", but it starts with \"lunch\""
+        ));
+    }
+
+    #[test]
+    fn pretty_print_event_test() {
+        let event = cal::Event {
+            organizer: String::from(""),
+            description: String::from("test description"),
+            interval: Interval {
+                start: Los_Angeles
+                    .ymd(2000, 1, 15)
+                    .and_hms(13, 1, 2)
+                    .with_timezone(&Utc),
+                end: Los_Angeles
+                    .ymd(2000, 1, 15)
+                    .and_hms(13, 1, 2)
+                    .with_timezone(&Utc),
+            },
+            recurrence: None,
+            all_day: None,
+            remind_minutes_before: None,
+        };
+        let mut cal = cal::Cal::new();
+        cal.add_event(event.clone());
+        let occurrence = cal
+            .events_in(event.interval.start..event.interval.start + chrono::Duration::days(1))
+            .next()
+            .unwrap();
+        assert_eq!(
+            pretty_print_event(&occurrence, &Los_Angeles),
+            String::from("On 1/15/2000 at 13:01:02:\ntest description")
+        );
+    }
+
+    #[test]
+    fn pretty_print_all_day_event_test() {
+        let mut cal = cal::Cal::new();
+        cal.add_event(parse_event("nov 2 all day for 3d trip", &Los_Angeles, now()).unwrap());
+        cal.add_event(parse_event("nov 3 all day party", &Los_Angeles, now()).unwrap());
+
+        // The trip started the day before, but still shows up.
+        let range = local_time::day_range(&Los_Angeles, NaiveDate::from_ymd(2019, 11, 3));
+        let printed: Vec<_> = cal
+            .events_in(range)
+            .map(|x| pretty_print_event(&x, &Los_Angeles))
+            .collect();
+        assert_eq!(
+            printed,
+            vec![
+                "From 11/2/2019 to 11/4/2019, all day:\ntrip",
+                "On 11/3/2019, all day:\nparty",
+            ]
+        );
+    }
+
+    #[test]
+    fn format_import_report_test() {
+        assert_eq!(format_import_report(2, &[]), "Imported 2 events");
+
+        let skipped: Vec<_> = (1..=12)
+            .map(|line| cal::ics::Skipped {
+                line: line,
+                component: String::from("VTODO"),
+                summary: None,
+                reason: String::from("only VEVENTs can be imported"),
+            })
+            .collect();
+        let report = format_import_report(0, &skipped);
+        assert!(report.starts_with("Imported 0 events\n\nSkipped 12:\nline 1: VTODO: "));
+        assert!(report.contains("\nline 10: VTODO"));
+        assert!(!report.contains("\nline 11: VTODO"));
+        assert!(report.ends_with("\n...and 2 more"));
+    }
+}
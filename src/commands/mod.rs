@@ -0,0 +1,457 @@
+//! Bot commands, and the router that picks the one a message is for.
+//! Commands don't talk to Telegram themselves: they're given a
+//! `Context` and produce a `Reply`, so they can be tested without it.
+
+pub mod builtins;
+
+use crate::chats::Chats;
+use crate::tg;
+
+use std::io;
+
+use chrono::DateTime;
+use chrono::Utc;
+use chrono_tz::Tz;
+use futures::future;
+use futures::Future;
+
+/// What a command sends back to the chat it was sent in.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub enum Reply {
+    Text(String),
+    Document(tg::InputFile),
+    /// Text with buttons under it. Pressing one runs the `on_button`
+    /// of the command that replied.
+    Buttons(String, Vec<Button>),
+}
+
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct Button {
+    pub label: String,
+    /// Given to `on_button` when the button is pressed. The router puts
+    /// the command's name in front of it, and together they have to fit
+    /// in `tg::MAX_CALLBACK_DATA_LEN` bytes or the reply fails.
+    pub data: String,
+}
+
+/// A reply that might not be ready yet.
+pub type ReplyFuture = Box<dyn Future<Item = Reply, Error = io::Error>>;
+
+/// What a command runs with.
+pub struct Context<'a> {
+    pub chats: &'a mut Chats,
+    /// The message the command was sent in.
+    pub message: &'a tg::Message,
+    pub now: DateTime<Utc>,
+    /// The chat's time zone when the message arrived.
+    pub timezone: Tz,
+    /// Gets the contents of the file with the given ID, or `None` if
+    /// it can't be downloaded.
+    pub download: &'a dyn Fn(&str) -> Option<String>,
+}
+
+impl<'a> Context<'a> {
+    pub fn chat_id(&self) -> i64 {
+        self.message.chat.id
+    }
+}
+
+/// A command, like /today.
+pub trait Command {
+    /// What `parse_args` makes of the text after the command.
+    type Args;
+
+    /// The name it's sent as, without the slash.
+    fn name(&self) -> &'static str;
+
+    /// Describes it in a line for /help and Telegram's command menu.
+    fn description(&self) -> &'static str;
+
+    /// What goes after the command, like `<when> <what>`. Empty if it
+    /// takes nothing.
+    fn usage(&self) -> &'static str {
+        ""
+    }
+
+    /// Parses the text after the command. If this fails, the error is
+    /// sent as the reply and the command isn't run.
+    fn parse_args(&self, text: &str, ctx: &Context) -> Result<Self::Args, String>;
+
+    fn run(&self, args: Self::Args, ctx: &mut Context) -> ReplyFuture;
+
+    /// Handles a press of one of the buttons this command replied
+    /// with, where `data` is the button's data. `ctx.message` is the
+    /// message the button was under.
+    fn on_button(&self, _data: &str, _ctx: &mut Context) -> ReplyFuture {
+        reply_text(String::from("That button doesn't do anything anymore"))
+    }
+}
+
+/// A `Command` with its argument type hidden, so that commands of all
+/// kinds can be kept together.
+trait Handler {
+    fn name(&self) -> &'static str;
+    fn description(&self) -> &'static str;
+    fn usage(&self) -> &'static str;
+    fn handle(&self, text: &str, ctx: &mut Context) -> ReplyFuture;
+    fn on_button(&self, data: &str, ctx: &mut Context) -> ReplyFuture;
+}
+
+impl<C: Command> Handler for C {
+    fn name(&self) -> &'static str {
+        Command::name(self)
+    }
+
+    fn description(&self) -> &'static str {
+        Command::description(self)
+    }
+
+    fn usage(&self) -> &'static str {
+        Command::usage(self)
+    }
+
+    fn handle(&self, text: &str, ctx: &mut Context) -> ReplyFuture {
+        match self.parse_args(text, ctx) {
+            Ok(args) => tag_buttons(Command::name(self), self.run(args, ctx)),
+            Err(err) => reply_text(err),
+        }
+    }
+
+    fn on_button(&self, data: &str, ctx: &mut Context) -> ReplyFuture {
+        tag_buttons(Command::name(self), Command::on_button(self, data, ctx))
+    }
+}
+
+/// The commands the bot knows. /help is always known, and lists the
+/// others.
+pub struct Router {
+    commands: Vec<Box<dyn Handler>>,
+}
+
+const HELP_COMMAND: &'static str = "help";
+
+const HELP_DESCRIPTION: &'static str = "Lists these commands";
+
+impl Router {
+    pub fn new() -> Router {
+        Router {
+            commands: Vec::new(),
+        }
+    }
+
+    /// Adds a command. Commands are listed by /help in the order they
+    /// were added.
+    ///
+    /// Panics if there's already a command with the same name.
+    pub fn register<C: Command + 'static>(&mut self, command: C) {
+        let name = Command::name(&command);
+        assert!(
+            name != HELP_COMMAND && self.find(name).is_none(),
+            "command /{} registered twice",
+            name
+        );
+        self.commands.push(Box::new(command));
+    }
+
+    /// Runs the command called `name` on `text`, the rest of the
+    /// message. Returns `None` if there's no such command.
+    pub fn dispatch(&self, name: &str, text: &str, ctx: &mut Context) -> Option<ReplyFuture> {
+        if name == HELP_COMMAND {
+            return Some(reply_text(self.help()));
+        }
+        self.find(name).map(|x| x.handle(text, ctx))
+    }
+
+    /// Runs the `on_button` of the command a button belongs to, where
+    /// `data` is the data of a button the router replied with. Returns
+    /// `None` if there's no such command.
+    pub fn dispatch_button(&self, data: &str, ctx: &mut Context) -> Option<ReplyFuture> {
+        let (name, data) = match data.find(' ') {
+            Some(ndx) => (&data[..ndx], &data[ndx + 1..]),
+            None => (data, ""),
+        };
+        self.find(name).map(|x| x.on_button(data, ctx))
+    }
+
+    /// Lists the commands, how to use them and what they do.
+    pub fn help(&self) -> String {
+        let mut help = String::from("Commands:");
+        for command in &self.commands {
+            help.push_str(&format!("\n/{}", command.name()));
+            if !command.usage().is_empty() {
+                help.push_str(&format!(" {}", command.usage()));
+            }
+            help.push_str(&format!(" - {}", command.description()));
+        }
+        help.push_str(&format!("\n/{} - {}", HELP_COMMAND, HELP_DESCRIPTION));
+        help
+    }
+
+    /// Lists the commands for Telegram to suggest as they're typed.
+    pub fn bot_commands(&self) -> Vec<tg::BotCommand> {
+        let mut bot_commands: Vec<_> = self
+            .commands
+            .iter()
+            .map(|x| tg::BotCommand {
+                command: String::from(x.name()),
+                description: String::from(x.description()),
+            })
+            .collect();
+        bot_commands.push(tg::BotCommand {
+            command: String::from(HELP_COMMAND),
+            description: String::from(HELP_DESCRIPTION),
+        });
+        bot_commands
+    }
+
+    fn find(&self, name: &str) -> Option<&dyn Handler> {
+        self.commands
+            .iter()
+            .find(|x| x.name() == name)
+            .map(|x| x.as_ref())
+    }
+}
+
+/// Puts the name of the command that replied in front of its buttons'
+/// data, so that presses find their way back to it. Telegram would
+/// reject the whole reply if any button's data ended up too long, so
+/// that's an error instead.
+fn tag_buttons(name: &'static str, reply: ReplyFuture) -> ReplyFuture {
+    Box::new(reply.and_then(move |reply| {
+        let (text, buttons) = match reply {
+            Reply::Buttons(text, buttons) => (text, buttons),
+            reply => return Ok(reply),
+        };
+        let buttons: Vec<_> = buttons
+            .into_iter()
+            .map(|x| Button {
+                data: format!("{} {}", name, x.data),
+                ..x
+            })
+            .collect();
+        let too_long = buttons
+            .iter()
+            .find(|x| x.data.len() > tg::MAX_CALLBACK_DATA_LEN);
+        debug_assert!(too_long.is_none(), "button data is too long");
+        if let Some(button) = too_long {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("button data {:?} is too long", button.data),
+            ));
+        }
+        Ok(Reply::Buttons(text, buttons))
+    }))
+}
+
+/// Replies with `text` straight away.
+pub fn reply_text(text: String) -> ReplyFuture {
+    Box::new(future::ok(Reply::Text(text)))
+}
+
+/// Replies with the result of work that's already been done.
+pub fn reply_with(result: io::Result<Reply>) -> ReplyFuture {
+    Box::new(future::result(result))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use chrono::TimeZone;
+
+    extern crate tempfile;
+
+    struct Add;
+
+    impl Command for Add {
+        type Args = (i32, i32);
+
+        fn name(&self) -> &'static str {
+            "add"
+        }
+
+        fn description(&self) -> &'static str {
+            "Adds two numbers"
+        }
+
+        fn usage(&self) -> &'static str {
+            "<a> <b>"
+        }
+
+        fn parse_args(&self, text: &str, _ctx: &Context) -> Result<(i32, i32), String> {
+            let numbers: Vec<i32> = text
+                .split_whitespace()
+                .map(str::parse)
+                .collect::<Result<_, _>>()
+                .map_err(|_| String::from("Those aren't numbers"))?;
+            match numbers[..] {
+                [a, b] => Ok((a, b)),
+                _ => Err(String::from("Expected two numbers")),
+            }
+        }
+
+        fn run(&self, args: (i32, i32), _ctx: &mut Context) -> ReplyFuture {
+            let sum = args.0 + args.1;
+            let double = Button {
+                label: String::from("Double it"),
+                data: sum.to_string(),
+            };
+            Box::new(future::ok(Reply::Buttons(sum.to_string(), vec![double])))
+        }
+
+        fn on_button(&self, data: &str, _ctx: &mut Context) -> ReplyFuture {
+            let sum: i32 = data.parse().unwrap();
+            reply_text((sum * 2).to_string())
+        }
+    }
+
+    /// Runs `f` with the context of a message in a new chat.
+    fn with_context<T>(f: impl FnOnce(&mut Context) -> T) -> T {
+        let dir = tempfile::tempdir().unwrap();
+        let mut chats = Chats::new(dir.path()).unwrap();
+        let message = tg::Message::default();
+        let mut ctx = Context {
+            chats: &mut chats,
+            message: &message,
+            now: Utc.ymd(2019, 10, 16).and_hms(17, 0, 0),
+            timezone: chrono_tz::UTC,
+            download: &|_| None,
+        };
+        f(&mut ctx)
+    }
+
+    fn dispatch(router: &Router, name: &str, text: &str) -> Option<Reply> {
+        with_context(|ctx| router.dispatch(name, text, ctx).map(|x| x.wait().unwrap()))
+    }
+
+    #[test]
+    fn commands_are_dispatched() {
+        let mut router = Router::new();
+        router.register(Add);
+
+        assert_eq!(
+            dispatch(&router, "add", "2 3"),
+            Some(Reply::Buttons(
+                String::from("5"),
+                vec![Button {
+                    label: String::from("Double it"),
+                    data: String::from("add 5"),
+                }]
+            ))
+        );
+        assert_eq!(
+            dispatch(&router, "add", "2"),
+            Some(Reply::Text(String::from("Expected two numbers")))
+        );
+        assert_eq!(dispatch(&router, "subtract", "2 3"), None);
+    }
+
+    #[test]
+    fn buttons_are_dispatched() {
+        let mut router = Router::new();
+        router.register(Add);
+
+        assert_eq!(
+            with_context(|ctx| router
+                .dispatch_button("add 5", ctx)
+                .unwrap()
+                .wait()
+                .unwrap()),
+            Reply::Text(String::from("10"))
+        );
+        assert!(with_context(|ctx| router
+            .dispatch_button("subtract 5", ctx)
+            .is_none()));
+    }
+
+    struct Label;
+
+    impl Command for Label {
+        type Args = String;
+
+        fn name(&self) -> &'static str {
+            "label"
+        }
+
+        fn description(&self) -> &'static str {
+            "Makes a button"
+        }
+
+        fn usage(&self) -> &'static str {
+            "<data>"
+        }
+
+        fn parse_args(&self, text: &str, _ctx: &Context) -> Result<String, String> {
+            Ok(String::from(text))
+        }
+
+        fn run(&self, data: String, _ctx: &mut Context) -> ReplyFuture {
+            let button = Button {
+                label: String::from("Press it"),
+                data: data,
+            };
+            Box::new(future::ok(Reply::Buttons(String::new(), vec![button])))
+        }
+    }
+
+    #[test]
+    // Debug builds catch the mistake sooner.
+    #[cfg_attr(debug_assertions, should_panic(expected = "button data is too long"))]
+    fn button_data_must_fit() {
+        let mut router = Router::new();
+        router.register(Label);
+
+        // With "label " in front, 58 bytes just fit.
+        let fits = "x".repeat(58);
+        assert!(with_context(|ctx| router.dispatch("label", &fits, ctx).unwrap().wait()).is_ok());
+
+        let err = with_context(|ctx| {
+            router
+                .dispatch("label", &"x".repeat(59), ctx)
+                .unwrap()
+                .wait()
+        })
+        .unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+    }
+
+    #[test]
+    fn help_lists_commands() {
+        let mut router = Router::new();
+        router.register(Add);
+
+        assert_eq!(
+            dispatch(&router, "help", ""),
+            Some(Reply::Text(String::from(
+                "Commands:\n/add <a> <b> - Adds two numbers\n/help - Lists these commands"
+            )))
+        );
+    }
+
+    #[test]
+    fn bot_commands_include_help() {
+        let mut router = Router::new();
+        router.register(Add);
+
+        let commands: Vec<_> = router
+            .bot_commands()
+            .into_iter()
+            .map(|x| (x.command, x.description))
+            .collect();
+        assert_eq!(
+            commands,
+            vec![
+                (String::from("add"), String::from("Adds two numbers")),
+                (String::from("help"), String::from("Lists these commands")),
+            ]
+        );
+    }
+
+    #[test]
+    #[should_panic]
+    fn commands_must_be_unique() {
+        let mut router = Router::new();
+        router.register(Add);
+        router.register(Add);
+    }
+}
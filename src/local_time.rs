@@ -0,0 +1,150 @@
+//! Conversions between chats' local times and UTC that account for
+//! daylight saving time and other offset changes.
+
+use std::ops::Range;
+
+use chrono::DateTime;
+use chrono::Duration;
+use chrono::LocalResult;
+use chrono::NaiveDate;
+use chrono::NaiveDateTime;
+use chrono::Offset;
+use chrono::TimeZone;
+use chrono::Utc;
+
+/// Why a local time can't be converted to a single UTC time.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum LocalTimeError {
+    /// The time happens twice, e.g. when the clocks go back.
+    Ambiguous,
+    /// The time is skipped, e.g. when the clocks go forward.
+    Nonexistent,
+}
+
+/// Converts a local time in `zone` to UTC.
+pub fn to_utc<Z: TimeZone>(
+    zone: &Z,
+    local: NaiveDateTime,
+) -> Result<DateTime<Utc>, LocalTimeError> {
+    match zone.from_local_datetime(&local) {
+        LocalResult::Single(x) => Ok(x.with_timezone(&Utc)),
+        LocalResult::Ambiguous(_, _) => Err(LocalTimeError::Ambiguous),
+        LocalResult::None => Err(LocalTimeError::Nonexistent),
+    }
+}
+
+/// Converts a local time in `zone` to UTC, for times that have to
+/// happen even if the clocks skip or repeat them. A time that happens
+/// twice is taken the first time, and a skipped time is taken as if
+/// the clocks hadn't changed yet.
+pub fn to_utc_nearest<Z: TimeZone>(zone: &Z, local: NaiveDateTime) -> DateTime<Utc> {
+    match zone.from_local_datetime(&local) {
+        LocalResult::Single(x) | LocalResult::Ambiguous(x, _) => x.with_timezone(&Utc),
+        LocalResult::None => {
+            let offset = zone
+                .offset_from_local_datetime(&(local - Duration::days(1)))
+                .earliest()
+                .unwrap_or_else(|| zone.offset_from_utc_datetime(&local));
+            let offset = Duration::seconds(i64::from(offset.fix().local_minus_utc()));
+            Utc.from_utc_datetime(&(local - offset))
+        }
+    }
+}
+
+/// Gets the first instant of `date` in `zone`. This is usually
+/// midnight, but if midnight was skipped it's the end of the skipped
+/// time instead.
+pub fn day_start<Z: TimeZone>(zone: &Z, date: NaiveDate) -> DateTime<Utc> {
+    // Midnight in the offset from before a skip is when the skip
+    // happened.
+    to_utc_nearest(zone, date.and_hms(0, 0, 0))
+}
+
+/// Gets the range of UTC times on `date` in `zone`.
+pub fn day_range<Z: TimeZone>(zone: &Z, date: NaiveDate) -> Range<DateTime<Utc>> {
+    Range {
+        start: day_start(zone, date),
+        end: day_start(zone, date.succ()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use chrono_tz::America::Los_Angeles;
+    use chrono_tz::America::Sao_Paulo;
+
+    #[test]
+    fn to_utc_handles_transitions() {
+        assert_eq!(
+            to_utc(
+                &Los_Angeles,
+                NaiveDate::from_ymd(2019, 7, 1).and_hms(9, 0, 0)
+            ),
+            Ok(Utc.ymd(2019, 7, 1).and_hms(16, 0, 0))
+        );
+        assert_eq!(
+            to_utc(
+                &Los_Angeles,
+                NaiveDate::from_ymd(2019, 12, 1).and_hms(9, 0, 0)
+            ),
+            Ok(Utc.ymd(2019, 12, 1).and_hms(17, 0, 0))
+        );
+        assert_eq!(
+            to_utc(
+                &Los_Angeles,
+                NaiveDate::from_ymd(2019, 11, 3).and_hms(1, 30, 0)
+            ),
+            Err(LocalTimeError::Ambiguous)
+        );
+        assert_eq!(
+            to_utc(
+                &Los_Angeles,
+                NaiveDate::from_ymd(2019, 3, 10).and_hms(2, 30, 0)
+            ),
+            Err(LocalTimeError::Nonexistent)
+        );
+    }
+
+    #[test]
+    fn to_utc_nearest_handles_transitions() {
+        // 1:30 happens first in daylight time.
+        assert_eq!(
+            to_utc_nearest(
+                &Los_Angeles,
+                NaiveDate::from_ymd(2019, 11, 3).and_hms(1, 30, 0)
+            ),
+            Utc.ymd(2019, 11, 3).and_hms(8, 30, 0)
+        );
+        // 2:30 is skipped, so it's taken in standard time, as 3:30.
+        assert_eq!(
+            to_utc_nearest(
+                &Los_Angeles,
+                NaiveDate::from_ymd(2019, 3, 10).and_hms(2, 30, 0)
+            ),
+            Utc.ymd(2019, 3, 10).and_hms(10, 30, 0)
+        );
+    }
+
+    #[test]
+    fn day_range_test() {
+        // The day the clocks go forward is 23 hours long.
+        assert_eq!(
+            day_range(&Los_Angeles, NaiveDate::from_ymd(2019, 3, 10)),
+            Range {
+                start: Utc.ymd(2019, 3, 10).and_hms(8, 0, 0),
+                end: Utc.ymd(2019, 3, 11).and_hms(7, 0, 0),
+            }
+        );
+
+        // In 2018, Sao Paulo's clocks went from 00:00 to 01:00.
+        assert_eq!(
+            day_range(&Sao_Paulo, NaiveDate::from_ymd(2018, 11, 4)),
+            Range {
+                start: Utc.ymd(2018, 11, 4).and_hms(3, 0, 0),
+                end: Utc.ymd(2018, 11, 5).and_hms(2, 0, 0),
+            }
+        );
+    }
+}
@@ -1,26 +1,38 @@
 extern crate bincode;
 extern crate chrono;
+extern crate chrono_tz;
+extern crate crc32fast;
 extern crate futures;
 extern crate itertools;
-extern crate lazy_static;
 extern crate reqwest;
 extern crate serde;
 extern crate serde_json;
 
 mod cal;
+mod chats;
+mod commands;
+mod local_time;
+mod reminders;
 mod tg;
+mod when;
 
-use std::mem::drop;
-use std::ops::Range;
+use std::cell::Cell;
+use std::path::Path;
 use std::string::String;
 
 use chrono::prelude::*;
+use chrono::Duration;
 use futures::future;
+use futures::stream;
 use futures::Future;
 use futures::Stream;
-use lazy_static::lazy_static;
 
-use crate::cal::interval::Interval;
+use crate::chats::Chats;
+use crate::chats::Settings;
+use crate::commands::Reply;
+use crate::commands::Router;
+use crate::reminders::Clock;
+use crate::reminders::Scheduler;
 
 fn main() {
     let token = std::env::var(TOKEN_ENV_VAR).expect("Missing TG_BOT_TOKEN env var");
@@ -29,78 +41,261 @@ fn main() {
     let me = tg_client.get_me().wait().unwrap().unwrap();
     println!("{:?}", me);
 
-    let mut cal =
-        cal::PersistentCal::open_or_create(CAL_FILE).expect("Couldn't open calendar file");
+    let mut chats = chats::Chats::new(CHATS_DIR).expect("Couldn't open chats directory");
+    adopt_legacy_cal(&mut chats);
+    let mut router = commands::Router::new();
+    commands::builtins::register(&mut router);
+
+    // Without this, Telegram clients can't suggest the commands as
+    // they're typed. The bot works either way.
+    let set_my_commands = tg::SetMyCommands {
+        commands: router.bot_commands(),
+    };
+    match tg_client.set_my_commands(set_my_commands).wait() {
+        Ok(Ok(_)) => (),
+        Ok(Err(_)) => println!("Couldn't publish the command list"),
+        Err(err) => println!("Couldn't publish the command list: {:?}", err),
+    }
 
-    tg::update_stream(&tg_client, 10)
-        .filter_map(|update| update.message)
-        .filter_map(|recv_msg| {
-            let (command, body) =
-                parse_command(recv_msg.text.as_ref().map(String::as_str).unwrap_or(""));
+    let download = |file_id: &str| {
+        let get_file = tg::GetFile {
+            file_id: String::from(file_id),
+        };
+        let file = tg_client.get_file(get_file).wait().ok()?.ok()?;
+        if file.file_size? > MAX_DOWNLOAD_SIZE {
+            return None;
+        }
+        tg_client.download_file(&file.file_path?).wait().ok()
+    };
+
+    // Polls end early when updates come in, so waiting no longer than
+    // the next reminder means it's sent on time.
+    let scheduler = Scheduler::new(Utc::now);
+    let poll_timeout = Cell::new(POLL_TIMEOUT);
+    tg::update_batches(&tg_client, || poll_timeout.get())
+        .wait()
+        .map(Result::unwrap)
+        .for_each(|updates| {
+            for update in updates {
+                handle_update(update, &tg_client, &mut chats, &router, &download);
+            }
+            send_reminders(&scheduler, &tg_client, &mut chats);
+            let wait = scheduler
+                .time_until_due(&mut chats, Duration::seconds(POLL_TIMEOUT as i64))
+                .unwrap_or_else(|_| Duration::seconds(POLL_TIMEOUT as i64));
+            poll_timeout.set(wait.num_seconds().max(1) as u64);
+        });
+}
 
-            if command == "echo" && !body.is_empty() {
-                let send_msg = tg::SendMessage {
-                    chat_id: recv_msg.chat.id,
-                    text: String::from(body),
-                };
-                Some(
-                    tg_client
-                        .send_message(send_msg)
-                        .map(Result::unwrap)
-                        .map(drop),
-                )
-            } else if command == "add_event" {
-                let response = match parse_event(body) {
-                    Ok(event) => {
-                        cal.add_event(event).unwrap();
-                        String::from("Added event successfully")
-                    }
-                    Err(err) => String::from(err),
-                };
+/// Runs the command or button press in an update, if it has one, and
+/// sends the reply.
+fn handle_update<S, F, E>(
+    update: tg::Update,
+    client: &tg::Client<S>,
+    chats: &mut Chats,
+    router: &Router,
+    download: &dyn Fn(&str) -> Option<String>,
+) where
+    S: Fn(String, Option<tg::Body>) -> F,
+    F: Future<Item = String, Error = E>,
+    E: std::fmt::Debug,
+{
+    // A button press is for the message the button was under.
+    let (message, button) = match (update.message, update.callback_query) {
+        (Some(message), _) => (message, None),
+        (None, Some(query)) => {
+            // Stops the client showing progress on the button.
+            let answer = tg::AnswerCallbackQuery {
+                callback_query_id: query.id,
+                text: None,
+            };
+            match client.answer_callback_query(answer).wait() {
+                Ok(Ok(_)) => (),
+                Ok(Err(_)) => println!("Couldn't answer a button press"),
+                Err(err) => println!("Couldn't answer a button press: {:?}", err),
+            }
+            match (query.message, query.data) {
+                (Some(message), Some(data)) => (message, Some(data)),
+                _ => return,
+            }
+        }
+        (None, None) => return,
+    };
+    let chat_id = message.chat.id;
+    // A chat that can't be loaded still gets replies, even if only to
+    // say its commands failed.
+    let timezone = match chats.settings(chat_id) {
+        Ok(settings) => settings.timezone,
+        Err(err) => {
+            println!("Couldn't load the settings of chat {}: {}", chat_id, err);
+            Settings::default().timezone
+        }
+    };
+
+    let mut ctx = commands::Context {
+        chats: chats,
+        message: &message,
+        now: Utc::now(),
+        timezone: timezone,
+        download: download,
+    };
+    let reply = match button {
+        Some(data) => router.dispatch_button(&data, &mut ctx),
+        None => {
+            // Commands sent with a document are in its caption.
+            let text = message.text.as_ref().or(message.caption.as_ref());
+            let (command, body) = parse_command(text.map(String::as_str).unwrap_or(""));
+            router.dispatch(command, body, &mut ctx)
+        }
+    };
+    if let Some(reply) = reply {
+        let reply = reply.wait().unwrap_or_else(|err| {
+            println!("Command failed in chat {}: {}", chat_id, err);
+            Reply::Text(String::from(FAILED_REPLY))
+        });
+        if let Err(err) = send_reply(client, chat_id, reply).wait() {
+            println!("Couldn't reply to chat {}: {:?}", chat_id, err);
+        }
+    }
+}
 
-                let send_msg = tg::SendMessage {
-                    chat_id: recv_msg.chat.id,
-                    text: response,
-                };
-                Some(
-                    tg_client
-                        .send_message(send_msg)
-                        .map(Result::unwrap)
-                        .map(drop),
-                )
-            } else if command == "today" {
-                let today_local = Utc::now().with_timezone(&*TIMEZONE).date();
-                let range = Range {
-                    start: today_local.and_hms(0, 0, 0).with_timezone(&Utc),
-                    end: today_local.and_hms(23, 59, 59).with_timezone(&Utc),
-                };
-                let mut response = itertools::join(
-                    cal.get_cal().events_in(range).map(pretty_print_event),
-                    "\n\n",
+/// Sends the reminders that are due, recording each one that's sent.
+/// Ones that can't be sent are tried again later.
+fn send_reminders<C, S, F, E>(scheduler: &Scheduler<C>, client: &tg::Client<S>, chats: &mut Chats)
+where
+    C: Clock,
+    S: Fn(String, Option<tg::Body>) -> F,
+    F: Future<Item = String, Error = E>,
+    E: std::fmt::Debug,
+{
+    let due = match scheduler.due(chats) {
+        Ok(due) => due,
+        Err(err) => {
+            println!("Couldn't check for reminders: {}", err);
+            return;
+        }
+    };
+    for reminder in due {
+        let message = tg::SendMessage {
+            chat_id: reminder.chat_id,
+            text: reminder.text.clone(),
+            reply_markup: None,
+        };
+        match client.send_message(message).wait() {
+            Ok(Ok(_)) => (),
+            Ok(Err(_)) => {
+                println!("Couldn't send a reminder to chat {}", reminder.chat_id);
+                continue;
+            }
+            Err(err) => {
+                println!(
+                    "Couldn't send a reminder to chat {}: {:?}",
+                    reminder.chat_id, err
                 );
-                if response == "" {
-                    response = String::from("No events today");
-                }
-
-                let send_msg = tg::SendMessage {
-                    chat_id: recv_msg.chat.id,
-                    text: response,
-                };
-                Some(
-                    tg_client
-                        .send_message(send_msg)
-                        .map(Result::unwrap)
-                        .map(drop),
-                )
-            } else {
-                None
+                continue;
             }
+        }
+        if let Err(err) = scheduler.sent(chats, &reminder) {
+            println!(
+                "Couldn't record a reminder sent to chat {}: {}",
+                reminder.chat_id, err
+            );
+        }
+    }
+}
+
+/// Moves the calendar store from before each chat had its own into
+/// the chat named by `LEGACY_CAL_CHAT_ENV_VAR`. Refuses to start
+/// without one rather than leave the store's events behind.
+fn adopt_legacy_cal(chats: &mut Chats) {
+    if !Path::new(LEGACY_CAL_FILE).exists() {
+        return;
+    }
+    let chat_id: i64 = std::env::var(LEGACY_CAL_CHAT_ENV_VAR)
+        .ok()
+        .and_then(|x| x.parse().ok())
+        .unwrap_or_else(|| {
+            panic!(
+                "Found a calendar store at {} from before each chat had its own. Set {} to \
+                 the ID of the chat to move its events to, or move the file away to drop them",
+                LEGACY_CAL_FILE, LEGACY_CAL_CHAT_ENV_VAR
+            )
+        });
+    chats
+        .adopt_cal(LEGACY_CAL_FILE, chat_id)
+        .expect("Couldn't move the old calendar store");
+    println!(
+        "Moved the calendar store at {} to chat {}",
+        LEGACY_CAL_FILE, chat_id
+    );
+}
+
+/// Sends a command's reply to a chat.
+fn send_reply<'a, S, F, E>(
+    client: &'a tg::Client<S>,
+    chat_id: i64,
+    reply: Reply,
+) -> Box<dyn Future<Item = (), Error = E> + 'a>
+where
+    S: Fn(String, Option<tg::Body>) -> F,
+    F: 'a + Future<Item = String, Error = E>,
+    E: 'a,
+{
+    let (text, keyboard) = match reply {
+        Reply::Text(text) => (text, None),
+        Reply::Buttons(text, buttons) => {
+            let keyboard = tg::InlineKeyboardMarkup {
+                inline_keyboard: buttons
+                    .into_iter()
+                    .map(|x| {
+                        vec![tg::InlineKeyboardButton {
+                            text: x.label,
+                            callback_data: Some(x.data),
+                        }]
+                    })
+                    .collect(),
+            };
+            (text, Some(keyboard))
+        }
+        Reply::Document(document) => {
+            return Box::new(
+                client
+                    .send_document(tg::SendDocument {
+                        chat_id: chat_id,
+                        document: document,
+                        caption: None,
+                    })
+                    .map(move |result| log_rejected(chat_id, result)),
+            )
+        }
+    };
+
+    // Long replies are sent as several messages, in order, with any
+    // buttons under the last one.
+    let mut messages: Vec<_> = tg::split_message(&text)
+        .into_iter()
+        .map(|x| tg::SendMessage {
+            chat_id: chat_id,
+            text: String::from(x),
+            reply_markup: None,
         })
-        .map(Future::into_stream)
-        .flatten()
-        .wait()
-        .map(Result::unwrap)
-        .for_each(|_| ());
+        .collect();
+    if let Some(last) = messages.last_mut() {
+        last.reply_markup = keyboard;
+    }
+    Box::new(stream::iter_ok(messages).for_each(move |message| {
+        client
+            .send_message(message)
+            .map(move |result| log_rejected(chat_id, result))
+    }))
+}
+
+/// Logs a reply Telegram wouldn't accept. The rest of the reply is
+/// still sent.
+fn log_rejected<T>(chat_id: i64, result: Result<T, ()>) {
+    if result.is_err() {
+        println!("Telegram rejected part of a reply to chat {}", chat_id);
+    }
 }
 
 /// Given the body of a message, parse out the command from the rest
@@ -135,74 +330,57 @@ fn parse_command(text: &str) -> (&str, &str) {
     }
 }
 
-/// Parses out a date, time, duration, and event description from the
-/// message body.
-fn parse_event(text: &str) -> Result<cal::Event, &'static str> {
-    use chrono::Duration;
-
-    const ERROR_MESSAGE: &'static str = "wrong";
-    let mut pieces = text.splitn(3, char::is_whitespace);
-    let date_text = pieces.next().ok_or(ERROR_MESSAGE)?;
-    let time_text = pieces.next().ok_or(ERROR_MESSAGE)?;
-    let description = pieces.next().unwrap_or("");
-
-    let date = NaiveDate::parse_from_str(date_text, "%m/%d/%Y").map_err(|_| ERROR_MESSAGE)?;
-    let time = NaiveTime::parse_from_str(time_text, "%H:%M:%S").map_err(|_| ERROR_MESSAGE)?;
-
-    let tz_datetime = TIMEZONE
-        .from_local_datetime(&NaiveDateTime::new(date, time))
-        .earliest()
-        .ok_or(ERROR_MESSAGE)?;
-    let utc_datetime = Utc.from_utc_datetime(&tz_datetime.naive_utc());
-
-    Ok(cal::Event {
-        organizer: String::new(),
-        description: String::from(description),
-        interval: Interval {
-            start: utc_datetime,
-            end: utc_datetime + Duration::hours(1),
-        },
-    })
-}
-
-fn pretty_print_event(event: &cal::Event) -> String {
-    let mut result = String::new();
-    result.push_str("On ");
-    result.push_str(
-        &event
-            .interval
-            .start
-            .with_timezone(&*TIMEZONE)
-            .format("%-m/%-d/%Y at %H:%M:%S")
-            .to_string(),
-    );
-    result.push_str(":\n");
-    result.push_str(&event.description);
-    result
-}
-
 /// Adapter for using reqwest with futures.
 fn synchronous_send(
     client: &reqwest::Client,
     url: String,
-    body: Option<String>,
+    body: Option<tg::Body>,
 ) -> impl Future<Item = String, Error = reqwest::Error> {
     let mut req = client.get(&url);
-    if let Some(b) = body {
-        req = req
-            .body(b)
-            .header(reqwest::header::CONTENT_TYPE, "application/json");
+    match body {
+        Some(tg::Body::Json(b)) => {
+            req = req
+                .body(b)
+                .header(reqwest::header::CONTENT_TYPE, "application/json");
+        }
+        Some(tg::Body::Form(fields)) => {
+            let mut form = reqwest::multipart::Form::new();
+            for field in fields {
+                form = match field.value {
+                    tg::FormValue::Text(text) => form.text(field.name, text),
+                    tg::FormValue::File(file) => form.part(
+                        field.name,
+                        reqwest::multipart::Part::bytes(file.contents).file_name(file.file_name),
+                    ),
+                };
+            }
+            req = client.post(&url).multipart(form);
+        }
+        None => (),
     }
     future::result::<String, reqwest::Error>(req.send().and_then(|mut resp| resp.text()))
 }
 
-const CAL_FILE: &'static str = "cal";
+/// Holds each chat's calendar and settings.
+const CHATS_DIR: &'static str = "chats";
+
+/// The largest file downloaded for a command, in bytes. Nothing else
+/// is handled while a file downloads.
+const MAX_DOWNLOAD_SIZE: i64 = 1 << 20;
 
 const TOKEN_ENV_VAR: &'static str = "TG_BOT_TOKEN";
 
-lazy_static! {
-    static ref TIMEZONE: chrono::offset::FixedOffset = chrono::offset::FixedOffset::west(7 * 3600);
-}
+/// Where the single calendar store was kept before each chat had its
+/// own. It's moved into the chat given by `LEGACY_CAL_CHAT_ENV_VAR`.
+const LEGACY_CAL_FILE: &'static str = "cal";
+
+const LEGACY_CAL_CHAT_ENV_VAR: &'static str = "LEGACY_CAL_CHAT_ID";
+
+/// Sent in place of the reply to a command that failed.
+const FAILED_REPLY: &'static str = "Sorry, something went wrong. Try again later.";
+
+/// The longest to wait for updates in each poll, in seconds.
+const POLL_TIMEOUT: u64 = 10;
 
 #[cfg(test)]
 mod tests {
@@ -224,56 +402,97 @@ mod tests {
     }
 
     #[test]
-    fn parse_event_correct_datetime() {
-        let body = "1/15/2024 7:53:29 hello world";
-        let event = parse_event(body).unwrap();
+    fn buttons_go_under_the_last_piece() {
+        use std::cell::RefCell;
+
+        let sent = RefCell::new(Vec::new());
+        let client = tg::Client::new(String::new(), |_, body| {
+            sent.borrow_mut().push(body);
+            future::ok::<_, ()>(String::from(
+                r#"{"ok": true, "result": {"message_id": 1, "chat": {"id": 1}}}"#,
+            ))
+        });
+        let button = commands::Button {
+            label: String::from("Book it"),
+            data: String::from("find_time 1 2"),
+        };
+        let text = format!("{}\n{}", "a".repeat(4000), "b".repeat(100));
+        send_reply(&client, 1, Reply::Buttons(text, vec![button]))
+            .wait()
+            .unwrap();
+
+        let keyboard = tg::InlineKeyboardMarkup {
+            inline_keyboard: vec![vec![tg::InlineKeyboardButton {
+                text: String::from("Book it"),
+                callback_data: Some(String::from("find_time 1 2")),
+            }]],
+        };
+        let body = |text: String, reply_markup| {
+            let message = tg::SendMessage {
+                chat_id: 1,
+                text: text,
+                reply_markup: reply_markup,
+            };
+            Some(tg::Body::Json(serde_json::to_string(&message).unwrap()))
+        };
         assert_eq!(
-            event.interval.start,
-            TIMEZONE.ymd(2024, 1, 15).and_hms(7, 53, 29)
+            sent.into_inner(),
+            vec![
+                body("a".repeat(4000), None),
+                body("b".repeat(100), Some(keyboard)),
+            ]
         );
     }
 
     #[test]
-    fn parse_event_description() {
-        let body = "1/1/1 1:1:1 god is dead";
-        let event = parse_event(body).unwrap();
-        assert_eq!(event.description, "god is dead");
-    }
-
-    #[test]
-    fn parse_event_no_description() {
-        let body = "1/1/1 1:1:1";
-        let event = parse_event(body).unwrap();
-        assert_eq!(event.description, "");
-    }
-
-    #[test]
-    fn parse_event_errors() {
-        assert!(parse_event("1/1/ 1:1:1").is_err());
-        assert!(parse_event("1/1/1 1:67:1").is_err());
-        assert!(parse_event("1/1/11:1:1").is_err());
-        assert!(parse_event("1/1/1 i forgot the time").is_err());
-    }
-
-    #[test]
-    fn pretty_print_event_test() {
-        let event = cal::Event {
-            organizer: String::from(""),
-            description: String::from("test description"),
-            interval: Interval {
-                start: TIMEZONE
-                    .ymd(2000, 1, 15)
-                    .and_hms(13, 1, 2)
-                    .with_timezone(&Utc),
-                end: TIMEZONE
-                    .ymd(2000, 1, 15)
-                    .and_hms(13, 1, 2)
-                    .with_timezone(&Utc),
-            },
+    fn broken_chats_get_error_replies() {
+        use std::cell::RefCell;
+
+        extern crate tempfile;
+
+        let dir = tempfile::tempdir().unwrap();
+        let mut chats = Chats::new(dir.path()).unwrap();
+        std::fs::create_dir(dir.path().join("1")).unwrap();
+        std::fs::write(dir.path().join("1").join("settings.json"), "{").unwrap();
+        let mut router = Router::new();
+        commands::builtins::register(&mut router);
+
+        let sent = RefCell::new(Vec::new());
+        let client = tg::Client::new(String::new(), |_, body| {
+            sent.borrow_mut().push(body);
+            future::ok::<_, ()>(String::from(
+                r#"{"ok": true, "result": {"message_id": 1, "chat": {"id": 1}}}"#,
+            ))
+        });
+        let update = |chat_id, text: &str| tg::Update {
+            update_id: 1,
+            message: Some(tg::Message {
+                message_id: 1,
+                chat: tg::Chat { id: chat_id },
+                text: Some(String::from(text)),
+                reply_to_message: None,
+                document: None,
+                caption: None,
+            }),
+            callback_query: None,
         };
-        assert_eq!(
-            pretty_print_event(&event),
-            String::from("On 1/15/2000 at 13:01:02:\ntest description")
-        );
+        let download = |_: &str| None;
+
+        handle_update(update(1, "/today"), &client, &mut chats, &router, &download);
+        let failed = |chat_id| {
+            let message = tg::SendMessage {
+                chat_id: chat_id,
+                text: String::from(FAILED_REPLY),
+                reply_markup: None,
+            };
+            Some(tg::Body::Json(serde_json::to_string(&message).unwrap()))
+        };
+        assert_eq!(sent.replace(Vec::new()), vec![failed(1)]);
+
+        // Other chats are unaffected.
+        handle_update(update(2, "/today"), &client, &mut chats, &router, &download);
+        let replies = sent.replace(Vec::new());
+        assert_eq!(replies.len(), 1);
+        assert!(replies[0] != failed(2));
     }
 }
@@ -0,0 +1,441 @@
+//! Reminds chats of events before they start, and posts the day's
+//! agenda to chats that ask for it. Between polls for updates, the bot
+//! asks a `Scheduler` for the reminders that are due, sends them, and
+//! records each one sent, so that a restart neither sends a reminder
+//! twice nor forgets one that fell due while it was down.
+
+use crate::cal::Cal;
+use crate::cal::Occurrence;
+use crate::chats::Chats;
+use crate::chats::SentReminder;
+use crate::commands::builtins::format_days;
+use crate::local_time;
+
+use std::cmp;
+use std::io;
+
+use chrono::DateTime;
+use chrono::Duration;
+use chrono::NaiveDate;
+use chrono::Utc;
+use chrono_tz::Tz;
+
+/// Tells the time. Anything that returns the time will do, like
+/// `Utc::now`, so tests can say what time it is.
+pub trait Clock {
+    fn now(&self) -> DateTime<Utc>;
+}
+
+impl<F: Fn() -> DateTime<Utc>> Clock for F {
+    fn now(&self) -> DateTime<Utc> {
+        self()
+    }
+}
+
+/// A reminder that's due to be sent.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct Reminder {
+    pub chat_id: i64,
+    /// When the reminder fell due.
+    pub due: DateTime<Utc>,
+    pub subject: Subject,
+    pub text: String,
+}
+
+/// What a reminder is for, as recorded once it's sent.
+#[derive(Clone, Copy, Debug, Eq, Ord, PartialEq, PartialOrd)]
+pub enum Subject {
+    /// An occurrence of an event, reminded of before it starts.
+    Occurrence(SentReminder),
+    /// The agenda for a day, posted at the chat's digest time.
+    Digest(NaiveDate),
+}
+
+/// The most minutes before an event that a chat can be reminded of
+/// it. Only events starting this soon are looked at.
+pub const MAX_REMIND_MINUTES: u32 = 7 * 24 * 60;
+
+/// Finds reminders that are due across all chats.
+pub struct Scheduler<C> {
+    clock: C,
+}
+
+impl<C: Clock> Scheduler<C> {
+    pub fn new(clock: C) -> Scheduler<C> {
+        Scheduler { clock: clock }
+    }
+
+    /// Gets the reminders that are due and haven't been sent, in the
+    /// order they fell due. Reminders for occurrences that have started
+    /// are never due, even if they weren't sent in time. Chats that
+    /// can't be loaded are logged and skipped.
+    pub fn due(&self, chats: &mut Chats) -> io::Result<Vec<Reminder>> {
+        let now = self.clock.now();
+        let mut due = Vec::new();
+        for chat_id in chats.chat_ids()? {
+            match pending(chats, chat_id, now, now) {
+                Ok(pending) => due.extend(pending),
+                Err(err) => println!("Couldn't check chat {} for reminders: {}", chat_id, err),
+            }
+        }
+        due.sort_by_key(|x| (x.due, x.chat_id, x.subject));
+        Ok(due)
+    }
+
+    /// Gets how long until the next reminder falls due, or `max` if
+    /// none does sooner. Reminders already due are due now.
+    pub fn time_until_due(&self, chats: &mut Chats, max: Duration) -> io::Result<Duration> {
+        let now = self.clock.now();
+        let mut next = now + max;
+        for chat_id in chats.chat_ids()? {
+            // Chats that can't be loaded are skipped, and `due` logs them.
+            let pending = match pending(chats, chat_id, now, next) {
+                Ok(pending) => pending,
+                Err(_) => continue,
+            };
+            if let Some(first) = pending.iter().map(|x| x.due).min() {
+                next = cmp::min(next, first);
+            }
+        }
+        Ok(cmp::max(next - now, Duration::zero()))
+    }
+
+    /// Records that a reminder was sent, so that it isn't again.
+    pub fn sent(&self, chats: &mut Chats, reminder: &Reminder) -> io::Result<()> {
+        match reminder.subject {
+            Subject::Occurrence(occurrence) => {
+                chats.reminder_sent(reminder.chat_id, occurrence, self.clock.now())
+            }
+            Subject::Digest(date) => chats.digest_sent(reminder.chat_id, date),
+        }
+    }
+}
+
+/// Gets a chat's reminders that fall due by `until` and haven't been
+/// sent, for occurrences starting after `now`, along with the digests
+/// due by then that haven't been sent.
+fn pending(
+    chats: &mut Chats,
+    chat_id: i64,
+    now: DateTime<Utc>,
+    until: DateTime<Utc>,
+) -> io::Result<Vec<Reminder>> {
+    let settings = chats.settings(chat_id)?.clone();
+    let sent = chats.sent_reminders(chat_id)?.clone();
+    let last_digest = chats.last_digest(chat_id)?;
+    let cal = chats.cal(chat_id)?.get_cal();
+    let zone = settings.timezone;
+
+    let horizon = until + Duration::minutes(i64::from(MAX_REMIND_MINUTES));
+    let mut pending: Vec<_> = cal
+        .events_overlapping(now..horizon)
+        .filter(|x| x.interval.start > now)
+        .filter_map(|x| {
+            let minutes = x
+                .event
+                .remind_minutes_before
+                .or(settings.remind_minutes_before)?;
+            let due = x.interval.start - Duration::minutes(i64::from(minutes));
+            let occurrence = SentReminder {
+                event: x.id,
+                start: x.interval.start,
+            };
+            if due > until || sent.contains(&occurrence) {
+                return None;
+            }
+            Some(Reminder {
+                chat_id: chat_id,
+                due: due,
+                subject: Subject::Occurrence(occurrence),
+                text: reminder_text(&x, &zone),
+            })
+        })
+        .collect();
+
+    if let Some(time) = settings.digest {
+        // Tomorrow's can fall due before `until` late in the day.
+        let today = now.with_timezone(&zone).date().naive_local();
+        for &date in &[today, today.succ()] {
+            let due = local_time::to_utc_nearest(&zone, date.and_time(time));
+            if due > until || last_digest >= Some(date) {
+                continue;
+            }
+            pending.push(Reminder {
+                chat_id: chat_id,
+                due: due,
+                subject: Subject::Digest(date),
+                text: digest_text(cal, &zone, date),
+            });
+        }
+    }
+    Ok(pending)
+}
+
+/// Lists a day's events in a digest, the same way /today does.
+fn digest_text(cal: &Cal, zone: &Tz, date: NaiveDate) -> String {
+    let range = local_time::day_range(zone, date);
+    let agenda = format_days(cal.events_overlapping(range), zone, date);
+    if agenda.is_empty() {
+        format!("{}\nNo events", date.format("%A %-m/%-d"))
+    } else {
+        agenda
+    }
+}
+
+/// Describes an occurrence in a reminder, like "Reminder: lunch,
+/// Wednesday 10/16 at 15:00".
+fn reminder_text(occurrence: &Occurrence, zone: &Tz) -> String {
+    let when = match occurrence.days() {
+        Some(days) => days.start.format("all day %A %-m/%-d").to_string(),
+        None => occurrence
+            .interval
+            .start
+            .with_timezone(zone)
+            .format("%A %-m/%-d at %H:%M")
+            .to_string(),
+    };
+    format!("Reminder: {}, {}", occurrence.event.description, when)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::cal::interval::Interval;
+    use crate::cal::recurrence::Frequency;
+    use crate::cal::recurrence::Recurrence;
+    use crate::cal::Event;
+    use crate::chats::Settings;
+
+    use std::cell::Cell;
+
+    use chrono::NaiveTime;
+    use chrono::TimeZone;
+
+    extern crate tempfile;
+
+    fn at(day: u32, hour: u32, minute: u32) -> DateTime<Utc> {
+        Utc.ymd(2019, 10, day).and_hms(hour, minute, 0)
+    }
+
+    fn event(description: &str, start: DateTime<Utc>) -> Event {
+        Event {
+            organizer: String::new(),
+            description: String::from(description),
+            interval: Interval {
+                start: start,
+                end: start + Duration::hours(1),
+            },
+            recurrence: None,
+            all_day: None,
+            remind_minutes_before: None,
+        }
+    }
+
+    fn remind_by_default(chats: &mut Chats, chat_id: i64, minutes: u32) {
+        let settings = Settings {
+            timezone: chrono_tz::UTC,
+            remind_minutes_before: Some(minutes),
+            ..Settings::default()
+        };
+        chats.set_settings(chat_id, settings).unwrap();
+    }
+
+    fn texts(reminders: &[Reminder]) -> Vec<&str> {
+        reminders.iter().map(|x| x.text.as_str()).collect()
+    }
+
+    #[test]
+    fn reminders_fall_due_before_events() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut chats = Chats::new(dir.path()).unwrap();
+        remind_by_default(&mut chats, 1, 10);
+        let cal = chats.cal(1).unwrap();
+        cal.add_event(event("lunch", at(16, 12, 0))).unwrap();
+        cal.add_event(Event {
+            remind_minutes_before: Some(25 * 60),
+            ..event("dentist", at(17, 9, 0))
+        })
+        .unwrap();
+        // 9:00 every day.
+        cal.add_event(Event {
+            recurrence: Some(Recurrence::new(Frequency::Daily)),
+            ..event("standup", at(1, 9, 0))
+        })
+        .unwrap();
+        // Chats without a default are only reminded of events that ask.
+        chats
+            .cal(2)
+            .unwrap()
+            .add_event(event("nap", at(16, 12, 0)))
+            .unwrap();
+
+        let now = Cell::new(at(16, 7, 0));
+        let scheduler = Scheduler::new(|| now.get());
+        assert_eq!(scheduler.due(&mut chats).unwrap(), vec![]);
+        assert_eq!(
+            scheduler
+                .time_until_due(&mut chats, Duration::hours(2))
+                .unwrap(),
+            Duration::hours(1)
+        );
+
+        now.set(at(16, 8, 55));
+        let due = scheduler.due(&mut chats).unwrap();
+        assert_eq!(
+            texts(&due),
+            vec![
+                "Reminder: dentist, Thursday 10/17 at 09:00",
+                "Reminder: standup, Wednesday 10/16 at 09:00",
+            ]
+        );
+        assert_eq!(due[1].due, at(16, 8, 50));
+        for reminder in &due {
+            scheduler.sent(&mut chats, reminder).unwrap();
+        }
+        assert_eq!(scheduler.due(&mut chats).unwrap(), vec![]);
+        assert_eq!(
+            scheduler
+                .time_until_due(&mut chats, Duration::hours(24))
+                .unwrap(),
+            Duration::minutes(175)
+        );
+
+        // Lunch's reminder was missed, but it hasn't started yet.
+        now.set(at(16, 11, 55));
+        assert_eq!(
+            texts(&scheduler.due(&mut chats).unwrap()),
+            vec!["Reminder: lunch, Wednesday 10/16 at 12:00"]
+        );
+        assert_eq!(
+            scheduler
+                .time_until_due(&mut chats, Duration::hours(1))
+                .unwrap(),
+            Duration::zero()
+        );
+        now.set(at(16, 12, 0));
+        assert_eq!(scheduler.due(&mut chats).unwrap(), vec![]);
+    }
+
+    #[test]
+    fn digests_are_posted_daily() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut chats = Chats::new(dir.path()).unwrap();
+        let settings = Settings {
+            timezone: chrono_tz::UTC,
+            digest: Some(NaiveTime::from_hms(8, 0, 0)),
+            ..Settings::default()
+        };
+        chats.set_settings(1, settings).unwrap();
+        let cal = chats.cal(1).unwrap();
+        cal.add_event(event("lunch", at(16, 12, 0))).unwrap();
+        cal.add_event(event("party", at(15, 23, 30))).unwrap();
+
+        let now = Cell::new(at(16, 7, 0));
+        let scheduler = Scheduler::new(|| now.get());
+        assert_eq!(scheduler.due(&mut chats).unwrap(), vec![]);
+        assert_eq!(
+            scheduler
+                .time_until_due(&mut chats, Duration::hours(2))
+                .unwrap(),
+            Duration::hours(1)
+        );
+
+        now.set(at(16, 8, 0));
+        let due = scheduler.due(&mut chats).unwrap();
+        // Events still going from the day before are listed too.
+        assert_eq!(
+            texts(&due),
+            vec!["Wednesday 10/16\n10/15 23:30-00:30: party\n12:00-13:00: lunch"]
+        );
+        scheduler.sent(&mut chats, &due[0]).unwrap();
+        drop(chats);
+
+        let mut chats = Chats::new(dir.path()).unwrap();
+        assert_eq!(scheduler.due(&mut chats).unwrap(), vec![]);
+        assert_eq!(
+            scheduler
+                .time_until_due(&mut chats, Duration::hours(48))
+                .unwrap(),
+            Duration::hours(24)
+        );
+
+        // A digest missed while the bot was down is posted late.
+        now.set(at(17, 9, 0));
+        assert_eq!(
+            texts(&scheduler.due(&mut chats).unwrap()),
+            vec!["Thursday 10/17\nNo events"]
+        );
+    }
+
+    #[test]
+    fn broken_chats_are_skipped() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut chats = Chats::new(dir.path()).unwrap();
+        remind_by_default(&mut chats, 2, 10);
+        chats
+            .cal(2)
+            .unwrap()
+            .add_event(event("lunch", at(16, 12, 0)))
+            .unwrap();
+        std::fs::create_dir(dir.path().join("1")).unwrap();
+        std::fs::write(dir.path().join("1").join("reminders.json"), "[").unwrap();
+
+        let now = Cell::new(at(16, 11, 0));
+        let scheduler = Scheduler::new(|| now.get());
+        assert_eq!(
+            scheduler
+                .time_until_due(&mut chats, Duration::hours(2))
+                .unwrap(),
+            Duration::minutes(50)
+        );
+        now.set(at(16, 11, 50));
+        assert_eq!(
+            texts(&scheduler.due(&mut chats).unwrap()),
+            vec!["Reminder: lunch, Wednesday 10/16 at 12:00"]
+        );
+    }
+
+    #[test]
+    fn sent_reminders_survive_restarts() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut chats = Chats::new(dir.path()).unwrap();
+        remind_by_default(&mut chats, 1, 60);
+        let all_day = crate::cal::AllDay {
+            start: chrono::NaiveDate::from_ymd(2019, 10, 17),
+            end: chrono::NaiveDate::from_ymd(2019, 10, 18),
+            zone: chrono_tz::UTC,
+        };
+        chats
+            .cal(1)
+            .unwrap()
+            .add_event(Event {
+                interval: all_day.interval(),
+                all_day: Some(all_day),
+                ..event("offsite", at(17, 0, 0))
+            })
+            .unwrap();
+        chats
+            .cal(1)
+            .unwrap()
+            .add_event(event("breakfast", at(17, 8, 0)))
+            .unwrap();
+
+        let scheduler = Scheduler::new(|| at(16, 23, 30));
+        let due = scheduler.due(&mut chats).unwrap();
+        assert_eq!(
+            texts(&due),
+            vec!["Reminder: offsite, all day Thursday 10/17"]
+        );
+        scheduler.sent(&mut chats, &due[0]).unwrap();
+        drop(chats);
+
+        let mut chats = Chats::new(dir.path()).unwrap();
+        assert_eq!(scheduler.due(&mut chats).unwrap(), vec![]);
+        let scheduler = Scheduler::new(|| at(17, 7, 0));
+        assert_eq!(
+            texts(&scheduler.due(&mut chats).unwrap()),
+            vec!["Reminder: breakfast, Thursday 10/17 at 08:00"]
+        );
+    }
+}
@@ -10,6 +10,14 @@ use futures::Future;
 use serde::de::DeserializeOwned;
 use serde::ser::Serialize;
 
+const BASE_URL: &'static str = "https://api.telegram.org/";
+
+/// The most UTF-16 code units Telegram accepts in one message.
+pub const MAX_MESSAGE_LEN: usize = 4096;
+
+/// The most bytes of callback data Telegram accepts with a button.
+pub const MAX_CALLBACK_DATA_LEN: usize = 64;
+
 /// A client for the Telegram Bot API. Methods correspond to API
 /// calls.
 pub struct Client<S> {
@@ -27,9 +35,29 @@ fn to_result<T>(r: Response<T>) -> Result<T> {
     }
 }
 
+/// The body of an API request.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub enum Body {
+    Json(String),
+    /// A multipart/form-data body, needed to upload files.
+    Form(Vec<FormField>),
+}
+
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct FormField {
+    pub name: String,
+    pub value: FormValue,
+}
+
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub enum FormValue {
+    Text(String),
+    File(InputFile),
+}
+
 impl<S, F, E> Client<S>
 where
-    S: Fn(String, Option<String>) -> F,
+    S: Fn(String, Option<Body>) -> F,
     F: Future<Item = String, Error = E>,
 {
     /// Creates a new `Client`. `token` is the bot token given by the
@@ -58,6 +86,61 @@ where
         self.request("sendMessage", Some(arg)).map(to_result)
     }
 
+    pub fn send_document(
+        &self,
+        arg: SendDocument,
+    ) -> impl Future<Item = Result<Message>, Error = E> {
+        let mut fields = vec![FormField {
+            name: String::from("chat_id"),
+            value: FormValue::Text(arg.chat_id.to_string()),
+        }];
+        if let Some(caption) = arg.caption {
+            fields.push(FormField {
+                name: String::from("caption"),
+                value: FormValue::Text(caption),
+            });
+        }
+        fields.push(FormField {
+            name: String::from("document"),
+            value: FormValue::File(arg.document),
+        });
+
+        self.send_body("sendDocument", Some(Body::Form(fields)))
+            .map(to_result)
+    }
+
+    pub fn answer_callback_query(
+        &self,
+        arg: AnswerCallbackQuery,
+    ) -> impl Future<Item = Result<bool>, Error = E> {
+        self.request("answerCallbackQuery", Some(arg))
+            .map(to_result)
+    }
+
+    /// Sets the commands Telegram suggests to users.
+    pub fn set_my_commands(
+        &self,
+        arg: SetMyCommands,
+    ) -> impl Future<Item = Result<bool>, Error = E> {
+        self.request("setMyCommands", Some(arg)).map(to_result)
+    }
+
+    pub fn get_file(&self, arg: GetFile) -> impl Future<Item = Result<File>, Error = E> {
+        self.request("getFile", Some(arg)).map(to_result)
+    }
+
+    /// Downloads the contents of a file, where `file_path` comes from
+    /// the `File` returned by `get_file`.
+    pub fn download_file(&self, file_path: &str) -> impl Future<Item = String, Error = E> {
+        let mut url_str = String::from(BASE_URL);
+        url_str.push_str("file/bot");
+        url_str.push_str(&self.token);
+        url_str.push('/');
+        url_str.push_str(file_path);
+
+        (self.send)(url_str, None)
+    }
+
     /// Fires off an API request, where `method` is the API method
     /// (e.g. "getUpdates" or "sendMessage").
     fn request<T, U>(&self, method: &str, body: Option<T>) -> impl Future<Item = U, Error = E>
@@ -65,32 +148,75 @@ where
         T: Serialize,
         U: DeserializeOwned,
     {
-        const BASE_URL: &'static str = "https://api.telegram.org/";
+        let body_string = body.as_ref().map(|o| serde_json::to_string(o).unwrap());
+        self.send_body(method, body_string.map(Body::Json))
+    }
 
+    /// Like `request`, but with an already encoded body.
+    fn send_body<U>(&self, method: &str, body: Option<Body>) -> impl Future<Item = U, Error = E>
+    where
+        U: DeserializeOwned,
+    {
         let mut url_str = String::from(BASE_URL);
         url_str.push_str("bot");
         url_str.push_str(&self.token);
         url_str.push('/');
         url_str.push_str(method);
 
-        let body_string = body.as_ref().map(|o| serde_json::to_string(o).unwrap());
-
-        (self.send)(url_str, body_string)
+        (self.send)(url_str, body)
             .map(|s| serde_json::from_str(&s).expect("Received invalid JSON response"))
     }
 }
 
+/// Splits `text` into pieces short enough to send as messages.
+/// Pieces end at blank lines where possible, then at line breaks, so
+/// that paragraphs and lines are kept whole.
+pub fn split_message(text: &str) -> Vec<&str> {
+    split_text(text, MAX_MESSAGE_LEN)
+}
+
+fn split_text(mut text: &str, max_len: usize) -> Vec<&str> {
+    let mut pieces = Vec::new();
+    while let Some(limit) = utf16_limit(text, max_len) {
+        let head = &text[..limit];
+        let (end, next) = match head.rfind("\n\n") {
+            Some(ndx) if ndx > 0 => (ndx, ndx + 2),
+            _ => match head.rfind('\n') {
+                Some(ndx) if ndx > 0 => (ndx, ndx + 1),
+                _ => (limit, limit),
+            },
+        };
+        pieces.push(&text[..end]);
+        text = &text[next..];
+    }
+    pieces.push(text);
+    pieces
+}
+
+/// Gets the byte index in `text` of the first character past `max_len`
+/// UTF-16 code units, or `None` if all of `text` fits.
+fn utf16_limit(text: &str, max_len: usize) -> Option<usize> {
+    let mut len = 0;
+    text.char_indices()
+        .find(|(_, c)| {
+            len += c.len_utf16();
+            len > max_len
+        })
+        .map(|(ndx, _)| ndx)
+}
+
 /// Gets a `Stream` of updates from the API.
 ///
 /// This relieves the user of stringing together `Future`s, dealing
 /// with the `Vec` in the return type of `Client::get_updates`, and of
 /// passing the correct update offset each time.
+#[allow(dead_code)]
 pub fn update_stream<'a, S, F, E>(
     client: &'a Client<S>,
     poll_timeout: u64,
 ) -> impl Stream<Item = Update, Error = E> + 'a
 where
-    S: Fn(String, Option<String>) -> F,
+    S: Fn(String, Option<Body>) -> F,
     F: 'a + Future<Item = String, Error = E>,
     E: 'a,
 {
@@ -99,6 +225,24 @@ where
     update_stream_impl(get_updates_fn, poll_timeout)
 }
 
+/// Gets a `Stream` of the updates from each poll of the API, which
+/// may be none. `poll_timeout` is called before each poll, so that the
+/// caller can be woken sooner when it has other work to do.
+pub fn update_batches<'a, S, F, E, T>(
+    client: &'a Client<S>,
+    poll_timeout: T,
+) -> impl Stream<Item = Vec<Update>, Error = E> + 'a
+where
+    S: Fn(String, Option<Body>) -> F,
+    F: 'a + Future<Item = String, Error = E>,
+    E: 'a,
+    T: 'a + Fn() -> u64,
+{
+    let get_updates_fn =
+        move |updates_req: GetUpdates| client.get_updates(updates_req).map(Result::unwrap);
+    update_batches_impl(get_updates_fn, poll_timeout)
+}
+
 /// Does the actual work of `update_stream` but without depending on
 /// `Client` for testability.
 fn update_stream_impl<G, U, E>(
@@ -109,24 +253,40 @@ where
     G: Fn(GetUpdates) -> U,
     U: Future<Item = Vec<Update>, Error = E>,
 {
-    assert_ne!(poll_timeout, 0);
+    update_batches_impl(get_updates, move || poll_timeout)
+        .map(stream::iter_ok)
+        .flatten()
+}
 
+/// Does the actual work of `update_batches` but without depending on
+/// `Client` for testability.
+fn update_batches_impl<G, U, E, T>(
+    get_updates: G,
+    poll_timeout: T,
+) -> impl Stream<Item = Vec<Update>, Error = E>
+where
+    G: Fn(GetUpdates) -> U,
+    U: Future<Item = Vec<Update>, Error = E>,
+    T: Fn() -> u64,
+{
     stream::unfold(None, move |offset| {
+        let timeout = poll_timeout();
+        assert_ne!(timeout, 0);
         let updates_req = GetUpdates {
             offset: offset,
             limit: Some(100),
-            timeout: Some(poll_timeout as _),
+            timeout: Some(timeout as _),
             allowed_updates: None,
         };
 
-        Some(get_updates(updates_req).map(|updates| {
+        Some(get_updates(updates_req).map(move |updates| {
             // We need to get the last update ID to pass the
-            // correct offset on the next get_updates() call.
-            let next_offset = updates.last().map(|u| u.update_id + 1);
-            (stream::iter_ok(updates), next_offset)
+            // correct offset on the next get_updates() call, and
+            // keep the last one if there were no updates.
+            let next_offset = updates.last().map(|u| u.update_id + 1).or(offset);
+            (updates, next_offset)
         }))
     })
-    .flatten()
 }
 
 #[cfg(test)]
@@ -181,6 +341,37 @@ mod tests {
         assert!(*&*last_requested_offset.borrow() == Some(4));
     }
 
+    #[test]
+    /// Makes sure that each poll is a batch, even if it's empty, and
+    /// asks for the timeout it's given at the time.
+    fn update_batches_poll_with_timeout() {
+        let timeouts = RefCell::new(Vec::new());
+        let offsets = RefCell::new(Vec::new());
+        let get_updates = |updates_req: GetUpdates| {
+            timeouts.borrow_mut().push(updates_req.timeout.unwrap());
+            offsets.borrow_mut().push(updates_req.offset);
+            let updates = match updates_req.offset {
+                None => vec![Update {
+                    update_id: 7,
+                    ..Default::default()
+                }],
+                Some(_) => vec![],
+            };
+            future::ok::<_, ()>(updates)
+        };
+
+        let timeout = RefCell::new(10);
+        let mut batches = update_batches_impl(get_updates, || *timeout.borrow())
+            .wait()
+            .map(Result::unwrap);
+        assert_eq!(batches.next().unwrap().len(), 1);
+        timeout.replace(3);
+        assert_eq!(batches.next().unwrap().len(), 0);
+        assert_eq!(batches.next().unwrap().len(), 0);
+        assert_eq!(*timeouts.borrow(), vec![10, 3, 3]);
+        assert_eq!(*offsets.borrow(), vec![None, Some(8), Some(8)]);
+    }
+
     #[test]
     /// Tests that `request` formats its request correctly.
     fn request_format() {
@@ -192,9 +383,9 @@ mod tests {
 
         // Our `send` implementation that will verify what `request`
         // sends.
-        let mock_send = |url: String, body_string: Option<String>| {
+        let mock_send = |url: String, body_string: Option<Body>| {
             assert_eq!(url.as_str(), EXPECTED_URL);
-            body_string.map(|s| assert_eq!(s, serde_json::to_string(&body).unwrap()));
+            body_string.map(|s| assert_eq!(s, Body::Json(serde_json::to_string(&body).unwrap())));
 
             future::ok::<String, ()>(serde_json::to_string(&()).unwrap())
         };
@@ -218,7 +409,7 @@ mod tests {
 
         let expected_result = Fromble { n: 1, b: true };
 
-        let stub_send = |_: String, _: Option<String>| {
+        let stub_send = |_: String, _: Option<Body>| {
             future::ok::<String, ()>(serde_json::to_string(&expected_result).unwrap())
         };
 
@@ -227,4 +418,136 @@ mod tests {
 
         assert_eq!(result, expected_result);
     }
+
+    #[test]
+    /// Tests that `download_file` fetches from the file URL rather
+    /// than calling a method.
+    fn download_file_url() {
+        let mock_send = |url: String, body: Option<Body>| {
+            assert_eq!(
+                url.as_str(),
+                "https://api.telegram.org/file/bot123:abc-xyz/documents/file_1.ics"
+            );
+            assert_eq!(body, None);
+
+            future::ok::<String, ()>(String::from("BEGIN:VCALENDAR"))
+        };
+
+        let client = Client::new(String::from("123:abc-xyz"), mock_send);
+        let contents = client.download_file("documents/file_1.ics").wait().unwrap();
+        assert_eq!(contents, "BEGIN:VCALENDAR");
+    }
+
+    #[test]
+    /// Tests that `send_document` uploads the file as a form.
+    fn send_document_format() {
+        let mock_send = |url: String, body: Option<Body>| {
+            assert!(url.ends_with("/sendDocument"));
+            assert_eq!(
+                body,
+                Some(Body::Form(vec![
+                    FormField {
+                        name: String::from("chat_id"),
+                        value: FormValue::Text(String::from("-42")),
+                    },
+                    FormField {
+                        name: String::from("document"),
+                        value: FormValue::File(InputFile {
+                            file_name: String::from("a.txt"),
+                            contents: b"hi".to_vec(),
+                        }),
+                    },
+                ]))
+            );
+
+            future::ok::<String, ()>(String::from(r#"{"ok":false}"#))
+        };
+
+        let client = Client::new(String::from(""), mock_send);
+        let result = client
+            .send_document(SendDocument {
+                chat_id: -42,
+                document: InputFile {
+                    file_name: String::from("a.txt"),
+                    contents: b"hi".to_vec(),
+                },
+                caption: None,
+            })
+            .wait()
+            .unwrap();
+        assert!(result.is_err());
+    }
+
+    #[test]
+    /// Tests that `set_my_commands` sends the commands as JSON.
+    fn set_my_commands_format() {
+        let mock_send = |url: String, body: Option<Body>| {
+            assert!(url.ends_with("/setMyCommands"));
+            assert_eq!(
+                body,
+                Some(Body::Json(String::from(
+                    r#"{"commands":[{"command":"help","description":"Lists these commands"}]}"#
+                )))
+            );
+
+            future::ok::<String, ()>(String::from(r#"{"ok":true,"result":true}"#))
+        };
+
+        let client = Client::new(String::from(""), mock_send);
+        let result = client
+            .set_my_commands(SetMyCommands {
+                commands: vec![BotCommand {
+                    command: String::from("help"),
+                    description: String::from("Lists these commands"),
+                }],
+            })
+            .wait()
+            .unwrap();
+        assert_eq!(result, Ok(true));
+    }
+
+    #[test]
+    fn split_text_test() {
+        assert_eq!(split_text("", 5), vec![""]);
+        assert_eq!(split_text("abcde", 5), vec!["abcde"]);
+        assert_eq!(split_text("ab\n\ncd\nef", 8), vec!["ab", "cd\nef"]);
+        assert_eq!(split_text("ab\ncd\nef", 6), vec!["ab\ncd", "ef"]);
+        assert_eq!(split_text("abcdefgh", 3), vec!["abc", "def", "gh"]);
+        // Lengths are in UTF-16 code units, as Telegram counts them,
+        // not bytes or characters.
+        assert_eq!(split_text("äöüß", 2), vec!["äö", "üß"]);
+        assert_eq!(split_text("😀😀😀", 4), vec!["😀😀", "😀"]);
+        assert_eq!(split_text("a😀b", 2), vec!["a", "😀", "b"]);
+        let text = "😀".repeat(MAX_MESSAGE_LEN);
+        let pieces = split_message(&text);
+        assert_eq!(pieces.len(), 2);
+        assert!(pieces
+            .iter()
+            .all(|x| x.encode_utf16().count() <= MAX_MESSAGE_LEN));
+    }
+
+    #[test]
+    /// Tests that buttons are only sent when there are some.
+    fn send_message_format() {
+        let mut message = SendMessage {
+            chat_id: 1,
+            text: String::from("hi"),
+            reply_markup: None,
+        };
+        assert_eq!(
+            serde_json::to_string(&message).unwrap(),
+            r#"{"chat_id":1,"text":"hi"}"#
+        );
+
+        message.reply_markup = Some(InlineKeyboardMarkup {
+            inline_keyboard: vec![vec![InlineKeyboardButton {
+                text: String::from("Book it"),
+                callback_data: Some(String::from("find_time 1 2")),
+            }]],
+        });
+        assert_eq!(
+            serde_json::to_string(&message).unwrap(),
+            r#"{"chat_id":1,"text":"hi","reply_markup":{"inline_keyboard":[[{"text":"Book it","callback_data":"find_time 1 2"}]]}}"#
+        );
+    }
 }
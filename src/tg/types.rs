@@ -22,6 +22,7 @@ pub struct User {
 pub struct Update {
     pub update_id: i64,
     pub message: Option<Message>,
+    pub callback_query: Option<CallbackQuery>,
 }
 
 #[derive(Clone, Debug, Default, Deserialize, Serialize)]
@@ -41,6 +42,53 @@ pub struct Chat {
 pub struct SendMessage {
     pub chat_id: i64,
     pub text: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub reply_markup: Option<InlineKeyboardMarkup>,
+}
+
+/// Buttons shown under a message, as rows of buttons.
+#[derive(Clone, Debug, Default, Deserialize, Eq, PartialEq, Serialize)]
+pub struct InlineKeyboardMarkup {
+    pub inline_keyboard: Vec<Vec<InlineKeyboardButton>>,
+}
+
+#[derive(Clone, Debug, Default, Deserialize, Eq, PartialEq, Serialize)]
+pub struct InlineKeyboardButton {
+    pub text: String,
+    /// Sent back in a `CallbackQuery` when the button is pressed. At
+    /// most 64 bytes.
+    pub callback_data: Option<String>,
+}
+
+/// A press of an `InlineKeyboardButton`.
+#[derive(Clone, Debug, Default, Deserialize, Serialize)]
+pub struct CallbackQuery {
+    pub id: String,
+    pub from: User,
+    /// The message the button was under, if it isn't too old.
+    pub message: Option<Message>,
+    pub data: Option<String>,
+}
+
+/// Tells Telegram a `CallbackQuery` was handled, so the client stops
+/// showing progress. `text` is shown to the user as a notification.
+#[derive(Clone, Debug, Default, Deserialize, Serialize)]
+pub struct AnswerCallbackQuery {
+    pub callback_query_id: String,
+    pub text: Option<String>,
+}
+
+/// A command shown in Telegram's command menu.
+#[derive(Clone, Debug, Default, Deserialize, Eq, PartialEq, Serialize)]
+pub struct BotCommand {
+    /// The command's name, without the slash.
+    pub command: String,
+    pub description: String,
+}
+
+#[derive(Clone, Debug, Default, Serialize)]
+pub struct SetMyCommands {
+    pub commands: Vec<BotCommand>,
 }
 
 #[derive(Clone, Debug, Default, Deserialize, Serialize)]
@@ -48,4 +96,45 @@ pub struct Message {
     pub message_id: i64,
     pub chat: Chat,
     pub text: Option<String>,
+    pub reply_to_message: Option<Box<Message>>,
+    pub document: Option<Document>,
+    /// The caption of a document or other media.
+    pub caption: Option<String>,
+}
+
+#[derive(Clone, Debug, Default, Deserialize, Serialize)]
+pub struct Document {
+    pub file_id: String,
+    pub file_name: Option<String>,
+    pub mime_type: Option<String>,
+    pub file_size: Option<i64>,
+}
+
+#[derive(Clone, Debug, Default, Deserialize, Serialize)]
+pub struct GetFile {
+    pub file_id: String,
+}
+
+/// A file ready to be downloaded with `Client::download_file`.
+#[derive(Clone, Debug, Default, Deserialize, Serialize)]
+pub struct File {
+    pub file_id: String,
+    pub file_size: Option<i64>,
+    pub file_path: Option<String>,
+}
+
+/// Arguments to `sendDocument`. This is sent as a form rather than as
+/// JSON, so it isn't `Serialize`.
+#[derive(Clone, Debug, Default)]
+pub struct SendDocument {
+    pub chat_id: i64,
+    pub document: InputFile,
+    pub caption: Option<String>,
+}
+
+/// A file to upload.
+#[derive(Clone, Debug, Default, Eq, PartialEq)]
+pub struct InputFile {
+    pub file_name: String,
+    pub contents: Vec<u8>,
 }
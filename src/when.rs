@@ -0,0 +1,1160 @@
+//! Parsing of the dates and times people type, like "tomorrow 3pm",
+//! "next friday at noon for 2h" or "oct 20 14:30-15:15".
+//!
+//! An expression has a date, a time or "all day", and a length, each
+//! optional and in any order. Parsing stops at the first word that
+//! doesn't fit, or that repeats a part already given, and the rest of
+//! the text is left for the caller (e.g. as an event's description).
+//!
+//! Relative dates are resolved against the current local time:
+//! "friday" is the next Friday on or after today, "next friday" the
+//! next one after today, and dates without a year the next such date
+//! on or after today.
+
+use std::fmt;
+use std::ops::Range;
+
+use chrono::Datelike;
+use chrono::Duration;
+use chrono::NaiveDate;
+use chrono::NaiveDateTime;
+use chrono::NaiveTime;
+use chrono::Timelike;
+use chrono::Weekday;
+
+/// When something happens, in local time.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub struct When {
+    pub date: NaiveDate,
+    /// The start time, if one was given.
+    pub time: Option<NaiveTime>,
+    /// Whether it was marked as lasting all day, in which case there's
+    /// no time.
+    pub all_day: bool,
+    pub length: Option<Length>,
+}
+
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum Length {
+    /// Lasts until the given local time.
+    Until(NaiveDateTime),
+    /// Lasts for the given amount of time.
+    For(Duration),
+}
+
+/// Why a date expression couldn't be understood, or couldn't be
+/// turned into times. Each carries the piece of text it's about.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub enum ParseError {
+    /// The text doesn't start with a date or time. Carries the word it
+    /// starts with instead, if there is one.
+    MissingWhen(Option<Span>),
+    /// A date that's written properly but doesn't exist, like "feb 30"
+    /// or "13/1".
+    NoSuchDate(Span),
+    /// A 12-hour time with an hour that isn't 1 to 12, like "13pm".
+    BadTime(Span),
+    /// A 24-hour time with a field out of range, like "24:00" or
+    /// "9:60".
+    TimeOutOfRange(Span),
+    /// A date without a time or "all day".
+    MissingTime(Span),
+    /// A local time that happens twice because the clocks go back.
+    AmbiguousTime(Span),
+    /// A local time that's skipped because the clocks go forward.
+    NonexistentTime(Span),
+    /// An all-day length that isn't a whole number of days.
+    PartialDays(Span),
+    /// An end that's at or before the start.
+    EndsBeforeStart(Span),
+    /// A date or length too far away to handle, like "in 99999 weeks".
+    OutOfRange(Span),
+}
+
+/// A piece of the text given to `parse`.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct Span {
+    /// Where it starts, in bytes.
+    pub start: usize,
+    pub text: String,
+}
+
+impl Span {
+    pub fn new(text: &str, range: Range<usize>) -> Span {
+        Span {
+            start: range.start,
+            text: String::from(&text[range]),
+        }
+    }
+}
+
+impl fmt::Display for ParseError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            ParseError::MissingWhen(None) => {
+                write!(f, "Expected a date or time, like \"tomorrow 3pm\"")
+            }
+            ParseError::MissingWhen(Some(ref x)) => write!(
+                f,
+                "Expected a date or time, like \"tomorrow 3pm\", but it starts with \"{}\"",
+                x.text
+            ),
+            ParseError::NoSuchDate(ref x) => write!(f, "There's no such date as \"{}\"", x.text),
+            ParseError::BadTime(ref x) => write!(
+                f,
+                "\"{}\" isn't a time: with am or pm, hours go from 1 to 12",
+                x.text
+            ),
+            ParseError::TimeOutOfRange(ref x) => write!(
+                f,
+                "\"{}\" isn't a time: hours go up to 23, and minutes and seconds up to 59",
+                x.text
+            ),
+            ParseError::MissingTime(ref x) => write!(
+                f,
+                "\"{}\" needs a time, like \"3pm\" or \"14:30\", or \"all day\"",
+                x.text
+            ),
+            ParseError::AmbiguousTime(ref x) => write!(
+                f,
+                "\"{}\" happens twice because the clocks go back, so it's ambiguous",
+                x.text
+            ),
+            ParseError::NonexistentTime(ref x) => write!(
+                f,
+                "\"{}\" doesn't exist because the clocks go forward",
+                x.text
+            ),
+            ParseError::PartialDays(ref x) => write!(
+                f,
+                "All-day events have to last a whole number of days, unlike \"{}\"",
+                x.text
+            ),
+            ParseError::EndsBeforeStart(ref x) => write!(
+                f,
+                "The event has to end after it starts, unlike \"{}\"",
+                x.text
+            ),
+            ParseError::OutOfRange(ref x) => write!(f, "\"{}\" is too far away", x.text),
+        }
+    }
+}
+
+/// The latest year a date can be in. Written dates can't have more
+/// than four digits in their year, and relative ones can't go further
+/// either.
+const MAX_YEAR: i32 = 9999;
+
+/// Parses the date expression at the start of `text`, where `now` is
+/// the current local time. Returns it along with the rest of `text`.
+pub fn parse(text: &str, now: NaiveDateTime) -> Result<(When, &str), ParseError> {
+    let tokens = tokenize(text);
+    let parser = Parser {
+        text: text,
+        tokens: &tokens,
+        now: now,
+    };
+
+    let mut date = None;
+    let mut time = None;
+    let mut all_day = false;
+    let mut end = None;
+    let mut pos = 0;
+    while let Some((len, part)) = parser.part_at(pos)? {
+        match part {
+            Part::Date(x) if date.is_none() => date = Some(x),
+            Part::DateTime(x) if date.is_none() && time.is_none() => {
+                date = Some(x.date());
+                time = Some(x.time());
+            }
+            Part::Time(x) if time.is_none() && !all_day => time = Some(x),
+            Part::TimeRange(x, y) if time.is_none() && !all_day && end.is_none() => {
+                time = Some(x);
+                end = Some(End::Time(y));
+            }
+            Part::AllDay if time.is_none() && !all_day => all_day = true,
+            Part::End(x) if end.is_none() => end = Some(x),
+            _ => break,
+        }
+        pos += len;
+    }
+    if pos == 0 {
+        return Err(ParseError::MissingWhen(
+            tokens.first().map(|_| parser.span(0, 1)),
+        ));
+    }
+
+    let date = date.unwrap_or_else(|| now.date());
+    let start = date.and_time(time.unwrap_or_else(|| NaiveTime::from_hms(0, 0, 0)));
+    let length = end.map(|x| match x {
+        End::Duration(duration) => Length::For(duration),
+        // An end time before the start is on the next day, as in
+        // "11pm-1am".
+        End::Time(end) if date.and_time(end) <= start => Length::Until(date.succ().and_time(end)),
+        End::Time(end) => Length::Until(date.and_time(end)),
+    });
+
+    let rest = tokens.get(pos).map_or("", |x| &text[x.start..]);
+    Ok((
+        When {
+            date: date,
+            time: time,
+            all_day: all_day,
+            length: length,
+        },
+        rest,
+    ))
+}
+
+/// Parses a duration like "30m", "1h30m" or "90 minutes" that makes
+/// up all of `text`.
+pub fn parse_duration(text: &str) -> Option<Duration> {
+    let tokens = tokenize(text);
+    match timeless_parser(text, &tokens).duration_at(0) {
+        Ok(Some((len, duration))) if len == tokens.len() => Some(duration),
+        _ => None,
+    }
+}
+
+/// Parses a time of day like "8:00", "8am" or "noon" that makes up
+/// all of `text`.
+pub fn parse_time(text: &str) -> Option<NaiveTime> {
+    let tokens = tokenize(text);
+    match timeless_parser(text, &tokens).time_at(0, false) {
+        Ok(Some(time)) if time.len == tokens.len() => Some(time.time),
+        _ => None,
+    }
+}
+
+/// Parses a range of times of day like "9-17", "9:00-17:30" or
+/// "9am-5pm" that makes up all of `text`. Bare hours like "9-17" are
+/// on the 24-hour clock, and "24" is midnight.
+pub fn parse_time_range(text: &str) -> Option<(NaiveTime, NaiveTime)> {
+    let tokens = tokenize(text);
+    let parser = timeless_parser(text, &tokens);
+    match parser.time_range_at(0) {
+        Ok(Some((len, Part::TimeRange(start, end)))) if len == tokens.len() => {
+            return Some((start, end))
+        }
+        _ => (),
+    }
+
+    let hour = |ndx: usize| {
+        parser
+            .word(ndx)?
+            .parse::<u32>()
+            .ok()
+            .filter(|x| *x <= 24)
+            .map(|x| NaiveTime::from_hms(x % 24, 0, 0))
+    };
+    match (hour(0), parser.word(1), hour(2)) {
+        (Some(start), Some("-"), Some(end)) if tokens.len() == 3 => Some((start, end)),
+        _ => None,
+    }
+}
+
+/// Makes a parser for things that don't depend on the current time.
+fn timeless_parser<'a>(text: &'a str, tokens: &'a [Token]) -> Parser<'a> {
+    Parser {
+        text: text,
+        tokens: tokens,
+        now: NaiveDate::from_ymd(1970, 1, 1).and_hms(0, 0, 0),
+    }
+}
+
+/// A word of the input, or a piece of one.
+struct Token {
+    /// Where it starts in the input.
+    start: usize,
+    /// Its text in lower case, without trailing commas.
+    text: String,
+}
+
+fn tokenize(text: &str) -> Vec<Token> {
+    let mut tokens = Vec::new();
+    let mut word_start = None;
+    for (ndx, c) in text.char_indices().chain(Some((text.len(), ' '))) {
+        if !c.is_whitespace() {
+            word_start = word_start.or(Some(ndx));
+        } else if let Some(start) = word_start.take() {
+            split_word(&text[start..ndx], start, &mut tokens);
+        }
+    }
+    tokens
+}
+
+/// Splits ranges like "14:30-15:15" or "3-4pm" around the dash. ISO
+/// dates like "2019-10-20" are left whole.
+fn split_word(word: &str, start: usize, tokens: &mut Vec<Token>) {
+    let mut push = |text: &str, start| {
+        tokens.push(Token {
+            start: start,
+            text: text.trim_end_matches(',').to_ascii_lowercase(),
+        })
+    };
+
+    match word.find('-') {
+        Some(dash) if !looks_like_iso_date(word) && word.len() > 1 => {
+            if dash > 0 {
+                push(&word[..dash], start);
+            }
+            push("-", start + dash);
+            if dash + 1 < word.len() {
+                split_word(&word[dash + 1..], start + dash + 1, tokens);
+            }
+        }
+        _ => push(word, start),
+    }
+}
+
+fn looks_like_iso_date(word: &str) -> bool {
+    let bytes = word.as_bytes();
+    bytes.len() == 10
+        && bytes[4] == b'-'
+        && bytes[7] == b'-'
+        && bytes
+            .iter()
+            .enumerate()
+            .all(|(ndx, b)| ndx == 4 || ndx == 7 || b.is_ascii_digit())
+}
+
+/// One part of an expression.
+enum Part {
+    Date(NaiveDate),
+    DateTime(NaiveDateTime),
+    Time(NaiveTime),
+    TimeRange(NaiveTime, NaiveTime),
+    AllDay,
+    End(End),
+}
+
+enum End {
+    Time(NaiveTime),
+    Duration(Duration),
+}
+
+/// A time as it was written.
+#[derive(Clone, Copy, Eq, PartialEq)]
+enum TimeKind {
+    /// Just an hour, like "3". This is only a time in ranges like
+    /// "3-4pm".
+    Bare,
+    /// A 24-hour time like "15:30".
+    Clock,
+    /// A 12-hour time like "3:30pm", or "noon" or "midnight".
+    Meridiem,
+}
+
+struct TimeMatch {
+    len: usize,
+    time: NaiveTime,
+    kind: TimeKind,
+}
+
+struct Parser<'a> {
+    text: &'a str,
+    tokens: &'a [Token],
+    now: NaiveDateTime,
+}
+
+impl<'a> Parser<'a> {
+    fn word(&self, ndx: usize) -> Option<&str> {
+        self.tokens.get(ndx).map(|x| x.text.as_str())
+    }
+
+    /// Gets the text of the `len` tokens starting at token `ndx`.
+    fn span(&self, ndx: usize, len: usize) -> Span {
+        let last = &self.tokens[ndx + len - 1];
+        // Lower casing ASCII doesn't change lengths.
+        Span::new(
+            self.text,
+            self.tokens[ndx].start..last.start + last.text.len(),
+        )
+    }
+
+    /// Matches the part of an expression starting at token `ndx`,
+    /// returning how many tokens it spans.
+    fn part_at(&self, ndx: usize) -> Result<Option<(usize, Part)>, ParseError> {
+        let word = match self.word(ndx) {
+            Some(word) => word,
+            None => return Ok(None),
+        };
+
+        match word {
+            // Filler words only count if what follows them does.
+            "at" | "on" | "from" => {
+                return Ok(self.part_at(ndx + 1)?.map(|(len, part)| (len + 1, part)));
+            }
+            "for" => {
+                return Ok(self
+                    .duration_at(ndx + 1)?
+                    .map(|(len, x)| (len + 1, Part::End(End::Duration(x)))));
+            }
+            "allday" => return Ok(Some((1, Part::AllDay))),
+            // "all-day" is split into three tokens.
+            "all" => {
+                let len = match (self.word(ndx + 1), self.word(ndx + 2)) {
+                    (Some("day"), _) => 2,
+                    (Some("-"), Some("day")) => 3,
+                    _ => return Ok(None),
+                };
+                return Ok(Some((len, Part::AllDay)));
+            }
+            "until" | "till" | "to" | "-" => {
+                return Ok(self
+                    .time_at(ndx + 1, false)?
+                    .map(|x| (x.len + 1, Part::End(End::Time(x.time)))));
+            }
+            _ => (),
+        }
+
+        if let Some(x) = self.date_at(ndx)? {
+            return Ok(Some(x));
+        }
+        if let Some(x) = self.time_range_at(ndx)? {
+            return Ok(Some(x));
+        }
+        // Durations don't need a "for" if they're written like "2h".
+        if compact_duration(word).is_some() {
+            return Ok(self
+                .duration_at(ndx)?
+                .map(|(len, x)| (len, Part::End(End::Duration(x)))));
+        }
+        Ok(None)
+    }
+
+    fn date_at(&self, ndx: usize) -> Result<Option<(usize, Part)>, ParseError> {
+        let today = self.now.date();
+        let word = match self.word(ndx) {
+            Some(word) => word,
+            None => return Ok(None),
+        };
+
+        let date = match word {
+            "today" => Some((1, today)),
+            "tomorrow" | "tmrw" => Some((1, today.succ())),
+            "yesterday" => Some((1, today.pred())),
+            "this" => self
+                .word(ndx + 1)
+                .and_then(parse_weekday)
+                .map(|x| (2, next_weekday(today, x, false))),
+            "next" => self
+                .word(ndx + 1)
+                .and_then(parse_weekday)
+                .map(|x| (2, next_weekday(today, x, true))),
+            "in" => {
+                let (len, duration) = match self.duration_at(ndx + 1)? {
+                    Some(x) => x,
+                    None => return Ok(None),
+                };
+                let then = self
+                    .now
+                    .checked_add_signed(duration)
+                    .filter(|x| x.year() <= MAX_YEAR)
+                    .ok_or_else(|| ParseError::OutOfRange(self.span(ndx, len + 1)))?;
+                let part = if duration.num_seconds() % (24 * 3600) == 0 {
+                    Part::Date(then.date())
+                } else {
+                    Part::DateTime(then)
+                };
+                return Ok(Some((len + 1, part)));
+            }
+            _ => match parse_weekday(word) {
+                Some(weekday) => Some((1, next_weekday(today, weekday, false))),
+                None => self.written_date_at(ndx)?,
+            },
+        };
+
+        Ok(date.map(|(len, x)| (len, Part::Date(x))))
+    }
+
+    /// Matches dates like "oct 20", "20th october 2019", "10/20" or
+    /// "2019-10-20".
+    fn written_date_at(&self, ndx: usize) -> Result<Option<(usize, NaiveDate)>, ParseError> {
+        let word = self.word(ndx).unwrap_or("");
+        let year_at = |ndx| {
+            self.word(ndx)
+                .filter(|x| x.len() == 4 && x.bytes().all(|b| b.is_ascii_digit()))
+                .and_then(|x| x.parse().ok())
+        };
+
+        let month_day = if let Some(month) = parse_month(word) {
+            self.word(ndx + 1)
+                .and_then(parse_day)
+                .map(|day| (month, day))
+        } else if let Some(day) = parse_day(word) {
+            self.word(ndx + 1)
+                .and_then(parse_month)
+                .map(|month| (month, day))
+        } else {
+            None
+        };
+        if let Some((month, day)) = month_day {
+            let (len, date) = match year_at(ndx + 2) {
+                Some(year) => (3, NaiveDate::from_ymd_opt(year, month, day)),
+                None => (2, self.next_month_day(month, day)),
+            };
+            let date = date.ok_or_else(|| ParseError::NoSuchDate(self.span(ndx, len)))?;
+            return Ok(Some((len, date)));
+        }
+
+        let no_such_date = || ParseError::NoSuchDate(self.span(ndx, 1));
+        if looks_like_iso_date(word) {
+            let date = NaiveDate::parse_from_str(word, "%Y-%m-%d").map_err(|_| no_such_date())?;
+            return Ok(Some((1, date)));
+        }
+
+        let parts: Vec<&str> = word.split('/').collect();
+        if parts.len() < 2
+            || parts.len() > 3
+            || parts
+                .iter()
+                .any(|x| x.is_empty() || x.len() > 4 || !x.bytes().all(|b| b.is_ascii_digit()))
+        {
+            return Ok(None);
+        }
+        let month = parts[0].parse().unwrap();
+        let day = parts[1].parse().unwrap();
+        let date = match parts.get(2) {
+            Some(year) => {
+                let year: i32 = year.parse().unwrap();
+                // Two digit years are in this century.
+                let year = if year < 100 { year + 2000 } else { year };
+                NaiveDate::from_ymd_opt(year, month, day)
+            }
+            None => self.next_month_day(month, day),
+        };
+        Ok(Some((1, date.ok_or_else(no_such_date)?)))
+    }
+
+    /// Gets the next date with the given month and day on or after
+    /// today, if there is one.
+    fn next_month_day(&self, month: u32, day: u32) -> Option<NaiveDate> {
+        let today = self.now.date();
+        // February 29th can be up to eight years away.
+        (today.year()..today.year() + 9)
+            .filter_map(|year| NaiveDate::from_ymd_opt(year, month, day))
+            .find(|x| *x >= today)
+    }
+
+    /// Matches a time, or a range of times like "3-4pm" or "14:30 to
+    /// 15:15".
+    fn time_range_at(&self, ndx: usize) -> Result<Option<(usize, Part)>, ParseError> {
+        let start = match self.time_at(ndx, true)? {
+            Some(start) => start,
+            None => return Ok(None),
+        };
+
+        let end = match self.word(ndx + start.len) {
+            Some("-") | Some("to") | Some("until") | Some("till") => {
+                self.time_at(ndx + start.len + 1, false)?
+            }
+            _ => None,
+        };
+        match end {
+            Some(ref end) if start.kind != TimeKind::Bare || end.kind == TimeKind::Meridiem => {
+                let part = Part::TimeRange(infer_meridiem(&start, end), end.time);
+                Ok(Some((start.len + 1 + end.len, part)))
+            }
+            _ if start.kind == TimeKind::Bare => Ok(None),
+            _ => Ok(Some((start.len, Part::Time(start.time)))),
+        }
+    }
+
+    /// Matches a time like "3pm", "3:30 pm", "15:30", "7:53:29",
+    /// "noon" or, if `allow_bare`, "3".
+    fn time_at(&self, ndx: usize, allow_bare: bool) -> Result<Option<TimeMatch>, ParseError> {
+        let word = match self.word(ndx) {
+            Some(word) => word,
+            None => return Ok(None),
+        };
+        let named = match word {
+            "noon" => Some(NaiveTime::from_hms(12, 0, 0)),
+            "midnight" => Some(NaiveTime::from_hms(0, 0, 0)),
+            _ => None,
+        };
+        if let Some(time) = named {
+            return Ok(Some(TimeMatch {
+                len: 1,
+                time: time,
+                kind: TimeKind::Meridiem,
+            }));
+        }
+
+        let (clock, pm, len) = match split_meridiem(word) {
+            Some((clock, pm)) => (clock, Some(pm), 1),
+            None => match self.word(ndx + 1).and_then(parse_meridiem) {
+                Some(pm) => (word, Some(pm), 2),
+                None => (word, None, 1),
+            },
+        };
+        let (hour, minute, second) = match parse_clock(clock) {
+            Some(x) => x,
+            None => return Ok(None),
+        };
+
+        let kind = match pm {
+            Some(_) => TimeKind::Meridiem,
+            None if clock.contains(':') => TimeKind::Clock,
+            None if allow_bare => TimeKind::Bare,
+            None => return Ok(None),
+        };
+        let hour = match pm {
+            Some(_) if !(1..=12).contains(&hour) => {
+                return Err(ParseError::BadTime(self.span(ndx, len)))
+            }
+            Some(pm) => hour % 12 + if pm { 12 } else { 0 },
+            None => hour,
+        };
+        let time = NaiveTime::from_hms_opt(hour, minute, second)
+            .ok_or_else(|| ParseError::TimeOutOfRange(self.span(ndx, len)))?;
+
+        Ok(Some(TimeMatch {
+            len: len,
+            time: time,
+            kind: kind,
+        }))
+    }
+
+    /// Matches durations like "2h30m", "90 min", "1 hour 30 minutes"
+    /// or "an hour".
+    fn duration_at(&self, ndx: usize) -> Result<Option<(usize, Duration)>, ParseError> {
+        let mut duration = Duration::zero();
+        let mut len = 0;
+        while let Some(word) = self.word(ndx + len) {
+            let (more, x) = match compact_duration(word) {
+                Some(x) => (1, x),
+                None => {
+                    let count = match word {
+                        "a" | "an" => Some(1),
+                        _ => word.parse::<u16>().ok(),
+                    };
+                    let unit = self.word(ndx + len + 1).and_then(parse_duration_unit);
+                    match (count, unit) {
+                        (Some(count), Some(unit)) => (2, unit * i32::from(count)),
+                        _ => break,
+                    }
+                }
+            };
+            len += more;
+            duration = duration
+                .checked_add(&x)
+                .ok_or_else(|| ParseError::OutOfRange(self.span(ndx, len)))?;
+        }
+
+        if len == 0 {
+            Ok(None)
+        } else {
+            Ok(Some((len, duration)))
+        }
+    }
+}
+
+/// Gives a range's start the end's meridiem if it has none and that
+/// keeps it before the end, so "3-4pm" is 15:00-16:00 but "11-1pm" is
+/// 11:00-13:00.
+fn infer_meridiem(start: &TimeMatch, end: &TimeMatch) -> NaiveTime {
+    if start.kind != TimeKind::Meridiem
+        && end.kind == TimeKind::Meridiem
+        && start.time.hour() < 12
+        && end.time.hour() >= 12
+    {
+        let pm = start.time + Duration::hours(12);
+        if pm <= end.time {
+            return pm;
+        }
+    }
+    start.time
+}
+
+/// Parses "H", "H:MM" or "H:MM:SS" without checking ranges.
+fn parse_clock(text: &str) -> Option<(u32, u32, u32)> {
+    let parts: Vec<&str> = text.split(':').collect();
+    if parts.len() > 3
+        || parts
+            .iter()
+            .any(|x| x.is_empty() || x.len() > 2 || !x.bytes().all(|b| b.is_ascii_digit()))
+    {
+        return None;
+    }
+    let part = |ndx: usize| parts.get(ndx).map_or(0, |x| x.parse().unwrap());
+    Some((part(0), part(1), part(2)))
+}
+
+/// Splits "3pm" into "3" and whether it's PM.
+fn split_meridiem(word: &str) -> Option<(&str, bool)> {
+    for suffix in &["am", "a.m.", "pm", "p.m."] {
+        if word.len() > suffix.len() && word.ends_with(suffix) {
+            return Some((&word[..word.len() - suffix.len()], suffix.starts_with('p')));
+        }
+    }
+    None
+}
+
+fn parse_meridiem(word: &str) -> Option<bool> {
+    match word {
+        "am" | "a.m." => Some(false),
+        "pm" | "p.m." => Some(true),
+        _ => None,
+    }
+}
+
+/// Parses durations written as one word, like "90m" or "2h30m".
+fn compact_duration(word: &str) -> Option<Duration> {
+    let mut duration = Duration::zero();
+    let mut rest = word;
+    while !rest.is_empty() {
+        let digits = rest
+            .find(|c: char| !c.is_ascii_digit())
+            .unwrap_or(rest.len());
+        let letters = rest[digits..]
+            .find(|c: char| !c.is_ascii_alphabetic())
+            .map_or(rest.len(), |x| x + digits);
+        if digits == 0 || digits > 4 || letters == digits {
+            return None;
+        }
+        let count: i32 = rest[..digits].parse().unwrap();
+        duration = duration.checked_add(&(parse_duration_unit(&rest[digits..letters])? * count))?;
+        rest = &rest[letters..];
+    }
+    if word.is_empty() {
+        None
+    } else {
+        Some(duration)
+    }
+}
+
+fn parse_duration_unit(word: &str) -> Option<Duration> {
+    match word {
+        "w" | "wk" | "wks" | "week" | "weeks" => Some(Duration::weeks(1)),
+        "d" | "day" | "days" => Some(Duration::days(1)),
+        "h" | "hr" | "hrs" | "hour" | "hours" => Some(Duration::hours(1)),
+        "m" | "min" | "mins" | "minute" | "minutes" => Some(Duration::minutes(1)),
+        _ => None,
+    }
+}
+
+fn parse_weekday(word: &str) -> Option<Weekday> {
+    match word {
+        "monday" | "mon" => Some(Weekday::Mon),
+        "tuesday" | "tue" | "tues" => Some(Weekday::Tue),
+        "wednesday" | "wed" => Some(Weekday::Wed),
+        "thursday" | "thu" | "thur" | "thurs" => Some(Weekday::Thu),
+        "friday" | "fri" => Some(Weekday::Fri),
+        "saturday" | "sat" => Some(Weekday::Sat),
+        "sunday" | "sun" => Some(Weekday::Sun),
+        _ => None,
+    }
+}
+
+/// Gets the next `weekday` on or after `today`, or strictly after it
+/// if `skip_today`.
+fn next_weekday(today: NaiveDate, weekday: Weekday, skip_today: bool) -> NaiveDate {
+    let mut days =
+        (7 + weekday.num_days_from_monday() - today.weekday().num_days_from_monday()) % 7;
+    if days == 0 && skip_today {
+        days = 7;
+    }
+    today + Duration::days(i64::from(days))
+}
+
+fn parse_month(word: &str) -> Option<u32> {
+    let month = match word {
+        "january" | "jan" => 1,
+        "february" | "feb" => 2,
+        "march" | "mar" => 3,
+        "april" | "apr" => 4,
+        "may" => 5,
+        "june" | "jun" => 6,
+        "july" | "jul" => 7,
+        "august" | "aug" => 8,
+        "september" | "sep" | "sept" => 9,
+        "october" | "oct" => 10,
+        "november" | "nov" => 11,
+        "december" | "dec" => 12,
+        _ => return None,
+    };
+    Some(month)
+}
+
+/// Parses a day of the month like "20" or "20th".
+fn parse_day(word: &str) -> Option<u32> {
+    let digits = ["st", "nd", "rd", "th"]
+        .iter()
+        .find(|x| word.ends_with(*x))
+        .map_or(word, |x| &word[..word.len() - x.len()]);
+    if digits.is_empty() || digits.len() > 2 || !digits.bytes().all(|b| b.is_ascii_digit()) {
+        return None;
+    }
+    match digits.parse() {
+        Ok(day) if (1..=31).contains(&day) => Some(day),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// The time tests are run at: Wednesday, October 16th 2019 at
+    /// 10:00.
+    fn now() -> NaiveDateTime {
+        NaiveDate::from_ymd(2019, 10, 16).and_hms(10, 0, 0)
+    }
+
+    fn date(month: u32, day: u32) -> NaiveDate {
+        NaiveDate::from_ymd(2019, month, day)
+    }
+
+    fn at(date: NaiveDate, hour: u32, minute: u32) -> When {
+        When {
+            date: date,
+            time: Some(NaiveTime::from_hms(hour, minute, 0)),
+            all_day: false,
+            length: None,
+        }
+    }
+
+    fn on(date: NaiveDate) -> When {
+        When {
+            date: date,
+            time: None,
+            all_day: false,
+            length: None,
+        }
+    }
+
+    fn all_day(date: NaiveDate) -> When {
+        When {
+            all_day: true,
+            ..on(date)
+        }
+    }
+
+    fn lasting(when: When, duration: Duration) -> When {
+        When {
+            length: Some(Length::For(duration)),
+            ..when
+        }
+    }
+
+    fn until(when: When, date: NaiveDate, hour: u32, minute: u32) -> When {
+        When {
+            length: Some(Length::Until(date.and_hms(hour, minute, 0))),
+            ..when
+        }
+    }
+
+    #[test]
+    fn parse_expressions() {
+        let cases = vec![
+            // Relative days.
+            ("today 3pm", at(date(10, 16), 15, 0)),
+            ("tomorrow 3pm", at(date(10, 17), 15, 0)),
+            ("Tomorrow at 3PM", at(date(10, 17), 15, 0)),
+            ("tmrw 9am", at(date(10, 17), 9, 0)),
+            ("yesterday noon", at(date(10, 15), 12, 0)),
+            ("3pm tomorrow", at(date(10, 17), 15, 0)),
+            ("at noon tomorrow", at(date(10, 17), 12, 0)),
+            ("tomorrow", on(date(10, 17))),
+            // Weekdays.
+            ("friday 9am", at(date(10, 18), 9, 0)),
+            ("fri 9am", at(date(10, 18), 9, 0)),
+            ("this friday 9am", at(date(10, 18), 9, 0)),
+            ("next friday at noon", at(date(10, 18), 12, 0)),
+            ("wednesday 11am", at(date(10, 16), 11, 0)),
+            ("this wed 11am", at(date(10, 16), 11, 0)),
+            ("next wednesday 11am", at(date(10, 23), 11, 0)),
+            ("on monday at 8:15", at(date(10, 21), 8, 15)),
+            ("tues 5pm", at(date(10, 22), 17, 0)),
+            ("Thurs 5pm", at(date(10, 17), 17, 0)),
+            ("sunday midnight", at(date(10, 20), 0, 0)),
+            // Written dates.
+            ("oct 20 14:30", at(date(10, 20), 14, 30)),
+            ("October 20th 2pm", at(date(10, 20), 14, 0)),
+            ("20 oct 2pm", at(date(10, 20), 14, 0)),
+            ("oct 1 9am", at(NaiveDate::from_ymd(2020, 10, 1), 9, 0)),
+            ("oct 16 9am", at(date(10, 16), 9, 0)),
+            ("jan 5, 2021 9am", at(NaiveDate::from_ymd(2021, 1, 5), 9, 0)),
+            ("dec 25", on(date(12, 25))),
+            ("sept 3rd 10:00", at(NaiveDate::from_ymd(2020, 9, 3), 10, 0)),
+            ("feb 29 noon", at(NaiveDate::from_ymd(2020, 2, 29), 12, 0)),
+            ("10/20 9am", at(date(10, 20), 9, 0)),
+            ("10/20/2019 9am", at(date(10, 20), 9, 0)),
+            ("10/20/19 9am", at(date(10, 20), 9, 0)),
+            ("1/15/2024 7:53:29", {
+                let mut when = at(NaiveDate::from_ymd(2024, 1, 15), 7, 53);
+                when.time = Some(NaiveTime::from_hms(7, 53, 29));
+                when
+            }),
+            ("2019-10-20 9am", at(date(10, 20), 9, 0)),
+            // Times.
+            ("3:30pm", at(date(10, 16), 15, 30)),
+            ("3:30 pm", at(date(10, 16), 15, 30)),
+            ("3 p.m.", at(date(10, 16), 15, 0)),
+            ("12am tomorrow", at(date(10, 17), 0, 0)),
+            ("12pm tomorrow", at(date(10, 17), 12, 0)),
+            ("9am", at(date(10, 16), 9, 0)),
+            ("23:59", at(date(10, 16), 23, 59)),
+            ("0:00 tomorrow", at(date(10, 17), 0, 0)),
+            // In some amount of time.
+            ("in 30 minutes", at(date(10, 16), 10, 30)),
+            ("in 2h", at(date(10, 16), 12, 0)),
+            ("in 14 hours", at(date(10, 17), 0, 0)),
+            ("in 3 days 9am", at(date(10, 19), 9, 0)),
+            ("in a week at noon", at(date(10, 23), 12, 0)),
+            ("in 1d", on(date(10, 17))),
+            // Durations.
+            (
+                "next friday at noon for 2h",
+                lasting(at(date(10, 18), 12, 0), Duration::hours(2)),
+            ),
+            (
+                "tomorrow 3pm for 90m",
+                lasting(at(date(10, 17), 15, 0), Duration::minutes(90)),
+            ),
+            (
+                "tomorrow 3pm for 2h30m",
+                lasting(at(date(10, 17), 15, 0), Duration::minutes(150)),
+            ),
+            (
+                "tomorrow 3pm for 1 hour 30 minutes",
+                lasting(at(date(10, 17), 15, 0), Duration::minutes(90)),
+            ),
+            (
+                "tomorrow 3pm for an hour",
+                lasting(at(date(10, 17), 15, 0), Duration::hours(1)),
+            ),
+            (
+                "tomorrow 3pm 45min",
+                lasting(at(date(10, 17), 15, 0), Duration::minutes(45)),
+            ),
+            (
+                "for 2 days tomorrow 9am",
+                lasting(at(date(10, 17), 9, 0), Duration::days(2)),
+            ),
+            (
+                "3pm 1h 15m",
+                lasting(at(date(10, 16), 15, 0), Duration::minutes(75)),
+            ),
+            // All day.
+            ("tomorrow all day", all_day(date(10, 17))),
+            ("all-day oct 20", all_day(date(10, 20))),
+            ("friday allday", all_day(date(10, 18))),
+            (
+                "oct 20 all day for 3 days",
+                lasting(all_day(date(10, 20)), Duration::days(3)),
+            ),
+            (
+                "all day friday 2d",
+                lasting(all_day(date(10, 18)), Duration::days(2)),
+            ),
+            // End times.
+            (
+                "oct 20 14:30-15:15",
+                until(at(date(10, 20), 14, 30), date(10, 20), 15, 15),
+            ),
+            (
+                "oct 20 14:30 - 15:15",
+                until(at(date(10, 20), 14, 30), date(10, 20), 15, 15),
+            ),
+            (
+                "tomorrow 3-4pm",
+                until(at(date(10, 17), 15, 0), date(10, 17), 16, 0),
+            ),
+            (
+                "tomorrow 11-1pm",
+                until(at(date(10, 17), 11, 0), date(10, 17), 13, 0),
+            ),
+            (
+                "tomorrow 9:30-10:15pm",
+                until(at(date(10, 17), 21, 30), date(10, 17), 22, 15),
+            ),
+            (
+                "tomorrow 10am to noon",
+                until(at(date(10, 17), 10, 0), date(10, 17), 12, 0),
+            ),
+            (
+                "from 3pm until 5pm friday",
+                until(at(date(10, 18), 15, 0), date(10, 18), 17, 0),
+            ),
+            (
+                "friday 11pm-1am",
+                until(at(date(10, 18), 23, 0), date(10, 19), 1, 0),
+            ),
+        ];
+
+        for (text, expected) in cases {
+            let result = parse(text, now());
+            assert_eq!(result, Ok((expected, "")), "{}", text);
+        }
+    }
+
+    #[test]
+    fn parse_leaves_the_rest() {
+        let cases = vec![
+            (
+                "tomorrow 3pm lunch with bob",
+                at(date(10, 17), 15, 0),
+                "lunch with bob",
+            ),
+            (
+                "1/1/1 1:1:1 god is dead",
+                {
+                    let mut when = at(NaiveDate::from_ymd(2001, 1, 1), 1, 1);
+                    when.time = Some(NaiveTime::from_hms(1, 1, 1));
+                    when
+                },
+                "god is dead",
+            ),
+            (
+                "friday 9am  Standup, room 4",
+                at(date(10, 18), 9, 0),
+                "Standup, room 4",
+            ),
+            // A second date or time is part of the rest.
+            (
+                "tomorrow 3pm friday review",
+                at(date(10, 17), 15, 0),
+                "friday review",
+            ),
+            ("tomorrow 3pm 4pm", at(date(10, 17), 15, 0), "4pm"),
+            // Filler words are only part of an expression when what
+            // follows them is.
+            (
+                "3pm at the office",
+                at(date(10, 16), 15, 0),
+                "at the office",
+            ),
+            ("3pm for lunch", at(date(10, 16), 15, 0), "for lunch"),
+            // Bare numbers are only times in ranges.
+            (
+                "friday 3pm till 4 or so",
+                at(date(10, 18), 15, 0),
+                "till 4 or so",
+            ),
+            ("friday 3 people", on(date(10, 18)), "3 people"),
+            (
+                "tomorrow all day all hands",
+                all_day(date(10, 17)),
+                "all hands",
+            ),
+            ("tomorrow 3pm all day", at(date(10, 17), 15, 0), "all day"),
+            ("noon check-in", at(date(10, 16), 12, 0), "check-in"),
+            (
+                "may 5 9am may the fourth be with you",
+                at(NaiveDate::from_ymd(2020, 5, 5), 9, 0),
+                "may the fourth be with you",
+            ),
+        ];
+
+        for (text, expected, rest) in cases {
+            assert_eq!(parse(text, now()), Ok((expected, rest)), "{}", text);
+        }
+    }
+
+    #[test]
+    fn parse_duration_test() {
+        assert_eq!(parse_duration("30m"), Some(Duration::minutes(30)));
+        assert_eq!(parse_duration("1h30m"), Some(Duration::minutes(90)));
+        assert_eq!(
+            parse_duration("an hour 15 mins"),
+            Some(Duration::minutes(75))
+        );
+        assert_eq!(parse_duration(" 2 Hours "), Some(Duration::hours(2)));
+        assert_eq!(parse_duration("30m lunch"), None);
+        assert_eq!(parse_duration("30"), None);
+        assert_eq!(parse_duration(""), None);
+    }
+
+    #[test]
+    fn parse_time_test() {
+        let time = |hour, minute| NaiveTime::from_hms(hour, minute, 0);
+        assert_eq!(parse_time("8:00"), Some(time(8, 0)));
+        assert_eq!(parse_time(" 7:30pm "), Some(time(19, 30)));
+        assert_eq!(parse_time("noon"), Some(time(12, 0)));
+        assert_eq!(parse_time("8"), None);
+        assert_eq!(parse_time("24:00"), None);
+        assert_eq!(parse_time("8:00 tomorrow"), None);
+        assert_eq!(parse_time(""), None);
+    }
+
+    #[test]
+    fn parse_time_range_test() {
+        let time = |hour, minute| NaiveTime::from_hms(hour, minute, 0);
+        assert_eq!(parse_time_range("9-17"), Some((time(9, 0), time(17, 0))));
+        assert_eq!(parse_time_range("22-24"), Some((time(22, 0), time(0, 0))));
+        assert_eq!(
+            parse_time_range("9:30-17:15"),
+            Some((time(9, 30), time(17, 15)))
+        );
+        assert_eq!(parse_time_range("9am-5pm"), Some((time(9, 0), time(17, 0))));
+        assert_eq!(parse_time_range("9-5pm"), Some((time(9, 0), time(17, 0))));
+        assert_eq!(parse_time_range("9-25"), None);
+        assert_eq!(parse_time_range("9"), None);
+        assert_eq!(parse_time_range("9-17 lunch"), None);
+    }
+
+    #[test]
+    fn parse_errors() {
+        let span = |start, text: &str| Span {
+            start: start,
+            text: String::from(text),
+        };
+        let cases = vec![
+            ("", ParseError::MissingWhen(None)),
+            (
+                "lunch tomorrow",
+                ParseError::MissingWhen(Some(span(0, "lunch"))),
+            ),
+            (
+                "20th of october",
+                ParseError::MissingWhen(Some(span(0, "20th"))),
+            ),
+            ("  3 pizzas", ParseError::MissingWhen(Some(span(2, "3")))),
+            ("feb 30 9am", ParseError::NoSuchDate(span(0, "feb 30"))),
+            (
+                "Feb 29, 2019",
+                ParseError::NoSuchDate(span(0, "Feb 29, 2019")),
+            ),
+            ("13/1 9am", ParseError::NoSuchDate(span(0, "13/1"))),
+            (
+                "on 2019-02-30",
+                ParseError::NoSuchDate(span(3, "2019-02-30")),
+            ),
+            ("1/32/2020", ParseError::NoSuchDate(span(0, "1/32/2020"))),
+            ("tomorrow 13pm", ParseError::BadTime(span(9, "13pm"))),
+            ("tomorrow 0 AM", ParseError::BadTime(span(9, "0 AM"))),
+            (
+                "tomorrow 24:00",
+                ParseError::TimeOutOfRange(span(9, "24:00")),
+            ),
+            (
+                "tomorrow 9:00-9:60",
+                ParseError::TimeOutOfRange(span(14, "9:60")),
+            ),
+        ];
+
+        for (text, expected) in cases {
+            assert_eq!(parse(text, now()), Err(expected), "{}", text);
+        }
+    }
+
+    #[test]
+    fn far_off_dates_are_errors() {
+        let chain = |n| format!("in{}", " 65535 weeks".repeat(n));
+        // Past the last year a date can be written in.
+        let text = chain(7);
+        assert_eq!(
+            parse(&text, now()),
+            Err(ParseError::OutOfRange(Span::new(&text, 0..text.len())))
+        );
+        // Past the last date chrono can represent.
+        let text = chain(220);
+        assert_eq!(
+            parse(&format!("{} 9am lunch", text), now()),
+            Err(ParseError::OutOfRange(Span::new(&text, 0..text.len())))
+        );
+        assert!(parse(&chain(6), now()).is_ok());
+    }
+}